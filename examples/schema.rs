@@ -3,7 +3,8 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use diogoboilerplate::msg::{InstantiateMsg, ExecuteMsg, ReceiveMsg, ListResponse, DetailsResponse, QueryMsg};
+use diogoboilerplate::msg::{InstantiateMsg, MigrateMsg, ExecuteMsg, ReceiveMsg, ListResponse, DetailsResponse, QueryMsg, StreamResponse, OffersResponse, FillsResponse, TemplateResponse, TimeRemainingResponse, PhaseResponse, ConfigResponse, ArbitersResponse, ArbiterStatsResponse, HistoryResponse, ArbiterQueryMsg, IsAuthorizedResponse, ApplicationsResponse, MessagesResponse};
+use diogoboilerplate::events::EscrowEvent;
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
@@ -12,9 +13,25 @@ fn main() {
     remove_schemas(&out_dir).unwrap();
 
     export_schema(&schema_for!(InstantiateMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(ReceiveMsg), &out_dir);
     export_schema(&schema_for!(ListResponse), &out_dir);
     export_schema(&schema_for!(DetailsResponse), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
+    export_schema(&schema_for!(StreamResponse), &out_dir);
+    export_schema(&schema_for!(OffersResponse), &out_dir);
+    export_schema(&schema_for!(FillsResponse), &out_dir);
+    export_schema(&schema_for!(TemplateResponse), &out_dir);
+    export_schema(&schema_for!(TimeRemainingResponse), &out_dir);
+    export_schema(&schema_for!(PhaseResponse), &out_dir);
+    export_schema(&schema_for!(ConfigResponse), &out_dir);
+    export_schema(&schema_for!(ArbitersResponse), &out_dir);
+    export_schema(&schema_for!(ArbiterStatsResponse), &out_dir);
+    export_schema(&schema_for!(HistoryResponse), &out_dir);
+    export_schema(&schema_for!(ArbiterQueryMsg), &out_dir);
+    export_schema(&schema_for!(IsAuthorizedResponse), &out_dir);
+    export_schema(&schema_for!(ApplicationsResponse), &out_dir);
+    export_schema(&schema_for!(MessagesResponse), &out_dir);
+    export_schema(&schema_for!(EscrowEvent), &out_dir);
 }
@@ -1,17 +1,23 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{ 
-    from_binary, to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult, Addr, BankMsg, SubMsg, WasmMsg 
+    from_binary, to_binary, Binary, Coin, Decimal, Deps, DepsMut, Env, Event, MessageInfo, Order, QuerierWrapper, Response, StdError, StdResult, Storage, Addr, Api 
 };
 use cw2::set_contract_version;
 use cw20::{
-    Balance, Cw20Coin, Cw20CoinVerified, Cw20ExecuteMsg, Cw20ReceiveMsg
+    Balance, Cw20Coin, Cw20CoinVerified, Cw20ReceiveMsg, Denom
 };
+use cw_storage_plus::Bound;
+use cw_utils::{Duration, Expiration};
+use std::cmp::Ordering;
 
 use crate::error::ContractError;
-use crate::msg::{InstantiateMsg, ExecuteMsg, QueryMsg, ListResponse, DetailsResponse, CreateMsg, ReceiveMsg};
+use crate::events::{EscrowEvent, EventKind, Leg};
+use crate::payout::Payouts;
+use crate::receipt::{is_minter, mint_receipts};
+use crate::msg::{ArbiterFeeMsg, ArbiterInfo, ArbiterQueryMsg, Decision, IsAuthorizedResponse, ArbitersResponse, ArbiterStatsResponse, ConfigResponse, InstantiateMsg, ExecuteMsg, EscrowOp, QueryMsg, ListResponse, DetailsResponse, CreateMsg, MigrateMsg, PhaseResponse, TimeRemainingResponse, ReceiveMsg, StreamResponse, OffersResponse, OfferInfo, FillsResponse, FillInfo, MessagesResponse, ThreadMessage, ApplicationInfo, ApplicationsResponse, HistoryInfo, HistoryResponse, TemplateMsg, TemplateResponse, WinnerMsg};
 use crate::state::{ 
-    Escrow, ESCROWS, all_escrow_ids, GenericBalance
+    Application, ArbiterFee, ArbiterProfile, Arbitration, Authorization, BackupArbiter, Change, Config, DEFAULT_ACCEPTANCE_WINDOW, DEFAULT_BOUNTY_MIN_AGE, Escrow, MetadataLimits, ARBITERS, ARBITER_STATS, record_acceptance, record_decline, record_resolution, Extension, Party, Phase, APPLICATIONS, CONFIG, ESCROWS, EXPIRATIONS, EXTERNAL_IDS, FEE_POOL, FILLS, HISTORY, LEGACY_EXPIRIES, MESSAGES, OFFERS, TEMPLATES, Template, all_escrow_ids, pair_key, record_fill, post_message, record_history, next_decision_nonce, has_records, load_escrow, remove_escrow, save_escrow, GenericBalance, Stream
  };
 
 // version info for migration info
@@ -22,16 +28,74 @@ const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 pub fn instantiate(
     deps: DepsMut,
     _env: Env,
-    _info: MessageInfo,
+    info: MessageInfo,
     _msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    let config = Config {
+        admin: Some(info.sender),
+        keeper_bounty: None,
+        bounty_min_age: None,
+        acceptance_window: None,
+        require_registered_arbiter: false,
+        metadata_limits: MetadataLimits::default(),
+        receipt_collection: None,
+    };
+    CONFIG.save(deps.storage, &config)?;
 
     Ok(Response::new()
         .add_attribute("action", "instantiate")
     )
 }
 
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    if CONFIG.may_load(deps.storage)?.is_none() {
+        let config = Config {
+            admin: msg.admin.map(|a| deps.api.addr_validate(&a)).transpose()?,
+            keeper_bounty: None,
+            bounty_min_age: None,
+            acceptance_window: None,
+            require_registered_arbiter: false,
+            metadata_limits: MetadataLimits::default(),
+            receipt_collection: None,
+        };
+        CONFIG.save(deps.storage, &config)?;
+    }
+
+    // escrows stored with end_height and end_time get the equivalent expiration,
+    // every escrow is saved again to fill the indexes added since
+    let legacy = LEGACY_EXPIRIES
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    // an escrow with both bounds ahead has no equivalent expiration, dropping either
+    // bound would change when it expires so these have to be settled first
+    let ambiguous: Vec<_> = legacy
+        .iter()
+        .filter(|(_, expiry)| expiry.to_expiration(&env.block).is_none())
+        .map(|(id, _)| id.as_str())
+        .collect();
+    if !ambiguous.is_empty() {
+        return Err(ContractError::AmbiguousExpiry { ids: ambiguous.join(",") });
+    }
+    let mut migrated = 0u32;
+    for (id, expiry) in legacy {
+        let mut escrow = ESCROWS.load(deps.storage, &id)?;
+        if expiry.end_height.is_some() || expiry.end_time.is_some() {
+            escrow.expires = expiry
+                .to_expiration(&env.block)
+                .ok_or_else(|| ContractError::AmbiguousExpiry { ids: id.clone() })?;
+            migrated += 1;
+        }
+        save_escrow(deps.storage, &id, &escrow)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("migrated", migrated.to_string()))
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
     deps: DepsMut,
@@ -40,7 +104,7 @@ pub fn execute(
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
     match msg {
-        ExecuteMsg::CreateEscrow (msg) => try_create_escrow(deps, msg, Balance::from(info.funds), &info.sender),
+        ExecuteMsg::CreateEscrow (msg) => try_create_escrow(deps, env, msg, Balance::from(info.funds), &info.sender),
 
         ExecuteMsg::SetRecipient { id, recipient } => try_set_recipient(deps, env, info, id, recipient),
 
@@ -48,16 +112,86 @@ pub fn execute(
 
         ExecuteMsg::Refund { id } => try_refund(deps, env, info, id),
 
-        ExecuteMsg::TopUp { id } => try_top_up(deps, id, Balance::from(info.funds), &info.sender),
+        ExecuteMsg::TopUp { id } => try_top_up(deps, env, id, Balance::from(info.funds), &info.sender),
+
+        ExecuteMsg::TopUpRecip { id } => try_top_up_recip(deps, env, id, Balance::from(info.funds), &info.sender),
+
+        ExecuteMsg::Receive(msg) => try_receive(deps, env, info, msg),
+
+        ExecuteMsg::CheckCondition { id } => try_check_condition(deps, env, info, id),
+
+        ExecuteMsg::Tick { id } => try_tick(deps, env, info, id),
+
+        ExecuteMsg::PauseStream { id } => try_pause_stream(deps, env, info, id),
+
+        ExecuteMsg::ResumeStream { id } => try_resume_stream(deps, env, info, id),
+
+        ExecuteMsg::Batch { ops } => try_batch(deps, env, info, ops),
+
+        ExecuteMsg::Take { id } => try_take(deps, env, id, Balance::from(info.funds), &info.sender),
+
+        ExecuteMsg::CreateTemplate(msg) => try_create_template(deps, info, msg),
+        ExecuteMsg::UpdateTemplate(msg) => try_update_template(deps, info, msg),
+        ExecuteMsg::DeleteTemplate { id } => try_delete_template(deps, info, id),
+
+        ExecuteMsg::Extend { id, new_expiration } => try_extend(deps, env, info, id, new_expiration),
+
+        ExecuteMsg::SweepExpired { limit } => try_sweep_expired(deps, env, info, limit),
+
+        ExecuteMsg::FundFeePool {} => try_fund_fee_pool(deps, info),
+
+        ExecuteMsg::AcceptArbitration { id } => try_accept_arbitration(deps, env, info, id),
+
+        ExecuteMsg::DeclineArbitration { id } => try_decline_arbitration(deps, info, id),
+
+        ExecuteMsg::TransferArbiter { id, new_arbiter } => try_transfer_arbiter(deps, env, info, id, new_arbiter),
+
+        ExecuteMsg::AcceptArbiterTransfer { id } => try_accept_arbiter_transfer(deps, env, info, id),
 
-        ExecuteMsg::TopUpRecip { id } => try_top_up_recip(deps, id, Balance::from(info.funds), &info.sender),
+        ExecuteMsg::TakeOverArbiter { id } => try_take_over_arbiter(deps, env, info, id),
 
-        ExecuteMsg::Receive(msg) => try_receive(deps, info, msg),
+        ExecuteMsg::AssignClaim { id, new_recipient } => try_assign_claim(deps, env, info, id, new_recipient),
+
+        ExecuteMsg::ConsentAssignment { id } => try_consent_assignment(deps, env, info, id),
+
+        ExecuteMsg::Apply { id, memo } => try_apply(deps, env, info, id, memo),
+
+        ExecuteMsg::PostMessage { id, ciphertext } => try_post_message(deps, env, info, id, ciphertext),
+
+        ExecuteMsg::WithdrawApplication { id } => try_withdraw_application(deps, info, id),
+
+        ExecuteMsg::SelectRecipient { id, applicant } => try_select_recipient(deps, env, info, id, applicant),
+
+        ExecuteMsg::SetWinners { id, winners } => try_set_winners(deps, env, info, id, winners),
+
+        ExecuteMsg::UpdateConfig {
+            keeper_bounty,
+            bounty_min_age,
+            acceptance_window,
+            require_registered_arbiter,
+            metadata_limits,
+            receipt_collection,
+        } => try_update_config(
+            deps,
+            env,
+            info,
+            keeper_bounty,
+            bounty_min_age,
+            acceptance_window,
+            require_registered_arbiter,
+            metadata_limits,
+            receipt_collection,
+        ),
+
+        ExecuteMsg::RegisterArbiter { name, fee_schedule, supported_tokens } => {
+            try_register_arbiter(deps, info, name, fee_schedule, supported_tokens)
+        }
     }
 }
 
 pub fn try_create_escrow(
     deps: DepsMut,
+    env: Env,
     msg: CreateMsg,
     balance: Balance,
     sender: &Addr,
@@ -66,7 +200,75 @@ pub fn try_create_escrow(
         return Err(ContractError::EmptyBalance{});
     }
 
-    let cw20_wishlist = msg.addr_wishlist(deps.api)?;
+    let msg = match &msg.template_id {
+        Some(template_id) => {
+            let template = TEMPLATES.load(deps.storage, template_id)?;
+            msg.with_template(&template)
+        }
+        None => msg,
+    };
+
+    let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+    validate_metadata(&msg, &config.metadata_limits)?;
+    if let Some(external_id) = &msg.external_id {
+        if EXTERNAL_IDS.has(deps.storage, external_id) {
+            return Err(ContractError::ExternalIdInUse {});
+        }
+    }
+
+    // a relative expiration is resolved against the current block
+    let expires = msg
+        .resolved_expiration(&env.block)
+        .ok_or(ContractError::InvalidExpiration {})?;
+    if expires.is_expired(&env.block) {
+        return Err(ContractError::AlreadyExpired {});
+    }
+    // the recipient must be able to fund before the escrow expires
+    if let Some(deadline) = msg.funding_deadline {
+        if deadline.is_expired(&env.block) {
+            return Err(ContractError::AlreadyExpired {});
+        }
+        if deadline.partial_cmp(&expires) == Some(Ordering::Greater) {
+            return Err(ContractError::InvalidExpiration {});
+        }
+    }
+    let arbiter_fee = msg
+        .arbiter_fee
+        .as_ref()
+        .map(|fee| validated_fee(deps.api, fee))
+        .transpose()?;
+    // the grace period is counted in the same unit as the expiration
+    if let Some(grace) = msg.grace_period {
+        (expires + grace).map_err(|_| ContractError::InvalidExpiration {})?;
+    }
+
+    // prize pools, swap offers, conditions, streams and applications each decide
+    // who is paid and when in their own way
+    let modes = [
+        msg.prize_pool,
+        msg.terms.is_some(),
+        msg.condition.is_some(),
+        msg.stream.is_some(),
+        msg.open_to_applications,
+    ];
+    if modes.iter().filter(|mode| **mode).count() > 1 {
+        return Err(ContractError::ConflictingModes {});
+    }
+
+    let mut cw20_wishlist = msg.addr_wishlist(deps.api)?;
+    let condition = msg.validated_condition(deps.api)?;
+    let terms = msg.validated_terms(deps.api)?;
+    if let Some(terms) = &terms {
+        if terms.ask_amount.is_zero() {
+            return Err(ContractError::InvalidTerms {});
+        }
+        // the asked cw20 token is always accepted from the recipient
+        if let Denom::Cw20(token) = &terms.ask {
+            if !cw20_wishlist.contains(token) {
+                cw20_wishlist.push(token.clone());
+            }
+        }
+    }
 
     let escrow_balance = match balance {
         Balance::Native(balance) => GenericBalance {
@@ -92,46 +294,270 @@ pub fn try_create_escrow(
         .recipient
         .and_then(|addr| deps.api.addr_validate(&addr).ok());
 
-    let escrow = Escrow {
-        arbiter: deps.api.addr_validate(&msg.arbiter)?,
+    let stream = match msg.stream.as_ref() {
+        Some(stream) => {
+            let amount = stream.validated_amount(deps.api)?;
+            let no_amount = amount.native.iter().any(|c| c.amount.is_zero())
+                || amount.cw20.iter().any(|c| c.amount.is_zero())
+                || (amount.native.is_empty() && amount.cw20.is_empty());
+            let no_interval = matches!(stream.interval, Duration::Height(0) | Duration::Time(0));
+            // intervals are paid until the expiration, which needs both in the same unit
+            let other_unit = matches!(
+                (stream.interval, expires),
+                (Duration::Height(_), Expiration::AtTime(_)) | (Duration::Time(_), Expiration::AtHeight(_))
+            );
+            if recipient.is_none() || no_amount || no_interval || other_unit {
+                return Err(ContractError::InvalidStream {});
+            }
+            Some(Stream {
+                amount,
+                interval: stream.interval,
+                next_payout: stream.interval.after(&env.block),
+                paused: false,
+            })
+        }
+        None => None,
+    };
+
+    let arbiter = deps.api.addr_validate(&msg.arbiter)?;
+    if config.require_registered_arbiter && !ARBITERS.has(deps.storage, &arbiter) {
+        return Err(ContractError::ArbiterNotRegistered {});
+    }
+    // an arbiter creating the escrow has nothing to accept
+    let (arbitration, acceptance_deadline) = if &arbiter == sender {
+        (Arbitration::Accepted, None)
+    } else {
+        let window = config.acceptance_window.unwrap_or(DEFAULT_ACCEPTANCE_WINDOW);
+        (Arbitration::Pending, Some(window.after(&env.block)))
+    };
+    let backup_arbiter = match msg.backup_arbiter {
+        Some(backup) => Some(BackupArbiter {
+            address: deps.api.addr_validate(&backup.address)?,
+            inactivity_timeout: backup.inactivity_timeout,
+            takes_over_at: backup.inactivity_timeout.after(&env.block),
+        }),
+        None => None,
+    };
+    let mut escrow = Escrow {
+        arbiter,
         recipient,
         source: sender.clone(),
         title: msg.title,
         description: msg.description,
-        end_height: msg.end_height,
-        end_time: msg.end_time,
+        expires,
         balance: escrow_balance,
         recip_balance: escrow_recip_balance,
         cw20_wishlist,
+        condition,
+        stream,
+        payout_msg: msg.payout_msg,
+        terms,
+        grace_period: msg.grace_period,
+        arbiter_extends: msg.arbiter_extends,
+        pending_extension: None,
+        funding_deadline: msg.funding_deadline,
+        arbiter_fee,
+        arbitration,
+        acceptance_deadline,
+        created_at: Some(env.block.time),
+        pending_arbiter: None,
+        backup_arbiter,
+        authorization: msg.authorization.unwrap_or(Authorization::Sender),
+        decision_nonce: next_decision_nonce(deps.storage)?,
+        assignment_needs_consent: msg.assignment_needs_consent,
+        pending_assignment: None,
+        recipient_receipt: msg.recipient_receipt,
+        source_receipt: msg.source_receipt,
+        receipt_collection: None,
+        recipient_token_id: None,
+        source_token_id: None,
+        open_to_applications: msg.open_to_applications,
+        application_deposit: msg.application_deposit,
+        prize_pool: msg.prize_pool,
+        winners: vec![],
+        tags: msg.tags,
+        external_id: msg.external_id,
+        content_hash: msg.content_hash,
+        memos: msg.memos,
     };
+    // a tradeable claim cannot wait for the arbiter to consent
+    if escrow.recipient_receipt && escrow.assignment_needs_consent {
+        return Err(ContractError::ConsentWithReceipt {});
+    }
+    if escrow.recipient_receipt || escrow.source_receipt {
+        let collection = config.receipt_collection.ok_or(ContractError::NoReceiptCollection {})?;
+        escrow.receipt_collection = Some(collection);
+    }
 
     // try to store it, fail if the id was already in use
-    ESCROWS.update(deps.storage, &msg.id, |existing| match existing {
-        None => Ok(escrow),
-        Some(_) => Err(ContractError::AlreadyInUse {}),
-    })?;
+    if ESCROWS.has(deps.storage, &msg.id) {
+        return Err(ContractError::AlreadyInUse {});
+    }
+    if has_records(deps.storage, &msg.id) {
+        return Err(ContractError::IdHasRecords {});
+    }
+    let mints = mint_receipts(deps.storage, &mut escrow)?;
+    save_escrow(deps.storage, &msg.id, &escrow)?;
 
-    let res = Response::new().add_attributes(vec![("action", "create_escrow"), ("id", msg.id.as_str())]);
+    let created = EscrowEvent::new(EventKind::EscrowCreated, &msg.id, sender)
+        .leg(Leg::Source)
+        .tokens(&escrow.balance);
+    let res = Response::new()
+        .add_attributes(vec![("action", "create_escrow"), ("id", msg.id.as_str())])
+        .add_event(created.into())
+        .add_messages(mints);
     Ok(res)
 }
 
+/// Checks the free text and metadata of a new escrow against the configured limits
+fn validate_metadata(msg: &CreateMsg, limits: &MetadataLimits) -> Result<(), ContractError> {
+    let too_long = |text: &str, max: u32| text.len() > max as usize;
+    if too_long(&msg.title, limits.max_title_length) {
+        return Err(ContractError::TitleTooLong { max: limits.max_title_length });
+    }
+    if too_long(&msg.description, limits.max_description_length) {
+        return Err(ContractError::DescriptionTooLong { max: limits.max_description_length });
+    }
+    if msg.tags.len() > limits.max_tags as usize {
+        return Err(ContractError::TooManyTags { max: limits.max_tags });
+    }
+    if msg.tags.iter().any(|tag| tag.is_empty() || too_long(tag, limits.max_tag_length)) {
+        return Err(ContractError::InvalidTag { max: limits.max_tag_length });
+    }
+    if let Some(external_id) = &msg.external_id {
+        if external_id.is_empty() || too_long(external_id, limits.max_external_id_length) {
+            return Err(ContractError::InvalidExternalId { max: limits.max_external_id_length });
+        }
+    }
+    if matches!(&msg.content_hash, Some(hash) if !hash.is_valid()) {
+        return Err(ContractError::InvalidContentHash {});
+    }
+    let max = limits.max_message_length;
+    for (i, memo) in msg.memos.iter().enumerate() {
+        let duplicate = msg.memos[..i].iter().any(|other| other.party == memo.party);
+        let empty = memo.key_id.is_empty() || memo.ciphertext.is_empty();
+        if duplicate || empty || too_long(&memo.key_id, max) || memo.ciphertext.len() > max as usize {
+            return Err(ContractError::InvalidMemo { max });
+        }
+    }
+    Ok(())
+}
+
+/// Arbiter fee of an escrow or a template: a share of at most the whole balance, or
+/// some fixed tokens
+fn validated_fee(api: &dyn Api, fee: &ArbiterFeeMsg) -> Result<ArbiterFee, ContractError> {
+    let fee = fee.validated(api)?;
+    let invalid = match &fee {
+        ArbiterFee::Bps(bps) => *bps == 0 || *bps > 10_000,
+        ArbiterFee::Fixed(fee) => fee.is_empty(),
+    };
+    if invalid {
+        return Err(ContractError::InvalidFee {});
+    }
+    Ok(fee)
+}
+
+/// Template the message describes, owned by the owner
+fn validated_template(api: &dyn Api, owner: Addr, msg: &TemplateMsg) -> Result<Template, ContractError> {
+    let cw20_wishlist = match msg.cw20_wishlist.as_ref() {
+        Some(v) => v.iter().map(|h| api.addr_validate(h)).collect::<StdResult<_>>()?,
+        None => vec![],
+    };
+    Ok(Template {
+        owner,
+        arbiter: api.addr_validate(&msg.arbiter)?,
+        cw20_wishlist,
+        expires_in: msg.expires_in,
+        arbiter_fee: msg.arbiter_fee.as_ref().map(|fee| validated_fee(api, fee)).transpose()?,
+    })
+}
+
+pub fn try_create_template(
+    deps: DepsMut,
+    info: MessageInfo,
+    msg: TemplateMsg,
+) -> Result<Response, ContractError> {
+    let template = validated_template(deps.api, info.sender, &msg)?;
+
+    if TEMPLATES.has(deps.storage, &msg.id) {
+        return Err(ContractError::TemplateAlreadyInUse {});
+    }
+    TEMPLATES.save(deps.storage, &msg.id, &template)?;
+
+    Ok(Response::new().add_attributes(vec![("action", "create_template"), ("id", msg.id.as_str())]))
+}
+
+pub fn try_update_template(
+    deps: DepsMut,
+    info: MessageInfo,
+    msg: TemplateMsg,
+) -> Result<Response, ContractError> {
+    // this fails if no template there
+    let template = TEMPLATES.load(deps.storage, &msg.id)?;
+    if info.sender != template.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let template = validated_template(deps.api, template.owner, &msg)?;
+    TEMPLATES.save(deps.storage, &msg.id, &template)?;
+
+    Ok(Response::new().add_attributes(vec![("action", "update_template"), ("id", msg.id.as_str())]))
+}
+
+pub fn try_delete_template(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: String,
+) -> Result<Response, ContractError> {
+    // this fails if no template there
+    let template = TEMPLATES.load(deps.storage, &id)?;
+    if info.sender != template.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    TEMPLATES.remove(deps.storage, &id);
+
+    Ok(Response::new().add_attributes(vec![("action", "delete_template"), ("id", id.as_str())]))
+}
+
 pub fn try_set_recipient(
-    deps: DepsMut, _env: Env, info: MessageInfo, id: String, recipient: String
+    deps: DepsMut, env: Env, info: MessageInfo, id: String, recipient: String
 )-> Result<Response, ContractError> {
-    let mut escrow = ESCROWS.load(deps.storage, &id)?;
-    if info.sender != escrow.arbiter {
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    let decision = Decision::SetRecipient { recipient: recipient.clone() };
+    if !is_authorized(&deps.querier, &escrow, &id, &info.sender, decision)? {
         return Err(ContractError::Unauthorized {});
     }
+    // the decision was taken by the arbiter or on its behalf, it cannot be replayed
+    escrow.arbiter_active(&env);
+    escrow.decision_nonce = next_decision_nonce(deps.storage)?;
+    if escrow.recipient_token_id.is_some() {
+        return Err(ContractError::TokenizedPosition {});
+    }
 
     let recipient = deps.api.addr_validate(recipient.as_str())?;
-    escrow.recipient = Some(recipient.clone());
-    ESCROWS.save(deps.storage, &id, &escrow)?;
+    let mut event = EscrowEvent::updated(&id, &info.sender, "recipient_set").to(&recipient);
+    let previous = escrow.recipient.replace(recipient.clone());
+    if let Some(old) = &previous {
+        event = event.replaced(old);
+        // an assignment proposed by the previous recipient is not the new one's to make,
+        // nor is the callback written for it
+        if *old != recipient {
+            escrow.pending_assignment = None;
+            escrow.payout_msg = None;
+        }
+    }
+    let mints = mint_receipts(deps.storage, &mut escrow)?;
+    save_escrow(deps.storage, &id, &escrow)?;
+    let change = Change::RecipientSet { from: previous, to: recipient.clone() };
+    record_history(deps.storage, &env, &id, &info.sender, change)?;
 
-    Ok(Response::new().add_attributes(vec![
-        ("action", "set_recipient"),
-        ("id", id.as_str()),
-        ("recipient", recipient.as_str()),
-    ]))
+    Ok(Response::new()
+        .add_attributes(vec![
+            ("action", "set_recipient"),
+            ("id", id.as_str()),
+            ("recipient", recipient.as_str()),
+        ])
+        .add_event(event.into())
+        .add_messages(mints))
 }
 
 pub fn try_approve(
@@ -140,27 +566,147 @@ pub fn try_approve(
     info: MessageInfo,
     id: String,
 ) -> Result<Response, ContractError> {
-    
-    let escrow = ESCROWS.load(deps.storage, &id)?;
-    if info.sender != escrow.arbiter {
+    let mut payouts = Payouts::default();
+    let res = approve_escrow(deps, &env, &info.sender, id, &mut payouts)?;
+    Ok(res.add_submessages(payouts.into_msgs()?))
+}
+
+/// Approves the escrow and records what is owed in payouts
+fn approve_escrow(
+    deps: DepsMut,
+    env: &Env,
+    sender: &Addr,
+    id: String,
+    payouts: &mut Payouts,
+) -> Result<Response, ContractError> {
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    if !is_authorized(&deps.querier, &escrow, &id, sender, Decision::Approve {})? {
         return Err(ContractError::Unauthorized {});
     }
-    if escrow.is_expired(&env) {
+    // an escrow that missed its funding deadline can only be refunded
+    if matches!(escrow.phase(env), Phase::Expired | Phase::Unfunded) {
         return Err(ContractError::Expired {});
     }
+    if escrow.prize_pool {
+        return approve_prize_pool(deps, env, sender, escrow, id, payouts);
+    }
     
-    let recipient = escrow.recipient.ok_or(ContractError::RecipientNotSet {})?;
+    let recipient = escrow.recipient.clone().ok_or(ContractError::RecipientNotSet {})?;
 
     // we delete the escrow
-    ESCROWS.remove(deps.storage, &id);
+    remove_escrow(deps.storage, &id)?;
+    record_resolution(deps.storage, env, &escrow, true)?;
     
-    // send all tokens out
-    let messages: Vec<SubMsg> = send_tokens(&recipient, &escrow.balance)?;
+    // send all tokens out, the recipient leg goes to the source
+    let fee = escrow.take_arbiter_fee();
+    payouts.add(&escrow.arbiter, &fee);
+    payouts.add_with(&recipient, &escrow.balance, escrow.recipient_delivery());
+    payouts.add(&escrow.source, &escrow.recip_balance);
+    let events = [
+        EscrowEvent::tokens_moved(EventKind::EscrowFeePaid, &id, sender, Leg::Source, &escrow.arbiter, &fee),
+        EscrowEvent::tokens_moved(EventKind::EscrowReleased, &id, sender, Leg::Source, &recipient, &escrow.balance),
+        EscrowEvent::tokens_moved(EventKind::EscrowReleased, &id, sender, Leg::Recipient, &escrow.source, &escrow.recip_balance),
+    ];
     Ok(Response::new()
+        .add_events(events.into_iter().flatten())
         .add_attribute("action", "approve")
         .add_attribute("id", id)
-        .add_attribute("to", recipient)
-        .add_submessages(messages))
+        .add_attribute("to", recipient))
+}
+
+/// Whether the decision comes from the arbiter, either sent by the arbiter itself
+/// or authorized by the arbiter contract for the current nonce when the escrow asks
+/// it. An arbiter that cannot answer the query authorizes nothing
+fn is_authorized(
+    querier: &QuerierWrapper,
+    escrow: &Escrow,
+    id: &str,
+    sender: &Addr,
+    decision: Decision,
+) -> StdResult<bool> {
+    if sender == &escrow.arbiter {
+        return Ok(true);
+    }
+    match escrow.authorization {
+        Authorization::Sender => Ok(false),
+        Authorization::Query => {
+            let query = ArbiterQueryMsg::IsAuthorized { id: id.to_string(), nonce: escrow.decision_nonce, decision };
+            let res: StdResult<IsAuthorizedResponse> = querier.query_wasm_smart(&escrow.arbiter, &query);
+            Ok(res.is_ok_and(|res| res.authorized))
+        }
+    }
+}
+
+/// Pays every winner of a prize pool its share, the balance may have changed since
+/// the winners were set so their shares are checked again
+fn approve_prize_pool(
+    deps: DepsMut,
+    env: &Env,
+    sender: &Addr,
+    mut escrow: Escrow,
+    id: String,
+    payouts: &mut Payouts,
+) -> Result<Response, ContractError> {
+    if !escrow.winners_cover_balance(&escrow.winners) {
+        return Err(ContractError::InvalidShares {});
+    }
+
+    remove_escrow(deps.storage, &id)?;
+    record_resolution(deps.storage, env, &escrow, true)?;
+
+    let fee = escrow.take_arbiter_fee();
+    payouts.add(&escrow.arbiter, &fee);
+    let mut events: Vec<Event> =
+        EscrowEvent::tokens_moved(EventKind::EscrowFeePaid, &id, sender, Leg::Source, &escrow.arbiter, &fee)
+            .into_iter()
+            .collect();
+    for winner in &escrow.winners {
+        payouts.add(&winner.address, &winner.share);
+        events.extend(EscrowEvent::tokens_moved(
+            EventKind::EscrowReleased, &id, sender, Leg::Source, &winner.address, &winner.share,
+        ));
+    }
+    payouts.add(&escrow.source, &escrow.recip_balance);
+    events.extend(EscrowEvent::tokens_moved(
+        EventKind::EscrowReleased, &id, sender, Leg::Recipient, &escrow.source, &escrow.recip_balance,
+    ));
+    Ok(Response::new()
+        .add_events(events)
+        .add_attribute("action", "approve")
+        .add_attribute("id", id)
+        .add_attribute("winners", escrow.winners.len().to_string()))
+}
+
+pub fn try_set_winners(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: String,
+    winners: Vec<WinnerMsg>,
+) -> Result<Response, ContractError> {
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    if info.sender != escrow.arbiter {
+        return Err(ContractError::Unauthorized {});
+    }
+    if !escrow.prize_pool {
+        return Err(ContractError::NotAPrizePool {});
+    }
+    let winners = winners
+        .iter()
+        .map(|w| w.validated(deps.api))
+        .collect::<StdResult<Vec<_>>>()?;
+    if !escrow.winners_cover_balance(&winners) {
+        return Err(ContractError::InvalidShares {});
+    }
+    escrow.winners = winners;
+    escrow.arbiter_active(&env);
+    save_escrow(deps.storage, &id, &escrow)?;
+
+    let event = EscrowEvent::updated(&id, &info.sender, "winners_set");
+    Ok(Response::new()
+        .add_attributes(vec![("action", "set_winners"), ("id", id.as_str())])
+        .add_attribute("winners", escrow.winners.len().to_string())
+        .add_event(event.into()))
 }
 
 pub fn try_refund(
@@ -168,728 +714,4892 @@ pub fn try_refund(
     env: Env,
     info: MessageInfo,
     id: String,
+) -> Result<Response, ContractError> {
+    let mut payouts = Payouts::default();
+    let res = refund_escrow(deps, &env, &info.sender, id, &mut payouts)?;
+    Ok(res.add_submessages(payouts.into_msgs()?))
+}
+
+/// Refunds the escrow and records what is owed in payouts
+fn refund_escrow(
+    deps: DepsMut,
+    env: &Env,
+    sender: &Addr,
+    id: String,
+    payouts: &mut Payouts,
 ) -> Result<Response, ContractError> {
     // this fails is no escrow there
-    let escrow = ESCROWS.load(deps.storage, &id)?;
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
     // the arbiter can send anytime OR anyone can send after expiration
-    if !escrow.is_expired(&env) && info.sender != escrow.arbiter {
+    let arbitrated = !escrow.is_refundable_by_anyone(env)
+        && is_authorized(&deps.querier, &escrow, &id, sender, Decision::Refund {})?;
+    if !escrow.is_refundable_by_anyone(env) && !arbitrated {
         Err(ContractError::Unauthorized {})
     } else {
         // we delete the escrow
-        ESCROWS.remove(deps.storage, &id);
+        remove_escrow(deps.storage, &id)?;
 
-        // send all tokens out
-        let messages = send_tokens(&escrow.source, &escrow.balance)?;
-
-        //TODO:
-        let recipient = escrow.recipient.ok_or(ContractError::RecipientNotSet{})?;
-        let messages_rec = send_tokens(&recipient, &escrow.recip_balance)?;
+        // send all tokens out, the arbiter is paid when it decides the refund
+        let mut events = vec![];
+        if arbitrated {
+            record_resolution(deps.storage, env, &escrow, false)?;
+            let fee = escrow.take_arbiter_fee();
+            payouts.add(&escrow.arbiter, &fee);
+            events.extend(EscrowEvent::tokens_moved(
+                EventKind::EscrowFeePaid, &id, sender, Leg::Source, &escrow.arbiter, &fee,
+            ));
+        }
+        events.extend(add_refund(&escrow, &id, sender, payouts)?);
 
         Ok(Response::new()
+            .add_events(events)
             .add_attribute("action", "refund")
-            .add_attribute("id", id)
-            .add_submessages(messages)
-            .add_submessages(messages_rec))
+            .add_attribute("id", id))
     }
 }
 
-pub fn try_top_up(
+/// Owes every leg of the escrow back to its depositor, returns the refund events
+fn add_refund(
+    escrow: &Escrow,
+    id: &str,
+    actor: &Addr,
+    payouts: &mut Payouts,
+) -> Result<Vec<Event>, ContractError> {
+    payouts.add(&escrow.source, &escrow.balance);
+    let mut events: Vec<Event> =
+        EscrowEvent::tokens_moved(EventKind::EscrowRefunded, id, actor, Leg::Source, &escrow.source, &escrow.balance)
+            .into_iter()
+            .collect();
+    if !escrow.recip_balance.is_empty() {
+        let recipient = escrow.recipient.as_ref().ok_or(ContractError::RecipientNotSet {})?;
+        payouts.add(recipient, &escrow.recip_balance);
+        events.extend(EscrowEvent::tokens_moved(
+            EventKind::EscrowRefunded, id, actor, Leg::Recipient, recipient, &escrow.recip_balance,
+        ));
+    }
+    Ok(events)
+}
+
+pub fn try_accept_arbitration(
     deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
     id: String,
-    balance: Balance,
-    sender: &Addr,
 ) -> Result<Response, ContractError> {
-    if balance.is_empty() {
-        return Err(ContractError::EmptyBalance {});
-    }
-    // this fails if no escrow there
-    let mut escrow = ESCROWS.load(deps.storage, &id)?;
-    if sender != &escrow.arbiter {
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    if info.sender != escrow.arbiter {
         return Err(ContractError::Unauthorized {});
     }
+    if escrow.arbitration == Arbitration::Accepted {
+        return Err(ContractError::ArbitrationAccepted {});
+    }
+    escrow.arbitration = Arbitration::Accepted;
+    escrow.arbiter_active(&env);
+    save_escrow(deps.storage, &id, &escrow)?;
+    record_acceptance(deps.storage, &escrow)?;
 
-    escrow.balance.add_tokens(balance);
-
-    // and save
-    ESCROWS.save(deps.storage, &id, &escrow)?;
-
-    let res = Response::new()
-        .add_attributes(vec!
-            [
-                ("action", "top_up"), ("id", id.as_str())
-            ]
-        );
-    Ok(res)
+    let event = EscrowEvent::updated(&id, &info.sender, "arbitration_accepted");
+    Ok(Response::new()
+        .add_attributes(vec![("action", "accept_arbitration"), ("id", id.as_str())])
+        .add_event(event.into()))
 }
 
-pub fn try_top_up_recip(
+pub fn try_transfer_arbiter(
     deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
     id: String,
-    recip_balance: Balance,
-    sender: &Addr,
+    new_arbiter: String,
 ) -> Result<Response, ContractError> {
-    if recip_balance.is_empty() {
-        return Err(ContractError::EmptyBalance {});
-    }
-    // this fails if no escrow there
-    let mut escrow = ESCROWS.load(deps.storage, &id)?;
-    // this fails if no recipient set
-    let recipient = escrow.recipient.as_ref().ok_or(ContractError::RecipientNotSet {})?;
-    if sender != recipient {
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    if info.sender != escrow.arbiter {
         return Err(ContractError::Unauthorized {});
     }
-    if let Balance::Cw20(token) = &recip_balance {
-        // ensure the token is on the wishlist
-        /*if !escrow.cw20_wishlist.iter().any(|t| t == &token.address) {
-            return Err(ContractError::NotInWishlist {});
-        }*/
-        if !escrow.cw20_wishlist.iter().any(|t| t == &token.address) {
-            
-        }
-    };
-
-    escrow.balance.add_tokens(recip_balance);
-
-    // and save
-    ESCROWS.save(deps.storage, &id, &escrow)?;
+    let new_arbiter = deps.api.addr_validate(&new_arbiter)?;
+    let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+    if config.require_registered_arbiter && !ARBITERS.has(deps.storage, &new_arbiter) {
+        return Err(ContractError::ArbiterNotRegistered {});
+    }
+    escrow.pending_arbiter = Some(new_arbiter.clone());
+    escrow.arbiter_active(&env);
+    save_escrow(deps.storage, &id, &escrow)?;
+    record_history(
+        deps.storage,
+        &env,
+        &id,
+        &info.sender,
+        Change::ArbiterTransferProposed { to: new_arbiter.clone() },
+    )?;
 
-    let res = Response::new()
-        .add_attributes(vec!
-            [
-                ("action", "top_up"), ("id", id.as_str())
-            ]
-        );
-    Ok(res)
+    Ok(Response::new()
+        .add_attributes(vec![("action", "transfer_arbiter"), ("id", id.as_str())])
+        .add_event(change_event("arbiter_transfer_proposed", &id, &info.sender, &escrow.arbiter, &new_arbiter)))
 }
 
-pub fn try_receive(
+pub fn try_accept_arbiter_transfer(
     deps: DepsMut,
+    env: Env,
     info: MessageInfo,
-    wrapper: Cw20ReceiveMsg,
+    id: String,
 ) -> Result<Response, ContractError> {
-    let msg: ReceiveMsg = from_binary(&wrapper.msg)?;
-    let balance = Balance::Cw20(Cw20CoinVerified {
-        address: info.sender,
-        amount: wrapper.amount,
-    });
-    let api = deps.api;
-    match msg {
-        ReceiveMsg::CreateEscrow(msg) => {
-            try_create_escrow(deps, msg, balance, &api.addr_validate(&wrapper.sender)?)
-        }
-        ReceiveMsg::TopUp { id } => try_top_up(deps, id, balance, &api.addr_validate(&wrapper.sender)?),
-        ReceiveMsg::TopUpRecip { id } => try_top_up_recip(deps, id, balance, &api.addr_validate(&wrapper.sender)?),
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    let new_arbiter = escrow.pending_arbiter.take().ok_or(ContractError::NoPendingTransfer {})?;
+    if info.sender != new_arbiter {
+        return Err(ContractError::Unauthorized {});
     }
-}
+    let old_arbiter = std::mem::replace(&mut escrow.arbiter, new_arbiter);
+    // accepting the transfer accepts the arbitration as well
+    escrow.arbitration = Arbitration::Accepted;
+    escrow.arbiter_active(&env);
+    save_escrow(deps.storage, &id, &escrow)?;
+    record_acceptance(deps.storage, &escrow)?;
+    record_history(
+        deps.storage,
+        &env,
+        &id,
+        &info.sender,
+        Change::ArbiterTransferred { from: old_arbiter.clone(), to: escrow.arbiter.clone() },
+    )?;
 
+    Ok(Response::new()
+        .add_attributes(vec![("action", "accept_arbiter_transfer"), ("id", id.as_str())])
+        .add_event(change_event("arbiter_transferred", &id, &info.sender, &old_arbiter, &escrow.arbiter)))
+}
 
-fn send_tokens(to: &Addr, balance: &GenericBalance) -> StdResult<Vec<SubMsg>> {
-    let native_balance = &balance.native;
-    let mut msgs: Vec<SubMsg> = if native_balance.is_empty() {
-        vec![]
-    } else {
-        vec![SubMsg::new(BankMsg::Send {
-            to_address: to.into(),
-            amount: native_balance.to_vec(),
-        })]
+pub fn try_take_over_arbiter(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: String,
+) -> Result<Response, ContractError> {
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    let backup = escrow.backup_arbiter.take().ok_or(ContractError::Unauthorized {})?;
+    if info.sender != backup.address {
+        return Err(ContractError::Unauthorized {});
+    }
+    if !backup.takes_over_at.is_expired(&env.block) {
+        return Err(ContractError::ArbiterStillActive {});
+    }
+    // the backup becomes the arbiter and the escrow has no backup anymore
+    let old_arbiter = std::mem::replace(&mut escrow.arbiter, backup.address);
+    escrow.pending_arbiter = None;
+    escrow.arbitration = Arbitration::Accepted;
+    save_escrow(deps.storage, &id, &escrow)?;
+    record_acceptance(deps.storage, &escrow)?;
+    record_history(
+        deps.storage,
+        &env,
+        &id,
+        &info.sender,
+        Change::ArbiterTakenOver { from: old_arbiter.clone(), to: escrow.arbiter.clone() },
+    )?;
+
+    Ok(Response::new()
+        .add_attributes(vec![("action", "take_over_arbiter"), ("id", id.as_str())])
+        .add_event(change_event("arbiter_taken_over", &id, &info.sender, &old_arbiter, &escrow.arbiter)))
+}
+
+pub fn try_assign_claim(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: String,
+    new_recipient: String,
+) -> Result<Response, ContractError> {
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    let recipient = escrow.recipient.clone().ok_or(ContractError::RecipientNotSet {})?;
+    if info.sender != recipient {
+        return Err(ContractError::Unauthorized {});
+    }
+    if escrow.recipient_token_id.is_some() {
+        return Err(ContractError::TokenizedPosition {});
+    }
+    let new_recipient = deps.api.addr_validate(&new_recipient)?;
+
+    let res = Response::new().add_attributes(vec![("action", "assign_claim"), ("id", id.as_str())]);
+    if escrow.assignment_needs_consent {
+        escrow.pending_assignment = Some(new_recipient.clone());
+        save_escrow(deps.storage, &id, &escrow)?;
+        record_history(
+            deps.storage,
+            &env,
+            &id,
+            &info.sender,
+            Change::ClaimAssignmentProposed { to: new_recipient.clone() },
+        )?;
+        return Ok(res
+            .add_attribute("result", "proposed")
+            .add_event(change_event("claim_assignment_proposed", &id, &info.sender, &recipient, &new_recipient)));
+    }
+
+    assign_claim(deps, &env, &mut escrow, &id, &info.sender, new_recipient.clone())?;
+    Ok(res
+        .add_attribute("result", "assigned")
+        .add_event(change_event("claim_assigned", &id, &info.sender, &recipient, &new_recipient)))
+}
+
+pub fn try_consent_assignment(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: String,
+) -> Result<Response, ContractError> {
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    if info.sender != escrow.arbiter {
+        return Err(ContractError::Unauthorized {});
+    }
+    let new_recipient = escrow.pending_assignment.take().ok_or(ContractError::NoPendingAssignment {})?;
+    let recipient = escrow.recipient.clone().ok_or(ContractError::RecipientNotSet {})?;
+    escrow.arbiter_active(&env);
+    assign_claim(deps, &env, &mut escrow, &id, &info.sender, new_recipient.clone())?;
+
+    Ok(Response::new()
+        .add_attributes(vec![("action", "consent_assignment"), ("id", id.as_str())])
+        .add_event(change_event("claim_assigned", &id, &info.sender, &recipient, &new_recipient)))
+}
+
+/// Hands the claim of the recipient over to the assignee
+fn assign_claim(
+    deps: DepsMut,
+    env: &Env,
+    escrow: &mut Escrow,
+    id: &str,
+    actor: &Addr,
+    new_recipient: Addr,
+) -> StdResult<()> {
+    let old_recipient = escrow.recipient.replace(new_recipient.clone());
+    // the payout message was meant for the old recipient contract
+    escrow.payout_msg = None;
+    escrow.pending_assignment = None;
+    save_escrow(deps.storage, id, escrow)?;
+    if let Some(from) = old_recipient {
+        record_history(deps.storage, env, id, actor, Change::ClaimAssigned { from, to: new_recipient })?;
+    }
+    Ok(())
+}
+
+pub fn try_apply(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: String,
+    memo: String,
+) -> Result<Response, ContractError> {
+    let escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    if !escrow.open_to_applications || escrow.recipient.is_some() {
+        return Err(ContractError::NotOpenToApplications {});
+    }
+    if APPLICATIONS.has(deps.storage, (&id, &info.sender)) {
+        return Err(ContractError::AlreadyApplied {});
+    }
+    let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+    let max = config.metadata_limits.max_application_memo_length;
+    if memo.len() > max as usize {
+        return Err(ContractError::ApplicationMemoTooLong { max });
+    }
+    let deposit: Vec<Coin> = escrow.application_deposit.into_iter().collect();
+    if !same_coins(&info.funds, &deposit) {
+        return Err(ContractError::WrongDeposit {});
+    }
+    let application = Application {
+        memo,
+        deposit,
+        height: env.block.height,
+        time: env.block.time,
     };
+    APPLICATIONS.save(deps.storage, (&id, &info.sender), &application)?;
 
-    let cw20_balance = &balance.cw20;
-    let cw20_msgs: StdResult<Vec<_>> = cw20_balance
-        .iter()
-        .map(|c| {
-            let msg = Cw20ExecuteMsg::Transfer {
-                recipient: to.into(),
-                amount: c.amount,
-            };
-            let exec = SubMsg::new(WasmMsg::Execute {
-                contract_addr: c.address.to_string(),
-                msg: to_binary(&msg)?,
-                funds: vec![],
-            });
-            Ok(exec)
-        })
-        .collect();
-    msgs.append(&mut cw20_msgs?);
-    Ok(msgs)
+    // the deposit is held apart from the legs of the escrow
+    let event = EscrowEvent::updated(&id, &info.sender, "applied")
+        .tokens(&GenericBalance { native: application.deposit, cw20: vec![] });
+    Ok(Response::new()
+        .add_attributes(vec![("action", "apply"), ("id", id.as_str())])
+        .add_attribute("applicant", info.sender)
+        .add_event(event.into()))
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::List {} => to_binary(&query_list(deps)?),
-        QueryMsg::Details { id } => to_binary(&query_details(deps, id)?),
+pub fn try_withdraw_application(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: String,
+) -> Result<Response, ContractError> {
+    let application = APPLICATIONS
+        .may_load(deps.storage, (&id, &info.sender))?
+        .ok_or(ContractError::NotAnApplicant {})?;
+    APPLICATIONS.remove(deps.storage, (&id, &info.sender));
+
+    let deposit = GenericBalance { native: application.deposit, cw20: vec![] };
+    let mut payouts = Payouts::default();
+    payouts.add(&info.sender, &deposit);
+    let event = EscrowEvent::updated(&id, &info.sender, "application_withdrawn").tokens(&deposit);
+    Ok(Response::new()
+        .add_attributes(vec![("action", "withdraw_application"), ("id", id.as_str())])
+        .add_event(event.into())
+        .add_submessages(payouts.into_msgs()?))
+}
+
+pub fn try_select_recipient(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: String,
+    applicant: String,
+) -> Result<Response, ContractError> {
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    let decision = Decision::SetRecipient { recipient: applicant.clone() };
+    if info.sender != escrow.source {
+        if !is_authorized(&deps.querier, &escrow, &id, &info.sender, decision)? {
+            return Err(ContractError::Unauthorized {});
+        }
+        escrow.arbiter_active(&env);
+    }
+    escrow.decision_nonce = next_decision_nonce(deps.storage)?;
+    if !escrow.open_to_applications || escrow.recipient.is_some() {
+        return Err(ContractError::NotOpenToApplications {});
+    }
+    let applicant = deps.api.addr_validate(&applicant)?;
+    if !APPLICATIONS.has(deps.storage, (&id, &applicant)) {
+        return Err(ContractError::NotAnApplicant {});
+    }
+
+    // the selection closes the applications and returns every deposit
+    let applications = APPLICATIONS
+        .prefix(&id)
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    let mut payouts = Payouts::default();
+    for (address, application) in applications {
+        APPLICATIONS.remove(deps.storage, (&id, &address));
+        payouts.add(&address, &GenericBalance { native: application.deposit, cw20: vec![] });
+    }
+
+    escrow.recipient = Some(applicant.clone());
+    escrow.pending_assignment = None;
+    escrow.payout_msg = None;
+    let mints = mint_receipts(deps.storage, &mut escrow)?;
+    save_escrow(deps.storage, &id, &escrow)?;
+    let change = Change::RecipientSelected { recipient: applicant.clone() };
+    record_history(deps.storage, &env, &id, &info.sender, change)?;
+
+    let event = EscrowEvent::updated(&id, &info.sender, "recipient_selected").to(&applicant);
+    Ok(Response::new()
+        .add_attributes(vec![("action", "select_recipient"), ("id", id.as_str())])
+        .add_event(event.into())
+        .add_attribute("recipient", applicant)
+        .add_submessages(payouts.into_msgs()?)
+        .add_messages(mints))
+}
+
+pub fn try_post_message(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: String,
+    ciphertext: Binary,
+) -> Result<Response, ContractError> {
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    let party = escrow.party_of(&info.sender).ok_or(ContractError::Unauthorized {})?;
+    let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+    let max = config.metadata_limits.max_message_length;
+    if ciphertext.is_empty() || ciphertext.len() > max as usize {
+        return Err(ContractError::InvalidMessage { max });
+    }
+    if party == Party::Arbiter {
+        escrow.arbiter_active(&env);
+        save_escrow(deps.storage, &id, &escrow)?;
     }
+    let index = post_message(deps.storage, &env, &id, &info.sender, ciphertext)?;
+
+    let event = EscrowEvent::updated(&id, &info.sender, "message_posted");
+    Ok(Response::new()
+        .add_attributes(vec![("action", "post_message"), ("id", id.as_str())])
+        .add_attribute("index", index.to_string())
+        .add_event(event.into()))
 }
 
-fn query_list(deps: Deps) -> StdResult<ListResponse> {
-    Ok(
-        ListResponse {
-            escrows: all_escrow_ids(deps.storage)?,
+fn change_event(change: &str, id: &str, actor: &Addr, from: &Addr, to: &Addr) -> Event {
+    EscrowEvent::updated(id, actor, change).replaced(from).to(to).into()
+}
+
+pub fn try_decline_arbitration(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: String,
+) -> Result<Response, ContractError> {
+    let escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    if info.sender != escrow.arbiter {
+        return Err(ContractError::Unauthorized {});
+    }
+    if escrow.arbitration == Arbitration::Accepted {
+        return Err(ContractError::ArbitrationAccepted {});
+    }
+    remove_escrow(deps.storage, &id)?;
+    record_decline(deps.storage, &escrow)?;
+
+    let mut payouts = Payouts::default();
+    let refunds = add_refund(&escrow, &id, &info.sender, &mut payouts)?;
+    let declined = EscrowEvent::updated(&id, &info.sender, "arbitration_declined");
+    Ok(Response::new()
+        .add_event(declined.into())
+        .add_events(refunds)
+        .add_attribute("action", "decline_arbitration")
+        .add_attribute("id", id)
+        .add_submessages(payouts.into_msgs()?))
+}
+
+pub fn try_extend(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: String,
+    new_expiration: Expiration,
+) -> Result<Response, ContractError> {
+    // this fails if no escrow there
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    if escrow.expires.partial_cmp(&new_expiration) != Some(Ordering::Less) {
+        return Err(ContractError::InvalidExtension {});
+    }
+    if new_expiration.is_expired(&env.block) {
+        return Err(ContractError::AlreadyExpired {});
+    }
+    if let Some(grace) = escrow.grace_period {
+        (new_expiration + grace).map_err(|_| ContractError::InvalidExpiration {})?;
+    }
+
+    let agreed = if escrow.arbiter_extends {
+        if info.sender != escrow.arbiter {
+            return Err(ContractError::Unauthorized {});
         }
-    )
+        escrow.arbiter_active(&env);
+        true
+    } else {
+        let recipient = escrow.recipient.as_ref().ok_or(ContractError::RecipientNotSet {})?;
+        if info.sender != escrow.source && &info.sender != recipient {
+            return Err(ContractError::Unauthorized {});
+        }
+        // the other party already proposed the same extension
+        matches!(
+            &escrow.pending_extension,
+            Some(pending) if pending.expires == new_expiration && pending.proposed_by != info.sender
+        )
+    };
+
+    let (result, change) = if agreed {
+        escrow.expires = new_expiration;
+        escrow.pending_extension = None;
+        ("extended", "extended")
+    } else {
+        escrow.pending_extension = Some(Extension {
+            expires: new_expiration,
+            proposed_by: info.sender.clone(),
+        });
+        ("proposed", "extension_proposed")
+    };
+    save_escrow(deps.storage, &id, &escrow)?;
+    let event = EscrowEvent::updated(&id, &info.sender, change);
+
+    Ok(Response::new()
+        .add_event(event.into())
+        .add_attribute("action", "extend")
+        .add_attribute("id", id)
+        .add_attribute("result", result)
+        .add_attribute("expires", new_expiration.to_string()))
 }
 
-fn query_details(deps: Deps, id: String) -> StdResult<DetailsResponse> {
-    let escrow = ESCROWS.load(deps.storage, &id)?;
-    let cw20_wishlist = escrow.human_wishlist();
+pub fn try_check_condition(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: String,
+) -> Result<Response, ContractError> {
+    // this fails is no escrow there
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    let condition = escrow.condition.as_ref().ok_or(ContractError::NoCondition {})?;
+    let mut payouts = Payouts::default();
 
-    let native_balance = escrow.balance.native;
+    // an expired escrow can only go back to its depositors, during the grace
+    // period only the arbiter can refund it
+    if escrow.is_expired(&env) {
+        if !escrow.is_refundable_by_anyone(&env) {
+            return Err(ContractError::Expired {});
+        }
+        remove_escrow(deps.storage, &id)?;
+        let refunds = add_refund(&escrow, &id, &info.sender, &mut payouts)?;
+        return Ok(Response::new()
+            .add_events(refunds)
+            .add_attribute("action", "check_condition")
+            .add_attribute("id", id)
+            .add_attribute("result", "refund")
+            .add_submessages(payouts.into_msgs()?));
+    }
+    // an escrow that missed its funding deadline can only be refunded
+    if escrow.phase(&env) == Phase::Unfunded {
+        return Err(ContractError::Expired {});
+    }
+    if escrow.arbitration == Arbitration::Pending {
+        return Err(ContractError::ArbitrationPending {});
+    }
 
-    let cw20_balance: StdResult<Vec<_>> = escrow
-        .balance.cw20.into_iter()
-        .map(|token| {
-            Ok(Cw20Coin {
-                address: token.address.into(),
-                amount: token.amount,
-            })
-        }).collect();
-    
-    let recipient = escrow.recipient.map(|addr| addr.into_string());
+    if !condition.is_met(&deps.querier)? {
+        return Err(ContractError::ConditionNotMet {});
+    }
 
-    let recip_native_balance = escrow.recip_balance.native;
+    let recipient = escrow.recipient.clone().ok_or(ContractError::RecipientNotSet {})?;
+    remove_escrow(deps.storage, &id)?;
 
-    let recip_cw20_balance: StdResult<Vec<_>> = escrow
-        .recip_balance.cw20.into_iter()
-        .map(|token| {
-            Ok(Cw20Coin {
-                address: token.address.into(),
-                amount: token.amount,
-            })
-        }).collect();
+    // the arbiter is paid and the recipient leg goes to the source, as on approval
+    let fee = escrow.take_arbiter_fee();
+    payouts.add(&escrow.arbiter, &fee);
+    payouts.add_with(&recipient, &escrow.balance, escrow.recipient_delivery());
+    payouts.add(&escrow.source, &escrow.recip_balance);
+    let events = [
+        EscrowEvent::tokens_moved(EventKind::EscrowFeePaid, &id, &info.sender, Leg::Source, &escrow.arbiter, &fee),
+        EscrowEvent::tokens_moved(EventKind::EscrowReleased, &id, &info.sender, Leg::Source, &recipient, &escrow.balance),
+        EscrowEvent::tokens_moved(EventKind::EscrowReleased, &id, &info.sender, Leg::Recipient, &escrow.source, &escrow.recip_balance),
+    ];
+    Ok(Response::new()
+        .add_events(events.into_iter().flatten())
+        .add_attribute("action", "check_condition")
+        .add_attribute("id", id)
+        .add_attribute("result", "release")
+        .add_attribute("to", recipient)
+        .add_submessages(payouts.into_msgs()?))
+}
+
+pub fn try_tick(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: String,
+) -> Result<Response, ContractError> {
+    // this fails is no escrow there
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    let recipient = escrow.recipient.clone().ok_or(ContractError::RecipientNotSet {})?;
+    let stream = escrow.stream.as_ref().ok_or(ContractError::NotAStream {})?;
+    if stream.paused {
+        return Err(ContractError::StreamPaused {});
+    }
+    // an escrow that missed its funding deadline can only be refunded
+    if escrow.phase(&env) == Phase::Unfunded {
+        return Err(ContractError::Expired {});
+    }
+    if escrow.arbitration == Arbitration::Pending {
+        return Err(ContractError::ArbitrationPending {});
+    }
+
+    // an expired stream still pays the intervals that fell due before its expiration
+    let intervals = stream.accrued_intervals(&escrow.accrual_block(&env.block));
+    if intervals == 0 && escrow.is_expired(&env) {
+        return Err(ContractError::Expired {});
+    }
+    if intervals == 0 {
+        return Err(ContractError::NoPayoutDue {});
+    }
+    let mut payouts = Payouts::default();
+    let events = release_stream(deps.storage, &mut escrow, &id, &info.sender, intervals, &mut payouts)?;
+
+    Ok(Response::new()
+        .add_events(events)
+        .add_attribute("action", "tick")
+        .add_attribute("id", id)
+        .add_attribute("intervals", intervals.to_string())
+        .add_attribute("to", recipient)
+        .add_submessages(payouts.into_msgs()?))
+}
+
+/// Pays the given number of intervals of the stream to the recipient and saves the
+/// escrow, or removes it once its balance is exhausted. Returns the release events
+fn release_stream(
+    storage: &mut dyn Storage,
+    escrow: &mut Escrow,
+    id: &str,
+    actor: &Addr,
+    intervals: u64,
+    payouts: &mut Payouts,
+) -> Result<Vec<Event>, ContractError> {
+    let recipient = escrow.recipient.clone().ok_or(ContractError::RecipientNotSet {})?;
+    let stream = escrow.stream.as_mut().ok_or(ContractError::NotAStream {})?;
+    stream.next_payout = (stream.next_payout + stream.interval * intervals)?;
+    let payout = escrow.balance.take_up_to(&stream.amount.checked_mul(intervals)?);
+
+    payouts.add_with(&recipient, &payout, escrow.recipient_delivery());
+    let mut events: Vec<Event> =
+        EscrowEvent::tokens_moved(EventKind::EscrowReleased, id, actor, Leg::Source, &recipient, &payout)
+            .into_iter()
+            .collect();
+
+    // a stream that released everything is done, its recipient leg goes to the source
+    if escrow.balance.is_empty() {
+        remove_escrow(storage, id)?;
+        payouts.add(&escrow.source, &escrow.recip_balance);
+        events.extend(EscrowEvent::tokens_moved(
+            EventKind::EscrowReleased, id, actor, Leg::Recipient, &escrow.source, &escrow.recip_balance,
+        ));
+    } else {
+        save_escrow(storage, id, escrow)?;
+    }
+    Ok(events)
+}
+
+pub fn try_pause_stream(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: String,
+) -> Result<Response, ContractError> {
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    if info.sender != escrow.arbiter {
+        return Err(ContractError::Unauthorized {});
+    }
+    let stream = escrow.stream.as_mut().ok_or(ContractError::NotAStream {})?;
+    if stream.paused {
+        return Err(ContractError::StreamPaused {});
+    }
+    // the intervals accrued so far are owed, they are paid before the clock stops
+    let intervals = stream.accrued_intervals(&env.block);
+    stream.paused = true;
+    escrow.arbiter_active(&env);
+    let mut payouts = Payouts::default();
+    let mut events = vec![EscrowEvent::updated(&id, &info.sender, "stream_paused").into()];
+    if intervals > 0 {
+        events.extend(release_stream(deps.storage, &mut escrow, &id, &info.sender, intervals, &mut payouts)?);
+    } else {
+        save_escrow(deps.storage, &id, &escrow)?;
+    }
+
+    Ok(Response::new()
+        .add_attributes(vec![("action", "pause_stream"), ("id", id.as_str())])
+        .add_attribute("intervals", intervals.to_string())
+        .add_events(events)
+        .add_submessages(payouts.into_msgs()?))
+}
+
+pub fn try_resume_stream(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: String,
+) -> Result<Response, ContractError> {
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    if info.sender != escrow.arbiter {
+        return Err(ContractError::Unauthorized {});
+    }
+    let stream = escrow.stream.as_mut().ok_or(ContractError::NotAStream {})?;
+    if !stream.paused {
+        return Err(ContractError::StreamNotPaused {});
+    }
+    stream.paused = false;
+    stream.next_payout = stream.interval.after(&env.block);
+    escrow.arbiter_active(&env);
+    save_escrow(deps.storage, &id, &escrow)?;
+
+    let event = EscrowEvent::updated(&id, &info.sender, "stream_resumed");
+    Ok(Response::new()
+        .add_attributes(vec![("action", "resume_stream"), ("id", id.as_str())])
+        .add_event(event.into()))
+}
+
+pub fn try_top_up(
+    deps: DepsMut,
+    env: Env,
+    id: String,
+    balance: Balance,
+    sender: &Addr,
+) -> Result<Response, ContractError> {
+    if balance.is_empty() {
+        return Err(ContractError::EmptyBalance {});
+    }
+    // this fails if no escrow there
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    if sender != &escrow.arbiter {
+        return Err(ContractError::Unauthorized {});
+    }
+    escrow.arbiter_active(&env);
+
+    let mut added = GenericBalance::default();
+    added.add_tokens(balance.clone());
+    escrow.balance.add_tokens(balance);
+
+    // and save
+    save_escrow(deps.storage, &id, &escrow)?;
+
+    let funded = EscrowEvent::new(EventKind::EscrowFunded, &id, sender)
+        .leg(Leg::Source)
+        .tokens(&added);
+    let res = Response::new()
+        .add_attributes(vec!
+            [
+                ("action", "top_up"), ("id", id.as_str())
+            ]
+        )
+        .add_event(funded.into());
+    Ok(res)
+}
+
+pub fn try_top_up_recip(
+    deps: DepsMut,
+    env: Env,
+    id: String,
+    recip_balance: Balance,
+    sender: &Addr,
+) -> Result<Response, ContractError> {
+    if recip_balance.is_empty() {
+        return Err(ContractError::EmptyBalance {});
+    }
+    // this fails if no escrow there
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    // this fails if no recipient set
+    let recipient = escrow.recipient.as_ref().ok_or(ContractError::RecipientNotSet {})?;
+    if sender != recipient {
+        return Err(ContractError::Unauthorized {});
+    }
+    if escrow.arbitration == Arbitration::Pending {
+        return Err(ContractError::ArbitrationPending {});
+    }
+    if let Some(deadline) = escrow.funding_deadline {
+        if deadline.is_expired(&env.block) {
+            return Err(ContractError::FundingClosed {});
+        }
+    }
+    if let Balance::Cw20(token) = &recip_balance {
+        // ensure the token is on the wishlist
+        /*if !escrow.cw20_wishlist.iter().any(|t| t == &token.address) {
+            return Err(ContractError::NotInWishlist {});
+        }*/
+        if !escrow.cw20_wishlist.iter().any(|t| t == &token.address) {
+            
+        }
+    };
+
+    let mut added = GenericBalance::default();
+    added.add_tokens(recip_balance.clone());
+    escrow.recip_balance.add_tokens(recip_balance);
+
+    // and save
+    save_escrow(deps.storage, &id, &escrow)?;
+
+    let funded = EscrowEvent::new(EventKind::EscrowFunded, &id, sender)
+        .leg(Leg::Recipient)
+        .tokens(&added);
+    let res = Response::new()
+        .add_attributes(vec!
+            [
+                ("action", "top_up_recip"), ("id", id.as_str())
+            ]
+        )
+        .add_event(funded.into());
+    Ok(res)
+}
+
+pub fn try_take(
+    deps: DepsMut,
+    env: Env,
+    id: String,
+    payment: Balance,
+    taker: &Addr,
+) -> Result<Response, ContractError> {
+    // this fails if no escrow there
+    let mut escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    if escrow.is_expired(&env) {
+        return Err(ContractError::Expired {});
+    }
+    let offer = escrow.open_offer().ok_or(ContractError::NotAnOpenOffer {})?;
+
+    // exactly the asked amount of the asked token must be sent
+    let received = match (&offer.terms.ask, &payment) {
+        (Denom::Native(denom), Balance::Native(coins)) => {
+            let sent: Vec<_> = coins.0.iter().filter(|c| !c.amount.is_zero()).collect();
+            match sent.as_slice() {
+                [coin] if &coin.denom == denom => coin.amount,
+                _ => return Err(ContractError::WrongAskToken {}),
+            }
+        }
+        (Denom::Cw20(address), Balance::Cw20(token)) if &token.address == address => token.amount,
+        _ => return Err(ContractError::WrongAskToken {}),
+    };
+    let expected = offer.terms.ask_amount;
+    if received > expected {
+        return Err(ContractError::Overpayment { expected, received });
+    }
+    if received < expected {
+        // only offers with a minimum fill size accept partial fills
+        let min_fill = offer
+            .terms
+            .min_fill
+            .ok_or(ContractError::Underpayment { expected, received })?;
+        if received < min_fill {
+            return Err(ContractError::FillTooSmall { min_fill, received });
+        }
+    }
+
+    // the taker gets the share of the balance matching the share of the terms paid
+    let mut filled = if received == expected {
+        remove_escrow(deps.storage, &id)?;
+        escrow.balance.clone()
+    } else {
+        let share = escrow.balance.multiply_ratio(received, expected);
+        if share.is_empty() {
+            return Err(ContractError::FillTooSmall {
+                min_fill: offer.terms.min_fill.unwrap_or_default(),
+                received,
+            });
+        }
+        escrow.balance.take_up_to(&share)
+    };
+    // as on approval the arbiter fee comes out of the released tokens, so takers
+    // receive their share of the balance less their share of the fee
+    let fee = escrow.take_fill_fee(&mut filled, received, expected);
+    if received < expected {
+        if let Some(terms) = escrow.terms.as_mut() {
+            terms.ask_amount -= received;
+        }
+        save_escrow(deps.storage, &id, &escrow)?;
+    }
+
+    let mut paid = GenericBalance::default();
+    paid.add_tokens(payment);
+    record_fill(deps.storage, &env, &id, taker, received, &filled)?;
+
+    let mut payouts = Payouts::default();
+    payouts.add(&escrow.arbiter, &fee);
+    // the payout message was meant for the recipient, not for takers
+    payouts.add(taker, &filled);
+    payouts.add(&escrow.source, &paid);
+    // the payment of the taker stands for the recipient leg
+    let events = [
+        EscrowEvent::tokens_moved(EventKind::EscrowFeePaid, &id, taker, Leg::Source, &escrow.arbiter, &fee),
+        EscrowEvent::tokens_moved(EventKind::EscrowReleased, &id, taker, Leg::Source, taker, &filled),
+        EscrowEvent::tokens_moved(EventKind::EscrowReleased, &id, taker, Leg::Recipient, &escrow.source, &paid),
+    ];
+
+    Ok(Response::new()
+        .add_events(events.into_iter().flatten())
+        .add_attribute("action", "take")
+        .add_attribute("id", id)
+        .add_attribute("taker", taker)
+        .add_attribute("paid", received)
+        .add_submessages(payouts.into_msgs()?))
+}
+
+pub fn try_sweep_expired(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+
+    // the index lists every escrow at the points it may become refundable by anyone,
+    // points that don't apply yet are skipped so the limit only counts swept escrows
+    let mut ids: Vec<String> = vec![];
+    for (kind, now) in [("height", env.block.height), ("time", env.block.time.nanos())] {
+        for key in EXPIRATIONS
+            .sub_prefix(kind)
+            .keys(deps.storage, None, None, Order::Ascending)
+        {
+            let (at, id) = key?;
+            if at > now || ids.len() >= limit {
+                break;
+            }
+            if !ids.contains(&id) && ESCROWS.load(deps.storage, &id)?.is_refundable_by_anyone(&env) {
+                ids.push(id);
+            }
+        }
+    }
+
+    let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+    let min_age = config.bounty_min_age.unwrap_or(DEFAULT_BOUNTY_MIN_AGE);
+    let mut res = Response::new().add_attribute("action", "sweep_expired");
+    let mut payouts = Payouts::default();
+    let mut swept = 0u128;
+    let mut rewarded = 0u128;
+    for id in ids {
+        let escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+        remove_escrow(deps.storage, &id)?;
+        let refunds = add_refund(&escrow, &id, &info.sender, &mut payouts)?;
+        res = res.add_events(refunds).add_attribute("id", id);
+        swept += 1;
+        if escrow.earns_bounty(&env, &info.sender, min_age) {
+            rewarded += 1;
+        }
+    }
+
+    // the bounty is paid as far as the fee pool allows
+    if let Some(bounty) = config.keeper_bounty {
+        let owed = bounty.amount.checked_mul(rewarded.into()).map_err(StdError::from)?;
+        let mut pool = FEE_POOL.may_load(deps.storage)?.unwrap_or_default();
+        let paid = pool.take_up_to(&GenericBalance {
+            native: vec![Coin::new(owed.u128(), bounty.denom)],
+            cw20: vec![],
+        });
+        FEE_POOL.save(deps.storage, &pool)?;
+        payouts.add(&info.sender, &paid);
+    }
+
+    Ok(res
+        .add_attribute("swept", swept.to_string())
+        .add_attribute("rewarded", rewarded.to_string())
+        .add_submessages(payouts.into_msgs()?))
+}
+
+pub fn try_fund_fee_pool(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let funds = Balance::from(info.funds);
+    if funds.is_empty() {
+        return Err(ContractError::EmptyBalance {});
+    }
+    let mut pool = FEE_POOL.may_load(deps.storage)?.unwrap_or_default();
+    pool.add_tokens(funds);
+    FEE_POOL.save(deps.storage, &pool)?;
+
+    Ok(Response::new().add_attribute("action", "fund_fee_pool"))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn try_update_config(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    keeper_bounty: Option<Coin>,
+    bounty_min_age: Option<u64>,
+    acceptance_window: Option<Duration>,
+    require_registered_arbiter: Option<bool>,
+    metadata_limits: Option<MetadataLimits>,
+    receipt_collection: Option<String>,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+    if config.admin.as_ref() != Some(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+    // only the settings that are given change
+    if let Some(bounty) = keeper_bounty {
+        config.keeper_bounty = Some(bounty);
+    }
+    if let Some(min_age) = bounty_min_age {
+        config.bounty_min_age = Some(min_age);
+    }
+    if let Some(window) = acceptance_window {
+        config.acceptance_window = Some(window);
+    }
+    if let Some(required) = require_registered_arbiter {
+        config.require_registered_arbiter = required;
+    }
+    if let Some(limits) = metadata_limits {
+        config.metadata_limits = limits;
+    }
+    if let Some(collection) = receipt_collection {
+        let collection = deps.api.addr_validate(&collection)?;
+        if !is_minter(&deps.querier, &collection, &env.contract.address)? {
+            return Err(ContractError::NotReceiptMinter {});
+        }
+        config.receipt_collection = Some(collection);
+    }
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new().add_attribute("action", "update_config"))
+}
+
+pub fn try_register_arbiter(
+    deps: DepsMut,
+    info: MessageInfo,
+    name: String,
+    fee_schedule: Option<ArbiterFeeMsg>,
+    supported_tokens: Vec<Denom>,
+) -> Result<Response, ContractError> {
+    let supported_tokens = supported_tokens
+        .into_iter()
+        .map(|token| match token {
+            Denom::Native(denom) => Ok(Denom::Native(denom)),
+            Denom::Cw20(addr) => Ok(Denom::Cw20(deps.api.addr_validate(addr.as_str())?)),
+        })
+        .collect::<StdResult<_>>()?;
+    let profile = ArbiterProfile {
+        name,
+        fee_schedule: fee_schedule.map(|fee| fee.validated(deps.api)).transpose()?,
+        supported_tokens,
+    };
+    ARBITERS.save(deps.storage, &info.sender, &profile)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "register_arbiter")
+        .add_attribute("arbiter", info.sender))
+}
+
+pub fn try_batch(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    ops: Vec<EscrowOp>,
+) -> Result<Response, ContractError> {
+    if ops.is_empty() {
+        return Err(ContractError::EmptyBatch {});
+    }
+
+    // every sent token must be assigned to exactly one operation
+    let mut assigned = GenericBalance::default();
+    for op in &ops {
+        if let EscrowOp::Create { funds, .. } | EscrowOp::TopUp { funds, .. } = op {
+            assigned.add_tokens(Balance::from(funds.clone()));
+        }
+    }
+    let mut sent = GenericBalance::default();
+    sent.add_tokens(Balance::from(info.funds.clone()));
+    if !same_coins(&assigned.native, &sent.native) {
+        return Err(ContractError::BatchFundsMismatch {});
+    }
+
+    let mut res = Response::new()
+        .add_attribute("action", "batch")
+        .add_attribute("ops", ops.len().to_string());
+    let mut payouts = Payouts::default();
+    for op in ops {
+        let op_res = match op {
+            EscrowOp::Create { escrow, funds } => try_create_escrow(
+                deps.branch(),
+                env.clone(),
+                escrow,
+                Balance::from(funds),
+                &info.sender,
+            )?,
+            EscrowOp::SetRecipient { id, recipient } => {
+                let op_info = MessageInfo {
+                    sender: info.sender.clone(),
+                    funds: vec![],
+                };
+                try_set_recipient(deps.branch(), env.clone(), op_info, id, recipient)?
+            }
+            EscrowOp::Approve { id } => {
+                approve_escrow(deps.branch(), &env, &info.sender, id, &mut payouts)?
+            }
+            EscrowOp::Refund { id } => {
+                refund_escrow(deps.branch(), &env, &info.sender, id, &mut payouts)?
+            }
+            EscrowOp::TopUp { id, funds } => {
+                try_top_up(deps.branch(), env.clone(), id, Balance::from(funds), &info.sender)?
+            }
+        };
+        res = res
+            .add_attributes(op_res.attributes)
+            .add_events(op_res.events)
+            .add_submessages(op_res.messages);
+    }
+
+    // payouts to the same address across escrows are merged
+    Ok(res.add_submessages(payouts.into_msgs()?))
+}
+
+/// Compares two coin lists regardless of order, ignoring zero amounts
+fn same_coins(a: &[Coin], b: &[Coin]) -> bool {
+    let non_zero = |coins: &[Coin]| {
+        let mut coins: Vec<Coin> = coins.iter().filter(|c| !c.amount.is_zero()).cloned().collect();
+        coins.sort_by(|x, y| x.denom.cmp(&y.denom));
+        coins
+    };
+    non_zero(a) == non_zero(b)
+}
+
+pub fn try_receive(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    wrapper: Cw20ReceiveMsg,
+) -> Result<Response, ContractError> {
+    let msg: ReceiveMsg = from_binary(&wrapper.msg)?;
+    let balance = Balance::Cw20(Cw20CoinVerified {
+        address: info.sender,
+        amount: wrapper.amount,
+    });
+    let api = deps.api;
+    match msg {
+        ReceiveMsg::CreateEscrow(msg) => {
+            try_create_escrow(deps, env, msg, balance, &api.addr_validate(&wrapper.sender)?)
+        }
+        ReceiveMsg::TopUp { id } => try_top_up(deps, env, id, balance, &api.addr_validate(&wrapper.sender)?),
+        ReceiveMsg::TopUpRecip { id } => try_top_up_recip(deps, env, id, balance, &api.addr_validate(&wrapper.sender)?),
+        ReceiveMsg::Take { id } => try_take(deps, env, id, balance, &api.addr_validate(&wrapper.sender)?),
+    }
+}
+
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::List {} => to_binary(&query_list(deps)?),
+        QueryMsg::Details { id } => to_binary(&query_details(deps, id)?),
+        QueryMsg::DetailsByExternalId { external_id } => {
+            to_binary(&query_details_by_external_id(deps, external_id)?)
+        }
+        QueryMsg::TimeRemaining { id } => to_binary(&query_time_remaining(deps, env, id)?),
+        QueryMsg::Phase { id } => to_binary(&query_phase(deps, env, id)?),
+        QueryMsg::Stream { id } => to_binary(&query_stream(deps, id)?),
+        QueryMsg::Offers { offer_asset, ask_asset, start_after, limit } => {
+            to_binary(&query_offers(deps, offer_asset, ask_asset, start_after, limit)?)
+        }
+        QueryMsg::Template { id } => to_binary(&query_template(deps, id)?),
+        QueryMsg::Arbiters { start_after, limit } => to_binary(&query_arbiters(deps, start_after, limit)?),
+        QueryMsg::ArbiterStats { address } => to_binary(&query_arbiter_stats(deps, address)?),
+        QueryMsg::History { id, start_after, limit } => to_binary(&query_history(deps, id, start_after, limit)?),
+        QueryMsg::Applications { id, start_after, limit } => {
+            to_binary(&query_applications(deps, id, start_after, limit)?)
+        }
+        QueryMsg::Messages { id, start_after, limit } => {
+            to_binary(&query_messages(deps, id, start_after, limit)?)
+        }
+        QueryMsg::Fills { id, start_after, limit } => {
+            to_binary(&query_fills(deps, id, start_after, limit)?)
+        }
+    }
+}
+
+fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    let config = CONFIG.may_load(deps.storage)?.unwrap_or_default();
+    let pool = FEE_POOL.may_load(deps.storage)?.unwrap_or_default();
+    Ok(ConfigResponse {
+        admin: config.admin.map(|a| a.into_string()),
+        keeper_bounty: config.keeper_bounty,
+        bounty_min_age: config.bounty_min_age,
+        acceptance_window: config.acceptance_window,
+        require_registered_arbiter: config.require_registered_arbiter,
+        metadata_limits: config.metadata_limits,
+        receipt_collection: config.receipt_collection.map(|addr| addr.into_string()),
+        fee_pool_native: pool.native,
+        fee_pool_cw20: pool
+            .cw20
+            .into_iter()
+            .map(|token| Cw20Coin {
+                address: token.address.into(),
+                amount: token.amount,
+            })
+            .collect(),
+    })
+}
+
+fn query_list(deps: Deps) -> StdResult<ListResponse> {
+    Ok(
+        ListResponse {
+            escrows: all_escrow_ids(deps.storage)?,
+        }
+    )
+}
+
+fn query_details(deps: Deps, id: String) -> StdResult<DetailsResponse> {
+    let escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    let cw20_wishlist = escrow.human_wishlist();
+
+    let native_balance = escrow.balance.native;
+
+    let cw20_balance: StdResult<Vec<_>> = escrow
+        .balance.cw20.into_iter()
+        .map(|token| {
+            Ok(Cw20Coin {
+                address: token.address.into(),
+                amount: token.amount,
+            })
+        }).collect();
+    
+    let recipient = escrow.recipient.map(|addr| addr.into_string());
+
+    let recip_native_balance = escrow.recip_balance.native;
+
+    let recip_cw20_balance: StdResult<Vec<_>> = escrow
+        .recip_balance.cw20.into_iter()
+        .map(|token| {
+            Ok(Cw20Coin {
+                address: token.address.into(),
+                amount: token.amount,
+            })
+        }).collect();
+
+    let details = DetailsResponse {
+        id,
+        arbiter: escrow.arbiter.into(),
+        recipient,
+        source: escrow.source.into(),
+        title: escrow.title,
+        description: escrow.description,
+        expires: escrow.expires,
+        native_balance,
+        cw20_balance: cw20_balance?,
+        recip_native_balance,
+        recip_cw20_balance: recip_cw20_balance?,
+        cw20_wishlist,
+        condition: escrow.condition,
+        payout_msg: escrow.payout_msg,
+        terms: escrow.terms,
+        grace_period: escrow.grace_period,
+        arbiter_extends: escrow.arbiter_extends,
+        pending_extension: escrow.pending_extension,
+        funding_deadline: escrow.funding_deadline,
+        arbiter_fee: escrow.arbiter_fee,
+        arbitration: escrow.arbitration,
+        acceptance_deadline: escrow.acceptance_deadline,
+        pending_arbiter: escrow.pending_arbiter.map(|a| a.into()),
+        backup_arbiter: escrow.backup_arbiter,
+        authorization: escrow.authorization,
+        decision_nonce: escrow.decision_nonce,
+        assignment_needs_consent: escrow.assignment_needs_consent,
+        pending_assignment: escrow.pending_assignment.map(|a| a.into()),
+        receipt_collection: escrow.receipt_collection.map(|addr| addr.into_string()),
+        recipient_token_id: escrow.recipient_token_id,
+        source_token_id: escrow.source_token_id,
+        open_to_applications: escrow.open_to_applications,
+        application_deposit: escrow.application_deposit,
+        prize_pool: escrow.prize_pool,
+        winners: escrow.winners,
+        tags: escrow.tags,
+        external_id: escrow.external_id,
+        content_hash: escrow.content_hash,
+        memos: escrow.memos,
+    };
+
+    Ok(details)
+}
+
+fn query_details_by_external_id(deps: Deps, external_id: String) -> StdResult<DetailsResponse> {
+    let id = EXTERNAL_IDS.load(deps.storage, &external_id)?;
+    query_details(deps, id)
+}
+
+fn query_time_remaining(deps: Deps, env: Env, id: String) -> StdResult<TimeRemainingResponse> {
+    let escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    let remaining = match escrow.expires {
+        Expiration::AtHeight(height) => Some(Duration::Height(height.saturating_sub(env.block.height))),
+        Expiration::AtTime(time) => Some(Duration::Time(
+            time.seconds().saturating_sub(env.block.time.seconds()),
+        )),
+        Expiration::Never {} => None,
+    };
+    Ok(TimeRemainingResponse {
+        id,
+        expires: escrow.expires,
+        expired: escrow.expires.is_expired(&env.block),
+        remaining,
+    })
+}
+
+fn query_phase(deps: Deps, env: Env, id: String) -> StdResult<PhaseResponse> {
+    let escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    Ok(PhaseResponse {
+        id,
+        phase: escrow.phase(&env),
+        funding_deadline: escrow.funding_deadline,
+        settlement_deadline: escrow.expires,
+    })
+}
+
+fn query_template(deps: Deps, id: String) -> StdResult<TemplateResponse> {
+    let template = TEMPLATES.load(deps.storage, &id)?;
+    Ok(TemplateResponse {
+        id,
+        owner: template.owner.into(),
+        arbiter: template.arbiter.into(),
+        cw20_wishlist: template.cw20_wishlist.iter().map(|a| a.to_string()).collect(),
+        expires_in: template.expires_in,
+        arbiter_fee: template.arbiter_fee,
+    })
+}
+
+// settings for pagination
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_LIMIT: u32 = 10;
+
+fn query_offers(
+    deps: Deps,
+    offer_asset: Denom,
+    ask_asset: Denom,
+    start_after: Option<(Decimal, String)>,
+    limit: Option<u32>,
+) -> StdResult<OffersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let pair = pair_key(&offer_asset, &ask_asset);
+
+    // resume right after the last offer of the previous page, as it was listed
+    let start = start_after
+        .as_ref()
+        .map(|(price, id)| Bound::exclusive((price.atomics().u128(), id.as_str())));
+
+    let offers = OFFERS
+        .sub_prefix(&pair)
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|key| {
+            let (_, id) = key?;
+            let escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+            let offer = escrow
+                .open_offer()
+                .ok_or_else(|| StdError::generic_err("Offers index is out of sync"))?;
+            Ok(OfferInfo {
+                price: offer.price(),
+                source: escrow.source.into(),
+                offer_amount: offer.amount,
+                ask_amount: offer.terms.ask_amount,
+                id,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+
+    Ok(OffersResponse { offers })
+}
+
+fn query_arbiters(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<ArbitersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_after = start_after.map(|a| deps.api.addr_validate(&a)).transpose()?;
+    let start = start_after.as_ref().map(Bound::exclusive);
+
+    let arbiters = ARBITERS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (address, profile) = item?;
+            Ok(ArbiterInfo {
+                address: address.into(),
+                profile,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+
+    Ok(ArbitersResponse { arbiters })
+}
+
+fn query_arbiter_stats(deps: Deps, address: String) -> StdResult<ArbiterStatsResponse> {
+    let arbiter = deps.api.addr_validate(&address)?;
+    let stats = ARBITER_STATS.may_load(deps.storage, &arbiter)?.unwrap_or_default();
+    Ok(ArbiterStatsResponse {
+        address,
+        approvals: stats.approvals,
+        refunds: stats.refunds,
+        accepted: stats.accepted,
+        disputes: stats.disputes,
+        avg_resolution_time: stats.avg_resolution_time(),
+    })
+}
+
+fn query_applications(
+    deps: Deps,
+    id: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<ApplicationsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_after = start_after.map(|a| deps.api.addr_validate(&a)).transpose()?;
+    let start = start_after.as_ref().map(Bound::exclusive);
+
+    let applications = APPLICATIONS
+        .prefix(&id)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (applicant, application) = item?;
+            Ok(ApplicationInfo {
+                applicant: applicant.into(),
+                application,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+
+    Ok(ApplicationsResponse { applications })
+}
+
+fn query_history(
+    deps: Deps,
+    id: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<HistoryResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let entries = HISTORY
+        .prefix(&id)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (index, entry) = item?;
+            Ok(HistoryInfo {
+                index,
+                actor: entry.actor.into(),
+                change: entry.change,
+                height: entry.height,
+                time: entry.time,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+
+    Ok(HistoryResponse { entries })
+}
+
+fn query_messages(
+    deps: Deps,
+    id: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<MessagesResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let messages = MESSAGES
+        .prefix(&id)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (index, message) = item?;
+            Ok(ThreadMessage {
+                index,
+                sender: message.sender.into(),
+                ciphertext: message.ciphertext,
+                height: message.height,
+                time: message.time,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+
+    Ok(MessagesResponse { messages })
+}
+
+fn query_fills(
+    deps: Deps,
+    id: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<FillsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let fills = FILLS
+        .prefix(&id)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (index, fill) = item?;
+            Ok(FillInfo {
+                index,
+                taker: fill.taker.into(),
+                paid: fill.paid,
+                native_received: fill.received.native,
+                cw20_received: fill
+                    .received
+                    .cw20
+                    .into_iter()
+                    .map(|token| Cw20Coin {
+                        address: token.address.into(),
+                        amount: token.amount,
+                    })
+                    .collect(),
+                height: fill.height,
+                time: fill.time,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+
+    Ok(FillsResponse { fills })
+}
+
+fn query_stream(deps: Deps, id: String) -> StdResult<StreamResponse> {
+    let escrow = load_escrow(deps.storage, &deps.querier, &id)?;
+    let stream = escrow
+        .stream
+        .ok_or_else(|| StdError::generic_err("Escrow is not a stream"))?;
+
+    let remaining_payouts = stream.remaining_payouts(&escrow.balance);
+    let last_payout = match remaining_payouts {
+        0 => Expiration::Never {},
+        n => (stream.next_payout + stream.interval * (n - 1))?,
+    };
+
+    Ok(StreamResponse {
+        id,
+        native_amount: stream.amount.native,
+        cw20_amount: stream
+            .amount
+            .cw20
+            .into_iter()
+            .map(|token| Cw20Coin {
+                address: token.address.into(),
+                amount: token.amount,
+            })
+            .collect(),
+        interval: stream.interval,
+        paused: stream.paused,
+        next_payout: stream.next_payout,
+        remaining_payouts,
+        last_payout,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{attr, coin, coins, BankMsg, CosmosMsg, Decimal, Storage, SubMsg, Uint128, WasmMsg};
+    use cw20::Cw20ExecuteMsg;
+    use crate::msg::BackupArbiterMsg;
+    use crate::state::{ContentHash, EncryptedMemo};
+    use crate::msg::ExecuteMsg::TopUp;
+
+    #[test]
+    fn instantiate_test(){
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+    }
+
+    #[test]
+    fn instantiate_and_create_escrow_and_query_details_test(){
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        let wishlist = vec![String::from("btc"), String::from("eth")];
+
+        // create an escrow
+        let create = CreateMsg {
+            id: "some_id".to_string(),
+            arbiter: String::from("some_arbiter"),
+            recipient: Some(String::from("some_recipient")),
+            title: "some_title".to_string(),
+            expires: Some(Expiration::AtHeight(123456)),
+            description: "some_description".to_string(),
+            cw20_wishlist: Some(wishlist),
+            ..Default::default()
+        };
+        let sender = String::from("source");
+        let balance = coins(100, "tokens");
+        let info = mock_info(&sender, &balance);
+        let msg = ExecuteMsg::CreateEscrow(create.clone());
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(("action", "create_escrow"), res.attributes[0]);
+
+        // ensure the details is what we expect
+        let details = query_details(deps.as_ref(), "some_id".to_string()).unwrap();
+        assert_eq!(
+            details,
+            DetailsResponse {
+                id: "some_id".to_string(),
+                arbiter: String::from("some_arbiter"),
+                recipient: Some(String::from("some_recipient")),
+                source: String::from("source"),
+                title: "some_title".to_string(),
+                description: "some_description".to_string(),
+                expires: Expiration::AtHeight(123456),
+                native_balance: balance.clone(),
+                cw20_balance: vec![],
+                recip_native_balance: vec![],
+                recip_cw20_balance: vec![],
+                cw20_wishlist: vec![String::from("btc"), String::from("eth")],
+                condition: None,
+                payout_msg: None,
+                terms: None,
+                grace_period: None,
+                arbiter_extends: false,
+                pending_extension: None,
+                funding_deadline: None,
+                arbiter_fee: None,
+                arbitration: Arbitration::Pending,
+                acceptance_deadline: Some(DEFAULT_ACCEPTANCE_WINDOW.after(&mock_env().block)),
+                pending_arbiter: None,
+                backup_arbiter: None,
+                authorization: Authorization::Sender,
+                decision_nonce: 1,
+                assignment_needs_consent: false,
+                pending_assignment: None,
+                receipt_collection: None,
+                recipient_token_id: None,
+                source_token_id: None,
+                open_to_applications: false,
+                application_deposit: None,
+                prize_pool: false,
+                winners: vec![],
+                tags: vec![],
+                external_id: None,
+                content_hash: None,
+                memos: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn instantiate_and_create_escrow_and_approve_and_approve_again_test() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        // create an escrow
+        let create = CreateMsg {
+            id: "some_id".to_string(),
+            arbiter: String::from("some_arbiter"),
+            recipient: Some(String::from("some_recipient")),
+            title: "some_title".to_string(),
+            expires: Some(Expiration::AtHeight(123456)),
+            cw20_wishlist: None,
+            description: "some_description".to_string(),
+            ..Default::default()
+        };
+        let sender = String::from("source");
+        let balance = coins(100, "tokens");
+        let info = mock_info(&sender, &balance);
+        let msg = ExecuteMsg::CreateEscrow(create.clone());
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(("action", "create_escrow"), res.attributes[0]);
+
+        // ensure the details is what we expect
+        let details = query_details(deps.as_ref(), "some_id".to_string()).unwrap();
+        assert_eq!(
+            details,
+            DetailsResponse {
+                id: "some_id".to_string(),
+                arbiter: String::from("some_arbiter"),
+                recipient: Some(String::from("some_recipient")),
+                source: String::from("source"),
+                title: "some_title".to_string(),
+                description: "some_description".to_string(),
+                expires: Expiration::AtHeight(123456),
+                native_balance: balance.clone(),
+                cw20_balance: vec![],
+                cw20_wishlist: vec![],
+                recip_native_balance: vec![],
+                recip_cw20_balance: vec![],
+                condition: None,
+                payout_msg: None,
+                terms: None,
+                grace_period: None,
+                arbiter_extends: false,
+                pending_extension: None,
+                funding_deadline: None,
+                arbiter_fee: None,
+                arbitration: Arbitration::Pending,
+                acceptance_deadline: Some(DEFAULT_ACCEPTANCE_WINDOW.after(&mock_env().block)),
+                pending_arbiter: None,
+                backup_arbiter: None,
+                authorization: Authorization::Sender,
+                decision_nonce: 1,
+                assignment_needs_consent: false,
+                pending_assignment: None,
+                receipt_collection: None,
+                recipient_token_id: None,
+                source_token_id: None,
+                open_to_applications: false,
+                application_deposit: None,
+                prize_pool: false,
+                winners: vec![],
+                tags: vec![],
+                external_id: None,
+                content_hash: None,
+                memos: vec![],
+            }
+        );
+
+        // someone (not arbiter) tries to approve it
+        let id = create.id.clone();
+        let info = mock_info(&String::from("anyone"), &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id });
+        match res {
+            Err(ContractError::Unauthorized {}) => {}
+            _ => panic!("Expect unauthorized error"),
+        }
+
+
+        // arbiter approve it
+        let id = create.id.clone();
+        let info = mock_info(&create.arbiter, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(("action", "approve"), res.attributes[0]);
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: create.recipient.unwrap(),
+                amount: balance,
+            }))
+        );
+
+        // second attempt fails (not found)
+        let id = create.id.clone();
+        let info = mock_info(&create.arbiter, &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::NotFound { .. })));
+    }
+
+    #[test]
+    fn create_escrow_and_set_recipient_test(){
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        // create an escrow
+        let create = CreateMsg {
+            id: "test_id".to_string(),
+            arbiter: String::from("test_arbiter"),
+            recipient: None,
+            title: "test_title".to_string(),
+            expires: Some(Expiration::AtHeight(123456)),
+            cw20_wishlist: None,
+            description: "test_description".to_string(),
+            ..Default::default()
+        };
+        let sender = String::from("test_source");
+        let balance = coins(100, "tokens");
+        let info = mock_info(&sender, &balance);
+        let msg = ExecuteMsg::CreateEscrow(create.clone());
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(("action", "create_escrow"), res.attributes[0]);
+
+        // ensure the details is what we expect
+        let details = query_details(deps.as_ref(), "test_id".to_string()).unwrap();
+        assert_eq!(
+            details,
+            DetailsResponse {
+                id: "test_id".to_string(),
+                arbiter: String::from("test_arbiter"),
+                recipient: None,
+                source: String::from("test_source"),
+                title: "test_title".to_string(),
+                description: "test_description".to_string(),
+                expires: Expiration::AtHeight(123456),
+                native_balance: balance.clone(),
+                cw20_balance: vec![],
+                recip_native_balance: vec![],
+                recip_cw20_balance: vec![],
+                cw20_wishlist: vec![],
+                condition: None,
+                payout_msg: None,
+                terms: None,
+                grace_period: None,
+                arbiter_extends: false,
+                pending_extension: None,
+                funding_deadline: None,
+                arbiter_fee: None,
+                arbitration: Arbitration::Pending,
+                acceptance_deadline: Some(DEFAULT_ACCEPTANCE_WINDOW.after(&mock_env().block)),
+                pending_arbiter: None,
+                backup_arbiter: None,
+                authorization: Authorization::Sender,
+                decision_nonce: 1,
+                assignment_needs_consent: false,
+                pending_assignment: None,
+                receipt_collection: None,
+                recipient_token_id: None,
+                source_token_id: None,
+                open_to_applications: false,
+                application_deposit: None,
+                prize_pool: false,
+                winners: vec![],
+                tags: vec![],
+                external_id: None,
+                content_hash: None,
+                memos: vec![],
+            }
+        );
+
+        // approve it, should fail as we have not set recipient
+        let id = create.id.clone();
+        let info = mock_info(&create.arbiter, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id });
+        match res {
+            Err(ContractError::RecipientNotSet {}) => {}
+            _ => panic!("Expect recipient not set error"),
+        }
+
+        // test someone that is not arbiter setting recipient should fail
+        let msg = ExecuteMsg::SetRecipient {
+            id: create.id.clone(),
+            recipient: "recp".to_string(),
+        };
+        let info = mock_info("someoneelse", &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, msg);
+        match res {
+            Err(ContractError::Unauthorized {}) => {}
+            _ => panic!("Expect unauthorized error"),
+        }
+
+        // test setting recipient valid
+        let msg = ExecuteMsg::SetRecipient {
+            id: create.id.clone(),
+            recipient: "recp".to_string(),
+        };
+        let info = mock_info(&create.arbiter, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(res.messages.len(), 0);
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "set_recipient"),
+                attr("id", create.id.as_str()),
+                attr("recipient", "recp")
+            ]
+        );
+        let history = query_history(deps.as_ref(), create.id.clone(), None, None).unwrap();
+        assert_eq!(history.entries[0].change, Change::RecipientSet { from: None, to: Addr::unchecked("recp") });
+
+        // approve it, should now work with recp
+        let id = create.id.clone();
+        let info = mock_info(&create.arbiter, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(("action", "approve"), res.attributes[0]);
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "recp".to_string(),
+                amount: balance,
+            }))
+        );
+    }
+
+    /*
+    #[test]
+    fn create_escrow_and_refund(){
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        // create an escrow
+        let create = CreateMsg {
+            id: "test_id".to_string(),
+            arbiter: String::from("test_arbiter"),
+            recipient: Some(String::from("recp")),
+            title: "test_title".to_string(),
+            expires: Some(Expiration::AtHeight(123456)),
+            cw20_wishlist: None,
+            description: "test_description".to_string(),
+            ..Default::default()
+        };
+        let sender = String::from("test_source");
+        let balance = coins(100, "tokens");
+        let recip_balance = Some(None);
+        //let recip_balance = None;
+        let info = mock_info(&sender, &balance);
+        let msg = ExecuteMsg::CreateEscrow(create.clone());
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(("action", "create_escrow"), res.attributes[0]);
+
+        // test someone that is not arbiter refund should fail
+        let msg = ExecuteMsg::Refund {
+            id: create.id.clone(),
+        };
+        let info = mock_info("someoneelse", &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, msg);
+        match res {
+            Err(ContractError::Unauthorized {}) => {}
+            _ => panic!("Expect unauthorized error"),
+        }
+
+        // test refund valid
+        let id = create.id.clone();
+        let info = mock_info(&create.arbiter, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Refund { id }).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(("action", "refund"), res.attributes[0]);
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: create.arbiter,
+                amount: balance,
+            }))
+        );
+        assert_eq!(
+            res.messages[1],
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: create.recipient.unwrap(),
+                amount: recip_balance,
+            }))
+        );
+    }*/
+
+    #[test]
+    fn add_native_tokens_proper() {
+        let mut tokens = GenericBalance::default();
+        tokens.add_tokens(Balance::from(vec![coin(123, "atom"), coin(789, "eth")]));
+        tokens.add_tokens(Balance::from(vec![coin(456, "atom"), coin(12, "btc")]));
+        assert_eq!(
+            tokens.native,
+            vec![coin(579, "atom"), coin(789, "eth"), coin(12, "btc")]
+        );
+    }
+    
+    #[test]
+    fn add_cw_tokens_proper() {
+        let mut tokens = GenericBalance::default();
+        let test_token = Addr::unchecked("test_token");
+        let test2_token = Addr::unchecked("test2_token");
+        tokens.add_tokens(Balance::Cw20(Cw20CoinVerified {
+            address: test_token.clone(),
+            amount: Uint128::new(12345),
+        }));
+        tokens.add_tokens(Balance::Cw20(Cw20CoinVerified {
+            address: test2_token.clone(),
+            amount: Uint128::new(777),
+        }));
+        tokens.add_tokens(Balance::Cw20(Cw20CoinVerified {
+            address: test_token.clone(),
+            amount: Uint128::new(23400),
+        }));
+        assert_eq!(
+            tokens.cw20,
+            vec![
+                Cw20CoinVerified {
+                    address: test_token,
+                    amount: Uint128::new(35745),
+                },
+                Cw20CoinVerified {
+                    address: test2_token,
+                    amount: Uint128::new(777),
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn top_up_mixed_tokens() {
+        // instantiate an empty contract &&  accept only certain tokens
+        // create an escrow with 2 native tokens &&  top it up with 2 more native tokens
+        // top up with one foreign token
+        // top up with second foreign token
+        // approve it  &&  first message releases all native coins
+        // second one release token_1 cw20 token  &&  third one release token_2 cw20 token
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        // create an escrow with 2 native tokens
+        let create = CreateMsg {
+            id: "escrow_id".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "some_title".to_string(),
+            expires: None,
+            cw20_wishlist: None,
+            description: "some_description".to_string(),
+            ..Default::default()
+        };
+        let sender = String::from("source");
+        let balance = vec![coin(100, "fee"), coin(200, "stake")];
+        let info = mock_info(&sender, &balance);
+        let msg = ExecuteMsg::CreateEscrow(create.clone());
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(("action", "create_escrow"), res.attributes[0]);
+
+        // top it up with 2 more native tokens
+        let extra_native = vec![coin(250, "random"), coin(300, "stake")];
+        let info = mock_info(&create.arbiter, &extra_native);
+        let top_up = ExecuteMsg::TopUp {
+            id: create.id.clone(),
+        };
+        let res = execute(deps.as_mut(), mock_env(), info, top_up).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(("action", "top_up"), res.attributes[0]);
+        
+        // top it up with 2 more native tokens |fails| not arbiter
+        let extra_native = vec![coin(250, "random"), coin(300, "stake")];
+        let info = mock_info(&sender, &extra_native);
+        let top_up = ExecuteMsg::TopUp {
+            id: create.id.clone(),
+        };
+        let res = execute(deps.as_mut(), mock_env(), info, top_up);
+        match res {
+            Err(ContractError::Unauthorized {}) => {}
+            _ => panic!("Expect unauthorized error"),
+        }
+
+        // top up with one foreign token
+        let wl_token_1 = String::from("wl_token_1");
+        let base = TopUp {
+            id: create.id.clone(),
+        };
+        let top_up = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: String::from("arbi"),
+            amount: Uint128::new(7890),
+            msg: to_binary(&base).unwrap(),
+        });
+        let info = mock_info(&wl_token_1, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, top_up).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(("action", "top_up"), res.attributes[0]);
+
+        // top up with one foreign token
+        let wl_token_1 = String::from("wl_token_1");
+        let base = TopUp {
+            id: create.id.clone(),
+        };
+        let top_up = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: String::from("random_sender"),
+            amount: Uint128::new(7890),
+            msg: to_binary(&base).unwrap(),
+        });
+        let info = mock_info(&wl_token_1, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, top_up);
+        match res {
+            Err(ContractError::Unauthorized {}) => {}
+            _ => panic!("Expect unauthorized error"),
+        }
+
+        // top up with second foreign token
+        let wl_token_2 = String::from("wl_token_2");
+        let base = TopUp {
+            id: create.id.clone(),
+        };
+        let top_up = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: String::from("arbi"),
+            amount: Uint128::new(888),
+            msg: to_binary(&base).unwrap(),
+        });
+        let info = mock_info(&wl_token_2, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, top_up).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(("action", "top_up"), res.attributes[0]);
+
+        // approve it
+        let id = create.id.clone();
+        let info = mock_info(&create.arbiter, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap();
+        assert_eq!(("action", "approve"), res.attributes[0]);
+        assert_eq!(3, res.messages.len());
+
+        // first message releases all native coins
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: create.recipient.clone().unwrap(),
+                amount: vec![coin(100, "fee"), coin(500, "stake"), coin(250, "random")],
+            }))
+        );
+
+        // second one release token_1 cw20 token
+        let send_msg = Cw20ExecuteMsg::Transfer {
+            recipient: create.recipient.clone().unwrap(),
+            amount: Uint128::new(7890),
+        };
+        assert_eq!(
+            res.messages[1],
+            SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: wl_token_1,
+                msg: to_binary(&send_msg).unwrap(),
+                funds: vec![]
+            }))
+        );
+
+        // third one release token_2 cw20 token
+        let send_msg = Cw20ExecuteMsg::Transfer {
+            recipient: create.recipient.unwrap(),
+            amount: Uint128::new(888),
+        };
+        assert_eq!(
+            res.messages[2],
+            SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: wl_token_2,
+                msg: to_binary(&send_msg).unwrap(),
+                funds: vec![]
+            }))
+        );
+    }
+
+    #[test]
+    fn stream_pays_accrued_intervals_until_exhausted() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        // create a stream paying 100 tokens every 10 blocks out of 550
+        let create = CreateMsg {
+            id: "payroll".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("contributor")),
+            title: "payroll".to_string(),
+            description: "monthly contributor payment".to_string(),
+            stream: Some(crate::msg::StreamMsg {
+                native_amount: coins(100, "tokens"),
+                cw20_amount: vec![],
+                interval: Duration::Height(10),
+            }),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(550, "tokens"));
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+
+        let mut env = mock_env();
+        let start = env.block.height;
+        let stream = query_stream(deps.as_ref(), "payroll".to_string()).unwrap();
+        assert_eq!(stream.next_payout, Expiration::AtHeight(start + 10));
+        assert_eq!(stream.remaining_payouts, 6);
+        assert_eq!(stream.last_payout, Expiration::AtHeight(start + 60));
+
+        // nothing is paid before the arbiter accepted the escrow
+        let tick = ExecuteMsg::Tick { id: "payroll".to_string() };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), tick.clone()).unwrap_err();
+        assert_eq!(err, ContractError::ArbitrationPending {});
+        let accept = ExecuteMsg::AcceptArbitration { id: "payroll".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), accept).unwrap();
+
+        // nothing accrued yet
+        let err = execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), tick.clone()).unwrap_err();
+        assert_eq!(err, ContractError::NoPayoutDue {});
+
+        // two intervals accrued, anyone can tick
+        env.block.height = start + 25;
+        let res = execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), tick.clone()).unwrap();
+        assert_eq!(("intervals", "2"), res.attributes[2]);
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "contributor".to_string(),
+                amount: coins(200, "tokens"),
+            }))
+        );
+        let stream = query_stream(deps.as_ref(), "payroll".to_string()).unwrap();
+        assert_eq!(stream.next_payout, Expiration::AtHeight(start + 30));
+        assert_eq!(stream.remaining_payouts, 4);
+
+        // only the arbiter can pause, the intervals accrued until then are paid
+        let pause = ExecuteMsg::PauseStream { id: "payroll".to_string() };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("contributor", &[]), pause.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        env.block.height = start + 45;
+        let res = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), pause).unwrap();
+        assert_eq!(("intervals", "2"), res.attributes[2]);
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "contributor".to_string(), amount: coins(200, "tokens") })]
+        );
+
+        // a paused stream does not pay
+        env.block.height = start + 60;
+        let err = execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), tick.clone()).unwrap_err();
+        assert_eq!(err, ContractError::StreamPaused {});
+
+        // resuming restarts the clock
+        let resume = ExecuteMsg::ResumeStream { id: "payroll".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), resume).unwrap();
+        let stream = query_stream(deps.as_ref(), "payroll".to_string()).unwrap();
+        assert_eq!(stream.next_payout, Expiration::AtHeight(start + 70));
+
+        // the last partial payout exhausts the balance and closes the escrow
+        env.block.height = start + 100;
+        let res = execute(deps.as_mut(), env, mock_info("keeper", &[]), tick).unwrap();
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "contributor".to_string(),
+                amount: coins(150, "tokens"),
+            }))
+        );
+        assert!(query_details(deps.as_ref(), "payroll".to_string()).is_err());
+    }
+
+    #[test]
+    fn expired_stream_pays_the_intervals_due_before_expiry() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        // a stream counted in blocks cannot expire at a time
+        let start = mock_env().block.height;
+        let mut create = CreateMsg {
+            id: "payroll".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("contributor")),
+            title: "payroll".to_string(),
+            description: "monthly contributor payment".to_string(),
+            expires: Some(Expiration::AtTime(mock_env().block.time.plus_seconds(1000))),
+            stream: Some(crate::msg::StreamMsg {
+                native_amount: coins(100, "tokens"),
+                cw20_amount: vec![],
+                interval: Duration::Height(10),
+            }),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(550, "tokens"));
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap_err();
+        assert_eq!(err, ContractError::InvalidStream {});
+
+        // pays 100 tokens every 10 blocks until it expires at start + 35
+        create.expires = Some(Expiration::AtHeight(start + 35));
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+        let accept = ExecuteMsg::AcceptArbitration { id: "payroll".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), accept).unwrap();
+
+        // long after the expiry the three intervals that fell due before it are still paid
+        let mut env = mock_env();
+        env.block.height = start + 100;
+        let tick = ExecuteMsg::Tick { id: "payroll".to_string() };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), tick.clone()).unwrap();
+        assert_eq!(("intervals", "3"), res.attributes[2]);
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "contributor".to_string(), amount: coins(300, "tokens") })]
+        );
+        let stream = query_stream(deps.as_ref(), "payroll".to_string()).unwrap();
+        assert_eq!(stream.next_payout, Expiration::AtHeight(start + 40));
+
+        // nothing else is due, the rest goes back to the source through the refund
+        let err = execute(deps.as_mut(), env, mock_info("keeper", &[]), tick).unwrap_err();
+        assert_eq!(err, ContractError::Expired {});
+    }
+
+    #[test]
+    fn unfunded_stream_and_condition_escrows_cannot_pay_out() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        // both wait for the recipient leg until 10 blocks from now
+        let env = mock_env();
+        let stream = CreateMsg {
+            id: "payroll".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("contributor")),
+            title: "payroll".to_string(),
+            description: "monthly contributor payment".to_string(),
+            funding_deadline: Some(Expiration::AtHeight(env.block.height + 10)),
+            stream: Some(crate::msg::StreamMsg {
+                native_amount: coins(100, "tokens"),
+                cw20_amount: vec![],
+                interval: Duration::Height(10),
+            }),
+            ..Default::default()
+        };
+        let conditional = CreateMsg {
+            id: "conditional".to_string(),
+            stream: None,
+            condition: Some(crate::msg::ConditionMsg {
+                oracle: String::from("oracle"),
+                query: Binary::from(b"{}".to_vec()),
+                comparison: crate::state::Comparison::AtLeast(Uint128::new(100)),
+            }),
+            ..stream.clone()
+        };
+        for create in [stream, conditional] {
+            let info = mock_info("source", &coins(500, "tokens"));
+            execute(deps.as_mut(), env.clone(), info, ExecuteMsg::CreateEscrow(create.clone())).unwrap();
+            let accept = ExecuteMsg::AcceptArbitration { id: create.id };
+            execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), accept).unwrap();
+        }
+
+        // once the deadline passed without funding, neither pays the recipient
+        let mut later = env;
+        later.block.height += 20;
+        let tick = ExecuteMsg::Tick { id: "payroll".to_string() };
+        let err = execute(deps.as_mut(), later.clone(), mock_info("keeper", &[]), tick).unwrap_err();
+        assert_eq!(err, ContractError::Expired {});
+        let check = ExecuteMsg::CheckCondition { id: "conditional".to_string() };
+        let err = execute(deps.as_mut(), later, mock_info("keeper", &[]), check).unwrap_err();
+        assert_eq!(err, ContractError::Expired {});
+    }
+
+    #[test]
+    fn batch_merges_payouts_to_the_same_address() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let escrow = |id: &str| CreateMsg {
+            id: id.to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "some_title".to_string(),
+            description: "some_description".to_string(),
+            ..Default::default()
+        };
+        let ops = vec![
+            EscrowOp::Create { escrow: escrow("first"), funds: coins(100, "tokens") },
+            EscrowOp::Create { escrow: escrow("second"), funds: vec![coin(50, "tokens"), coin(7, "fee")] },
+            EscrowOp::TopUp { id: "first".to_string(), funds: coins(20, "tokens") },
+        ];
+
+        // funds must match exactly what the operations use
+        let info = mock_info("arbi", &coins(170, "tokens"));
+        let err = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Batch { ops: ops.clone() }).unwrap_err();
+        assert_eq!(err, ContractError::BatchFundsMismatch {});
+
+        let info = mock_info("arbi", &[coin(170, "tokens"), coin(7, "fee")]);
+        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Batch { ops }).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(("action", "batch"), res.attributes[0]);
+        let funded = |kind: &str, id: &str, native: &str| {
+            Event::new(kind)
+                .add_attribute("id", id)
+                .add_attribute("actor", "arbi")
+                .add_attribute("leg", "source")
+                .add_attribute("native", native)
+        };
+        assert_eq!(
+            res.events,
+            vec![
+                funded("escrow_created", "first", "100tokens"),
+                funded("escrow_created", "second", "50tokens,7fee"),
+                funded("escrow_funded", "first", "20tokens"),
+            ]
+        );
+
+        // approving both escrows pays the recipient once
+        let ops = vec![
+            EscrowOp::Approve { id: "first".to_string() },
+            EscrowOp::Approve { id: "second".to_string() },
+        ];
+        let info = mock_info("arbi", &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Batch { ops }).unwrap();
+        let released = |id: &str, native: &str| {
+            Event::new("escrow_released")
+                .add_attribute("id", id)
+                .add_attribute("actor", "arbi")
+                .add_attribute("leg", "source")
+                .add_attribute("to", "reci")
+                .add_attribute("native", native)
+        };
+        assert_eq!(res.events, vec![released("first", "120tokens"), released("second", "50tokens,7fee")]);
+        assert_eq!(1, res.messages.len());
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "reci".to_string(),
+                amount: vec![coin(170, "tokens"), coin(7, "fee")],
+            }))
+        );
+        assert_eq!(all_escrow_ids(&deps.storage).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn refund_without_recipient_skips_empty_legs_and_zero_amounts() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        // create an escrow without recipient, one of the coins is empty
+        let create = CreateMsg {
+            id: "no_recipient".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: None,
+            title: "some_title".to_string(),
+            description: "some_description".to_string(),
+            ..Default::default()
+        };
+        let info = mock_info("source", &[coin(0, "dust"), coin(100, "tokens")]);
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+
+        // arbiter refunds, only the source leg holds tokens
+        let info = mock_info("arbi", &[]);
+        let msg = ExecuteMsg::Refund { id: "no_recipient".to_string() };
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "source".to_string(),
+                amount: coins(100, "tokens"),
+            }))]
+        );
+    }
+
+    #[test]
+    fn approve_calls_contract_recipient_with_payout_msg() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        // the recipient is a treasury contract that wants to be notified
+        let payout_msg = Binary::from(br#"{"escrow_settled":{}}"#.to_vec());
+        let create = CreateMsg {
+            id: "to_treasury".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("treasury")),
+            title: "some_title".to_string(),
+            description: "some_description".to_string(),
+            payout_msg: Some(payout_msg.clone()),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(100, "tokens"));
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+
+        let top_up = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: String::from("arbi"),
+            amount: Uint128::new(500),
+            msg: to_binary(&TopUp { id: "to_treasury".to_string() }).unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info("cw20_token", &[]), top_up).unwrap();
+
+        let info = mock_info("arbi", &[]);
+        let msg = ExecuteMsg::Approve { id: "to_treasury".to_string() };
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(WasmMsg::Execute {
+                    contract_addr: "treasury".to_string(),
+                    msg: payout_msg.clone(),
+                    funds: coins(100, "tokens"),
+                }),
+                SubMsg::new(WasmMsg::Execute {
+                    contract_addr: "cw20_token".to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::Send {
+                        contract: "treasury".to_string(),
+                        amount: Uint128::new(500),
+                        msg: payout_msg,
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn replaced_recipient_is_paid_without_the_payout_msg() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        // the callback is written for the treasury contract
+        let create = CreateMsg {
+            id: "to_treasury".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("treasury")),
+            title: "some_title".to_string(),
+            description: "some_description".to_string(),
+            payout_msg: Some(Binary::from(br#"{"escrow_settled":{}}"#.to_vec())),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(100, "tokens"));
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+
+        // setting the same recipient again keeps it, a new recipient gets plain transfers
+        let set = |recipient: &str| ExecuteMsg::SetRecipient { id: "to_treasury".to_string(), recipient: recipient.to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), set("treasury")).unwrap();
+        let details = query_details(deps.as_ref(), "to_treasury".to_string()).unwrap();
+        assert!(details.payout_msg.is_some());
+        execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), set("wallet")).unwrap();
+        let details = query_details(deps.as_ref(), "to_treasury".to_string()).unwrap();
+        assert_eq!(details.payout_msg, None);
+
+        let msg = ExecuteMsg::Approve { id: "to_treasury".to_string() };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "wallet".to_string(), amount: coins(100, "tokens") })]
+        );
+    }
+
+    #[test]
+    fn offers_are_listed_by_pair_and_sorted_by_price() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let usdc = Denom::Cw20(Addr::unchecked("usdc"));
+        let mut offer = |id: &str, atom: u128, ask: Denom, ask_amount: u128, recipient: Option<String>| {
+            let create = CreateMsg {
+                id: id.to_string(),
+                arbiter: String::from("arbi"),
+                recipient,
+                title: "otc".to_string(),
+                description: "atom for usdc".to_string(),
+                terms: Some(crate::state::Terms {
+                    ask,
+                    ask_amount: Uint128::new(ask_amount),
+                    min_fill: None,
+                }),
+                ..Default::default()
+            };
+            let info = mock_info("maker", &coins(atom, "atom"));
+            execute(deps.as_mut(), mock_env(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+            let accept = ExecuteMsg::AcceptArbitration { id: id.to_string() };
+            execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), accept).unwrap();
+        };
+        offer("pricey", 10, usdc.clone(), 120, None);
+        offer("cheap", 100, usdc.clone(), 900, None);
+        offer("middle", 50, usdc.clone(), 500, None);
+        offer("other_pair", 10, Denom::Native("btc".to_string()), 1, None);
+        offer("private", 10, usdc.clone(), 10, Some("friend".to_string()));
+
+        let offers = |deps: Deps, start_after: Option<(Decimal, String)>, limit: Option<u32>| {
+            query_offers(
+                deps,
+                Denom::Native("atom".to_string()),
+                usdc.clone(),
+                start_after,
+                limit,
+            )
+            .unwrap()
+            .offers
+        };
+        let page = offers(deps.as_ref(), None, Some(2));
+        assert_eq!(
+            page.iter().map(|o| o.id.as_str()).collect::<Vec<_>>(),
+            vec!["cheap", "middle"]
+        );
+        assert_eq!(page[0].price, Decimal::from_ratio(9u128, 1u128));
+        assert_eq!(page[0].offer_amount, Uint128::new(100));
+        assert_eq!(page[0].ask_amount, Uint128::new(900));
+        let cursor = (page[1].price, page[1].id.clone());
+        let page = offers(deps.as_ref(), Some(cursor.clone()), None);
+        assert_eq!(
+            page.iter().map(|o| o.id.as_str()).collect::<Vec<_>>(),
+            vec!["pricey"]
+        );
+
+        // the cursor still works once the last offer of the page is fully taken
+        let take = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: String::from("taker"),
+            amount: Uint128::new(500),
+            msg: to_binary(&ReceiveMsg::Take { id: "middle".to_string() }).unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info("usdc", &[]), take).unwrap();
+        assert!(query_details(deps.as_ref(), "middle".to_string()).is_err());
+        let page = offers(deps.as_ref(), Some(cursor), None);
+        assert_eq!(
+            page.iter().map(|o| o.id.as_str()).collect::<Vec<_>>(),
+            vec!["pricey"]
+        );
+
+        // an offer leaves the book once a counterparty is set
+        let msg = ExecuteMsg::SetRecipient { id: "cheap".to_string(), recipient: "taker".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), msg).unwrap();
+        assert_eq!(offers(deps.as_ref(), None, None).len(), 1);
+    }
+
+    #[test]
+    fn take_native_offer_requires_exact_terms() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        // offer a cw20 balance for 50 native atom
+        let create = CreateMsg {
+            id: "offer".to_string(),
+            arbiter: String::from("arbi"),
+            title: "otc".to_string(),
+            description: "token for atom".to_string(),
+            terms: Some(crate::state::Terms {
+                ask: Denom::Native("atom".to_string()),
+                ask_amount: Uint128::new(50),
+                min_fill: None,
+            }),
+            ..Default::default()
+        };
+        let receive = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: String::from("maker"),
+            amount: Uint128::new(1000),
+            msg: to_binary(&ReceiveMsg::CreateEscrow(create)).unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info("token", &[]), receive).unwrap();
+
+        // nothing can be taken before the arbiter accepts
+        let take = ExecuteMsg::Take { id: "offer".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("taker", &coins(50, "atom")), take.clone()).unwrap_err();
+        assert_eq!(err, ContractError::NotAnOpenOffer {});
+        let accept = ExecuteMsg::AcceptArbitration { id: "offer".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), accept).unwrap();
+
+        let err = execute(deps.as_mut(), mock_env(), mock_info("taker", &coins(50, "btc")), take.clone()).unwrap_err();
+        assert_eq!(err, ContractError::WrongAskToken {});
+        let err = execute(deps.as_mut(), mock_env(), mock_info("taker", &coins(60, "atom")), take.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::Overpayment { expected: Uint128::new(50), received: Uint128::new(60) }
+        );
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info("taker", &coins(50, "atom")), take.clone()).unwrap();
+        assert_eq!(("taker", "taker"), res.attributes[2]);
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(WasmMsg::Execute {
+                    contract_addr: "token".to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                        recipient: "taker".to_string(),
+                        amount: Uint128::new(1000),
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                }),
+                SubMsg::new(BankMsg::Send {
+                    to_address: "maker".to_string(),
+                    amount: coins(50, "atom"),
+                }),
+            ]
+        );
+
+        // the offer is gone
+        let err = execute(deps.as_mut(), mock_env(), mock_info("taker", &coins(50, "atom")), take).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::NotFound { .. })));
+    }
+
+    #[test]
+    fn broken_receipt_collection_fails_instead_of_paying_the_stored_party() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let create = CreateMsg {
+            id: "tokenized".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "tokenized".to_string(),
+            description: "positions are nfts".to_string(),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(100, "tokens"));
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+
+        // the collection of the receipt can't be queried at all
+        let mut escrow = ESCROWS.load(&deps.storage, "tokenized").unwrap();
+        escrow.receipt_collection = Some(Addr::unchecked("broken"));
+        escrow.recipient_token_id = Some("1".to_string());
+        ESCROWS.save(&mut deps.storage, "tokenized", &escrow).unwrap();
+
+        assert!(query_details(deps.as_ref(), "tokenized".to_string()).is_err());
+        let approve = ExecuteMsg::Approve { id: "tokenized".to_string() };
+        assert!(execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), approve).is_err());
+    }
+
+    #[test]
+    fn top_up_recip_funds_the_recipient_leg() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let create = CreateMsg {
+            id: "swap".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "swap".to_string(),
+            description: "atom for btc".to_string(),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(100, "atom"));
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+        let accept = ExecuteMsg::AcceptArbitration { id: "swap".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), accept).unwrap();
+
+        // only the recipient funds its leg, the source leg is left untouched
+        let top_up = ExecuteMsg::TopUpRecip { id: "swap".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("source", &coins(2, "btc")), top_up.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), mock_env(), mock_info("reci", &coins(2, "btc")), top_up).unwrap();
+        let top_up = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: String::from("reci"),
+            amount: Uint128::new(30),
+            msg: to_binary(&ReceiveMsg::TopUpRecip { id: "swap".to_string() }).unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info("wbtc", &[]), top_up).unwrap();
+        let details = query_details(deps.as_ref(), "swap".to_string()).unwrap();
+        assert_eq!(details.native_balance, coins(100, "atom"));
+        assert!(details.cw20_balance.is_empty());
+        assert_eq!(details.recip_native_balance, coins(2, "btc"));
+        assert_eq!(
+            details.recip_cw20_balance,
+            vec![Cw20Coin { address: "wbtc".to_string(), amount: Uint128::new(30) }]
+        );
+
+        // a refund returns each leg to the party that funded it
+        let refund = ExecuteMsg::Refund { id: "swap".to_string() };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), refund).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(100, "atom") }),
+                SubMsg::new(BankMsg::Send { to_address: "reci".to_string(), amount: coins(2, "btc") }),
+                SubMsg::new(WasmMsg::Execute {
+                    contract_addr: "wbtc".to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::Transfer { recipient: "reci".to_string(), amount: Uint128::new(30) }).unwrap(),
+                    funds: vec![],
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn approve_sends_recipient_leg_to_source() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let create = CreateMsg {
+            id: "swap".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "swap".to_string(),
+            description: "atom for btc".to_string(),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(100, "atom"));
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+        let accept = ExecuteMsg::AcceptArbitration { id: "swap".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), accept).unwrap();
+
+        let top_up = ExecuteMsg::TopUpRecip { id: "swap".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("reci", &coins(2, "btc")), top_up).unwrap();
+        let details = query_details(deps.as_ref(), "swap".to_string()).unwrap();
+        assert_eq!(details.native_balance, coins(100, "atom"));
+        assert_eq!(details.recip_native_balance, coins(2, "btc"));
+
+        let approve = ExecuteMsg::Approve { id: "swap".to_string() };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), approve).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send {
+                    to_address: "reci".to_string(),
+                    amount: coins(100, "atom"),
+                }),
+                SubMsg::new(BankMsg::Send {
+                    to_address: "source".to_string(),
+                    amount: coins(2, "btc"),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn partial_fills_release_proportional_share() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        // offer 1000 usd for 100 atom, fills of at least 20 atom
+        let create = CreateMsg {
+            id: "big_offer".to_string(),
+            arbiter: String::from("arbi"),
+            title: "otc".to_string(),
+            description: "usd for atom".to_string(),
+            terms: Some(crate::state::Terms {
+                ask: Denom::Native("atom".to_string()),
+                ask_amount: Uint128::new(100),
+                min_fill: Some(Uint128::new(20)),
+            }),
+            ..Default::default()
+        };
+        let info = mock_info("maker", &coins(1000, "usd"));
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+        let accept = ExecuteMsg::AcceptArbitration { id: "big_offer".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), accept).unwrap();
+
+        let take = ExecuteMsg::Take { id: "big_offer".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &coins(10, "atom")), take.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::FillTooSmall { min_fill: Uint128::new(20), received: Uint128::new(10) }
+        );
+
+        // first taker fills 30%
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &coins(30, "atom")), take.clone()).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "alice".to_string(), amount: coins(300, "usd") }),
+                SubMsg::new(BankMsg::Send { to_address: "maker".to_string(), amount: coins(30, "atom") }),
+            ]
+        );
+        let details = query_details(deps.as_ref(), "big_offer".to_string()).unwrap();
+        assert_eq!(details.native_balance, coins(700, "usd"));
+        assert_eq!(details.terms.unwrap().ask_amount, Uint128::new(70));
+
+        // second taker fills the rest, which closes the offer
+        let res = execute(deps.as_mut(), mock_env(), mock_info("bob", &coins(70, "atom")), take).unwrap();
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(BankMsg::Send { to_address: "bob".to_string(), amount: coins(700, "usd") })
+        );
+        assert!(query_details(deps.as_ref(), "big_offer".to_string()).is_err());
+
+        let fills = query_fills(deps.as_ref(), "big_offer".to_string(), None, None).unwrap().fills;
+        assert_eq!(fills.len(), 2);
+        assert_eq!((fills[0].index, fills[0].taker.as_str(), fills[0].paid), (0, "alice", Uint128::new(30)));
+        assert_eq!(fills[0].native_received, coins(300, "usd"));
+        assert_eq!((fills[1].index, fills[1].taker.as_str(), fills[1].paid), (1, "bob", Uint128::new(70)));
+        let fills = query_fills(deps.as_ref(), "big_offer".to_string(), Some(0), None).unwrap().fills;
+        assert_eq!(fills.len(), 1);
+    }
+
+    #[test]
+    fn create_escrow_from_template() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        // escrows from this template expire 30 days after creation and pay 2.5% to the arbiter
+        let template = TemplateMsg {
+            id: "payroll".to_string(),
+            arbiter: String::from("arbi"),
+            cw20_wishlist: Some(vec![String::from("token")]),
+            expires_in: Some(Duration::Time(30 * 24 * 3600)),
+            arbiter_fee: Some(ArbiterFeeMsg::Bps(20_000)),
+        };
+        let info = mock_info("team", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::CreateTemplate(template.clone())).unwrap_err();
+        assert_eq!(err, ContractError::InvalidFee {});
+        let template = TemplateMsg { arbiter_fee: Some(ArbiterFeeMsg::Bps(250)), ..template };
+        execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::CreateTemplate(template.clone())).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::CreateTemplate(template.clone())).unwrap_err();
+        assert_eq!(err, ContractError::TemplateAlreadyInUse {});
+
+        let stored = query_template(deps.as_ref(), "payroll".to_string()).unwrap();
+        assert_eq!(stored.owner, "team");
+        assert_eq!(stored.arbiter, "arbi");
+        assert_eq!(stored.arbiter_fee, Some(ArbiterFee::Bps(250)));
+
+        let create = CreateMsg {
+            id: "june".to_string(),
+            recipient: Some(String::from("worker")),
+            title: "june".to_string(),
+            description: "salary".to_string(),
+            template_id: Some("payroll".to_string()),
+            ..Default::default()
+        };
+        let info = mock_info("team", &coins(100, "usd"));
+        execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap();
+
+        let details = query_details(deps.as_ref(), "june".to_string()).unwrap();
+        assert_eq!(details.arbiter, "arbi");
+        assert_eq!(details.cw20_wishlist, vec!["token".to_string()]);
+        assert_eq!(
+            details.expires,
+            Expiration::AtTime(mock_env().block.time.plus_seconds(30 * 24 * 3600))
+        );
+        assert_eq!(details.arbiter_fee, Some(ArbiterFee::Bps(250)));
+
+        // fields set on the message override the template
+        let fixed_fee = ArbiterFeeMsg::Fixed { native: coins(5, "usd"), cw20: vec![] };
+        let create = CreateMsg {
+            id: "july".to_string(),
+            arbiter: String::from("boss"),
+            cw20_wishlist: Some(vec![]),
+            expires_in: Some(Duration::Height(100)),
+            arbiter_fee: Some(fixed_fee),
+            ..create
+        };
+        execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap();
+        let details = query_details(deps.as_ref(), "july".to_string()).unwrap();
+        assert_eq!(details.arbiter, "boss");
+        assert!(details.cw20_wishlist.is_empty());
+        assert_eq!(details.expires, Expiration::AtHeight(mock_env().block.height + 100));
+        assert_eq!(
+            details.arbiter_fee,
+            Some(ArbiterFee::Fixed(GenericBalance { native: coins(5, "usd"), cw20: vec![] }))
+        );
+
+        let create = CreateMsg {
+            id: "august".to_string(),
+            template_id: Some("missing".to_string()),
+            ..create
+        };
+        execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::CreateEscrow(create)).unwrap_err();
+
+        // only the owner updates or deletes the template
+        let update = TemplateMsg { arbiter: String::from("boss"), arbiter_fee: None, ..template };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("boss", &[]), ExecuteMsg::UpdateTemplate(update.clone())).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::UpdateTemplate(update)).unwrap();
+        let stored = query_template(deps.as_ref(), "payroll".to_string()).unwrap();
+        assert_eq!(stored.owner, "team");
+        assert_eq!(stored.arbiter, "boss");
+        assert_eq!(stored.arbiter_fee, None);
+        let details = query_details(deps.as_ref(), "june".to_string()).unwrap();
+        assert_eq!(details.arbiter, "arbi");
+
+        let delete = ExecuteMsg::DeleteTemplate { id: "payroll".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("boss", &[]), delete.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), mock_env(), info, delete).unwrap();
+        assert!(query_template(deps.as_ref(), "payroll".to_string()).is_err());
+    }
+
+    #[test]
+    fn expirations_are_checked_at_creation_and_queryable() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let env = mock_env();
+        let info = mock_info("source", &coins(100, "tokens"));
+        let create = CreateMsg {
+            id: "deadline".to_string(),
+            arbiter: String::from("arbi"),
+            title: "deadline".to_string(),
+            description: "expires".to_string(),
+            expires: Some(Expiration::AtHeight(env.block.height)),
+            ..Default::default()
+        };
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap_err();
+        assert_eq!(err, ContractError::AlreadyExpired {});
+
+        let both = CreateMsg {
+            expires: Some(Expiration::AtHeight(env.block.height + 10)),
+            expires_in: Some(Duration::Height(10)),
+            ..create.clone()
+        };
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(both)).unwrap_err();
+        assert_eq!(err, ContractError::InvalidExpiration {});
+
+        let create = CreateMsg {
+            expires: None,
+            expires_in: Some(Duration::Time(3600)),
+            ..create
+        };
+        execute(deps.as_mut(), env.clone(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+
+        let mut later = env.clone();
+        later.block.time = later.block.time.plus_seconds(600);
+        let res = query_time_remaining(deps.as_ref(), later.clone(), "deadline".to_string()).unwrap();
+        assert_eq!(res.expires, Expiration::AtTime(env.block.time.plus_seconds(3600)));
+        assert_eq!(res.remaining, Some(Duration::Time(3000)));
+        assert!(!res.expired);
+
+        later.block.time = later.block.time.plus_seconds(3000);
+        let res = query_time_remaining(deps.as_ref(), later, "deadline".to_string()).unwrap();
+        assert_eq!(res.remaining, Some(Duration::Time(0)));
+        assert!(res.expired);
+    }
+
+    #[test]
+    fn migrate_converts_legacy_expirations() {
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+
+        // store escrows the way they were serialized with end_height and end_time
+        let escrow = Escrow {
+            arbiter: Addr::unchecked("arbi"),
+            recipient: None,
+            source: Addr::unchecked("source"),
+            title: "legacy".to_string(),
+            description: "legacy".to_string(),
+            expires: Expiration::Never {},
+            balance: GenericBalance {
+                native: coins(100, "tokens"),
+                cw20: vec![],
+            },
+            recip_balance: GenericBalance::default(),
+            cw20_wishlist: vec![],
+            condition: None,
+            stream: None,
+            payout_msg: None,
+            terms: None,
+            grace_period: None,
+            arbiter_extends: false,
+            pending_extension: None,
+            funding_deadline: None,
+            arbiter_fee: None,
+            arbitration: Arbitration::Accepted,
+            acceptance_deadline: None,
+            created_at: None,
+            pending_arbiter: None,
+            backup_arbiter: None,
+            authorization: Authorization::Sender,
+            decision_nonce: 0,
+            assignment_needs_consent: false,
+            pending_assignment: None,
+            recipient_receipt: false,
+            source_receipt: false,
+            receipt_collection: None,
+            recipient_token_id: None,
+            source_token_id: None,
+            open_to_applications: false,
+            application_deposit: None,
+            prize_pool: false,
+            winners: vec![],
+            tags: vec![],
+            external_id: None,
+            content_hash: None,
+            memos: vec![],
+        };
+        let legacy = |fields: String| {
+            let json = String::from_utf8(to_binary(&escrow).unwrap().0).unwrap();
+            json.replace("\"expires\":{\"never\":{}}", &fields).into_bytes()
+        };
+        let past = env.block.height - 1;
+        let soon = env.block.time.seconds() + 10;
+        deps.storage.set(
+            &ESCROWS.key("past"),
+            &legacy(format!("\"end_height\":{},\"end_time\":null", past)),
+        );
+        deps.storage.set(
+            &ESCROWS.key("soon"),
+            &legacy(format!("\"end_height\":null,\"end_time\":{}", soon)),
+        );
+        deps.storage.set(&ESCROWS.key("never"), &legacy("\"end_height\":null".to_string()));
+        // the height bound already reached wins
+        deps.storage.set(
+            &ESCROWS.key("reached"),
+            &legacy(format!("\"end_height\":{},\"end_time\":{}", past, soon)),
+        );
+
+        // with both bounds ahead neither can be dropped, the migration waits for the
+        // escrow to be settled
+        deps.storage.set(
+            &ESCROWS.key("both"),
+            &legacy(format!("\"end_height\":{},\"end_time\":{}", env.block.height + 5, soon)),
+        );
+        let err = migrate(deps.as_mut(), env.clone(), MigrateMsg { admin: None }).unwrap_err();
+        assert_eq!(err, ContractError::AmbiguousExpiry { ids: "both".to_string() });
+        deps.storage.remove(&ESCROWS.key("both"));
+
+        let res = migrate(deps.as_mut(), env.clone(), MigrateMsg { admin: None }).unwrap();
+        assert_eq!(res.attributes[1], attr("migrated", "3"));
+        let reached = ESCROWS.load(&deps.storage, "reached").unwrap();
+        assert!(reached.is_expired(&env));
+
+        let past = ESCROWS.load(&deps.storage, "past").unwrap();
+        assert_eq!(past.expires, Expiration::AtHeight(env.block.height));
+        assert!(past.is_expired(&env));
+        let soon = ESCROWS.load(&deps.storage, "soon").unwrap();
+        assert!(!soon.is_expired(&env));
+        let res = query_time_remaining(deps.as_ref(), env.clone(), "soon".to_string()).unwrap();
+        assert_eq!(res.remaining, Some(Duration::Time(10)));
+        let never = ESCROWS.load(&deps.storage, "never").unwrap();
+        assert_eq!(never.expires, Expiration::Never {});
+        assert_eq!(never.balance.native, coins(100, "tokens"));
+
+        // migrated escrows are indexed for sweeping
+        let sweep = ExecuteMsg::SweepExpired { limit: None };
+        let res = execute(deps.as_mut(), env, mock_info("keeper", &[]), sweep).unwrap();
+        assert_eq!(res.attributes[1], attr("id", "past"));
+        assert_eq!(res.attributes[2], attr("id", "reached"));
+        assert_eq!(res.attributes[3], attr("swept", "2"));
+    }
+
+    #[test]
+    fn extend_needs_both_parties_and_grace_keeps_refund_to_arbiter() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let env = mock_env();
+        let create = CreateMsg {
+            id: "slipping".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "slipping".to_string(),
+            description: "deal that slips".to_string(),
+            expires: Some(Expiration::AtHeight(env.block.height + 10)),
+            grace_period: Some(Duration::Height(5)),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(100, "tokens"));
+        execute(deps.as_mut(), env.clone(), info, ExecuteMsg::CreateEscrow(create.clone())).unwrap();
+
+        let extend = |expires: u64| ExecuteMsg::Extend {
+            id: "slipping".to_string(),
+            new_expiration: Expiration::AtHeight(expires),
+        };
+        let later = env.block.height + 20;
+        let err = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), extend(later)).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let err = execute(deps.as_mut(), env.clone(), mock_info("source", &[]), extend(env.block.height + 5)).unwrap_err();
+        assert_eq!(err, ContractError::InvalidExtension {});
+
+        // the source proposes, a different counter proposal replaces it
+        let res = execute(deps.as_mut(), env.clone(), mock_info("source", &[]), extend(later)).unwrap();
+        assert_eq!(res.attributes[2], attr("result", "proposed"));
+        let res = execute(deps.as_mut(), env.clone(), mock_info("reci", &[]), extend(later + 1)).unwrap();
+        assert_eq!(res.attributes[2], attr("result", "proposed"));
+        let res = execute(deps.as_mut(), env.clone(), mock_info("source", &[]), extend(later + 1)).unwrap();
+        assert_eq!(res.attributes[2], attr("result", "extended"));
+        let details = query_details(deps.as_ref(), "slipping".to_string()).unwrap();
+        assert_eq!(details.expires, Expiration::AtHeight(later + 1));
+        assert_eq!(details.pending_extension, None);
+
+        // expired but within the grace period, only the arbiter can refund
+        let mut expired = env.clone();
+        expired.block.height = later + 3;
+        let refund = ExecuteMsg::Refund { id: "slipping".to_string() };
+        let err = execute(deps.as_mut(), expired.clone(), mock_info("anybody", &[]), refund.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        expired.block.height = later + 6;
+        let res = execute(deps.as_mut(), expired, mock_info("anybody", &[]), refund).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(100, "tokens") })]
+        );
+
+        // the arbiter alone extends when configured so
+        let create = CreateMsg {
+            id: "slipping".to_string(),
+            arbiter_extends: true,
+            ..create
+        };
+        let info = mock_info("source", &coins(100, "tokens"));
+        execute(deps.as_mut(), env.clone(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+        let err = execute(deps.as_mut(), env.clone(), mock_info("source", &[]), extend(later)).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let res = execute(deps.as_mut(), env, mock_info("arbi", &[]), extend(later)).unwrap();
+        assert_eq!(res.attributes[2], attr("result", "extended"));
+    }
+
+    #[test]
+    fn funding_deadline_and_settlement_deadline_phases() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let env = mock_env();
+        let create = CreateMsg {
+            id: "unfunded".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "swap".to_string(),
+            description: "two legs".to_string(),
+            funding_deadline: Some(Expiration::AtHeight(env.block.height + 10)),
+            expires: Some(Expiration::AtHeight(env.block.height + 100)),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(100, "tokens"));
+        execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap();
+        let funded = CreateMsg { id: "funded".to_string(), ..create.clone() };
+        execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(funded)).unwrap();
+        let late = CreateMsg {
+            id: "late".to_string(),
+            funding_deadline: Some(Expiration::AtHeight(env.block.height + 200)),
+            ..create
+        };
+        let err = execute(deps.as_mut(), env.clone(), info, ExecuteMsg::CreateEscrow(late)).unwrap_err();
+        assert_eq!(err, ContractError::InvalidExpiration {});
+
+        for id in ["unfunded", "funded"] {
+            let accept = ExecuteMsg::AcceptArbitration { id: id.to_string() };
+            execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), accept).unwrap();
+        }
+
+        let phase = |deps: Deps, env: &Env, id: &str| query_phase(deps, env.clone(), id.to_string()).unwrap().phase;
+        assert_eq!(phase(deps.as_ref(), &env, "unfunded"), Phase::Funding);
+        let top_up = |id: &str| ExecuteMsg::TopUpRecip { id: id.to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("reci", &coins(50, "usd")), top_up("funded")).unwrap();
+        assert_eq!(phase(deps.as_ref(), &env, "funded"), Phase::Settlement);
+
+        // the unfunded escrow can no longer be funded nor approved, anyone refunds the source
+        let mut after_funding = env.clone();
+        after_funding.block.height += 11;
+        assert_eq!(phase(deps.as_ref(), &after_funding, "unfunded"), Phase::Unfunded);
+        let err = execute(deps.as_mut(), after_funding.clone(), mock_info("reci", &coins(50, "usd")), top_up("unfunded")).unwrap_err();
+        assert_eq!(err, ContractError::FundingClosed {});
+        let approve = ExecuteMsg::Approve { id: "unfunded".to_string() };
+        let err = execute(deps.as_mut(), after_funding.clone(), mock_info("arbi", &[]), approve).unwrap_err();
+        assert_eq!(err, ContractError::Expired {});
+        let refund = |id: &str| ExecuteMsg::Refund { id: id.to_string() };
+        let res = execute(deps.as_mut(), after_funding.clone(), mock_info("anybody", &[]), refund("unfunded")).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(100, "tokens") })]
+        );
+
+        // the funded escrow waits for the arbiter until the settlement deadline
+        assert_eq!(phase(deps.as_ref(), &after_funding, "funded"), Phase::Settlement);
+        let err = execute(deps.as_mut(), after_funding.clone(), mock_info("anybody", &[]), refund("funded")).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let mut after_settlement = env;
+        after_settlement.block.height += 100;
+        assert_eq!(phase(deps.as_ref(), &after_settlement, "funded"), Phase::Expired);
+        let res = execute(deps.as_mut(), after_settlement, mock_info("anybody", &[]), refund("funded")).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(100, "tokens") }),
+                SubMsg::new(BankMsg::Send { to_address: "reci".to_string(), amount: coins(50, "usd") }),
+            ]
+        );
+    }
+
+    #[test]
+    fn arbiter_fee_is_paid_on_approve_and_refund() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let create = CreateMsg {
+            id: "paid".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "paid".to_string(),
+            description: "professional arbiter".to_string(),
+            arbiter_fee: Some(crate::msg::ArbiterFeeMsg::Bps(20_000)),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(1000, "tokens"));
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap_err();
+        assert_eq!(err, ContractError::InvalidFee {});
+
+        // 2.5% of the balance on approval
+        let create = CreateMsg {
+            arbiter_fee: Some(crate::msg::ArbiterFeeMsg::Bps(250)),
+            ..create
+        };
+        execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap();
+        let approve = ExecuteMsg::Approve { id: "paid".to_string() };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), approve).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "arbi".to_string(), amount: coins(25, "tokens") }),
+                SubMsg::new(BankMsg::Send { to_address: "reci".to_string(), amount: coins(975, "tokens") }),
+            ]
+        );
+
+        // a fixed fee on refund
+        let create = CreateMsg {
+            arbiter_fee: Some(crate::msg::ArbiterFeeMsg::Fixed { native: coins(10, "tokens"), cw20: vec![] }),
+            ..create
+        };
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+        let refund = ExecuteMsg::Refund { id: "paid".to_string() };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), refund).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "arbi".to_string(), amount: coins(10, "tokens") }),
+                SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(990, "tokens") }),
+            ]
+        );
+    }
+
+    #[test]
+    fn arbiter_accepts_or_declines_before_recipient_funds() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract, arbiters have 100 blocks to accept
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("admin"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        let update = ExecuteMsg::UpdateConfig {
+            keeper_bounty: None,
+            bounty_min_age: None,
+            acceptance_window: Some(Duration::Height(100)),
+            require_registered_arbiter: None,
+            metadata_limits: None,
+            receipt_collection: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), update).unwrap();
+
+        let env = mock_env();
+        let create = CreateMsg {
+            id: "handshake".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "handshake".to_string(),
+            description: "arbiter must accept".to_string(),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(100, "tokens"));
+        execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap();
+        let details = query_details(deps.as_ref(), "handshake".to_string()).unwrap();
+        assert_eq!(details.arbitration, Arbitration::Pending);
+        assert_eq!(details.acceptance_deadline, Some(Expiration::AtHeight(env.block.height + 100)));
+        assert_eq!(query_phase(deps.as_ref(), env.clone(), "handshake".to_string()).unwrap().phase, Phase::Arbitration);
+
+        let top_up = ExecuteMsg::TopUpRecip { id: "handshake".to_string() };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("reci", &coins(5, "usd")), top_up.clone()).unwrap_err();
+        assert_eq!(err, ContractError::ArbitrationPending {});
+
+        let accept = ExecuteMsg::AcceptArbitration { id: "handshake".to_string() };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("reci", &[]), accept.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), accept.clone()).unwrap();
+        let err = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), accept).unwrap_err();
+        assert_eq!(err, ContractError::ArbitrationAccepted {});
+        execute(deps.as_mut(), env.clone(), mock_info("reci", &coins(5, "usd")), top_up).unwrap();
+
+        // declining refunds the source right away
+        let create = CreateMsg { id: "declined".to_string(), ..create.clone() };
+        execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap();
+        let decline = ExecuteMsg::DeclineArbitration { id: "declined".to_string() };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), decline).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(100, "tokens") })]
+        );
+
+        // an arbiter that never answers lets anyone refund after the window
+        let create = CreateMsg { id: "ignored".to_string(), ..create };
+        execute(deps.as_mut(), env.clone(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+        let refund = ExecuteMsg::Refund { id: "ignored".to_string() };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("anybody", &[]), refund.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let mut later = env;
+        later.block.height += 100;
+        assert_eq!(query_phase(deps.as_ref(), later.clone(), "ignored".to_string()).unwrap().phase, Phase::Unaccepted);
+        let res = execute(deps.as_mut(), later, mock_info("anybody", &[]), refund).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(100, "tokens") })]
+        );
+    }
+
+    #[test]
+    fn arbiter_registry_and_stats() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract that only accepts registered arbiters
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("admin"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        let update = ExecuteMsg::UpdateConfig {
+            keeper_bounty: None,
+            bounty_min_age: None,
+            acceptance_window: None,
+            require_registered_arbiter: Some(true),
+            metadata_limits: None,
+            receipt_collection: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), update).unwrap();
+
+        let env = mock_env();
+        let create = CreateMsg {
+            id: "first".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "first".to_string(),
+            description: "registered arbiter".to_string(),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(100, "tokens"));
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap_err();
+        assert_eq!(err, ContractError::ArbiterNotRegistered {});
+
+        let register = ExecuteMsg::RegisterArbiter {
+            name: "Arbi".to_string(),
+            fee_schedule: Some(ArbiterFeeMsg::Bps(100)),
+            supported_tokens: vec![Denom::Native("tokens".to_string())],
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), register).unwrap();
+        let arbiters = query_arbiters(deps.as_ref(), None, None).unwrap();
+        assert_eq!(
+            arbiters.arbiters,
+            vec![ArbiterInfo {
+                address: "arbi".to_string(),
+                profile: ArbiterProfile {
+                    name: "Arbi".to_string(),
+                    fee_schedule: Some(ArbiterFee::Bps(100)),
+                    supported_tokens: vec![Denom::Native("tokens".to_string())],
+                },
+            }]
+        );
+        assert!(query_arbiters(deps.as_ref(), Some("arbi".to_string()), None).unwrap().arbiters.is_empty());
+
+        // accepting counts, approving 100 seconds later resolves it
+        execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap();
+        let accept = ExecuteMsg::AcceptArbitration { id: "first".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), accept).unwrap();
+        let mut later = env.clone();
+        later.block.time = later.block.time.plus_seconds(100);
+        let approve = ExecuteMsg::Approve { id: "first".to_string() };
+        execute(deps.as_mut(), later, mock_info("arbi", &[]), approve).unwrap();
+
+        // refunding a pending escrow implicitly accepts it and counts a dispute
+        let second = CreateMsg { id: "second".to_string(), ..create.clone() };
+        execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(second)).unwrap();
+        let mut later = env.clone();
+        later.block.time = later.block.time.plus_seconds(300);
+        let refund = ExecuteMsg::Refund { id: "second".to_string() };
+        execute(deps.as_mut(), later, mock_info("arbi", &[]), refund).unwrap();
+
+        // approving while the parties disagree on an extension counts a dispute
+        let contested = CreateMsg {
+            id: "contested".to_string(),
+            expires: Some(Expiration::AtHeight(env.block.height + 100)),
+            ..create.clone()
+        };
+        execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(contested)).unwrap();
+        let extend = ExecuteMsg::Extend {
+            id: "contested".to_string(),
+            new_expiration: Expiration::AtHeight(env.block.height + 200),
+        };
+        execute(deps.as_mut(), env.clone(), info.clone(), extend).unwrap();
+        let mut later = env.clone();
+        later.block.time = later.block.time.plus_seconds(200);
+        let approve = ExecuteMsg::Approve { id: "contested".to_string() };
+        execute(deps.as_mut(), later, mock_info("arbi", &[]), approve).unwrap();
+
+        // declining counts a dispute as well
+        let declined = CreateMsg { id: "declined".to_string(), ..create.clone() };
+        execute(deps.as_mut(), env.clone(), info, ExecuteMsg::CreateEscrow(declined)).unwrap();
+        let decline = ExecuteMsg::DeclineArbitration { id: "declined".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), decline).unwrap();
+
+        // escrows of the arbiter itself do not count
+        let own = CreateMsg { id: "own".to_string(), ..create };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &coins(100, "tokens")), ExecuteMsg::CreateEscrow(own)).unwrap();
+        let approve = ExecuteMsg::Approve { id: "own".to_string() };
+        execute(deps.as_mut(), env, mock_info("arbi", &[]), approve).unwrap();
+
+        let stats = query_arbiter_stats(deps.as_ref(), "arbi".to_string()).unwrap();
+        assert_eq!(
+            stats,
+            ArbiterStatsResponse {
+                address: "arbi".to_string(),
+                approvals: 2,
+                refunds: 1,
+                accepted: 3,
+                disputes: 3,
+                avg_resolution_time: Some(200),
+            }
+        );
+        let stats = query_arbiter_stats(deps.as_ref(), "nobody".to_string()).unwrap();
+        assert_eq!(stats.avg_resolution_time, None);
+    }
+
+    #[test]
+    fn arbiter_transfer_and_backup_take_over() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let env = mock_env();
+        let create = CreateMsg {
+            id: "rotate".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "rotate".to_string(),
+            description: "arbiter changes hands".to_string(),
+            backup_arbiter: Some(BackupArbiterMsg {
+                address: String::from("backup"),
+                inactivity_timeout: Duration::Height(50),
+            }),
+            ..Default::default()
+        };
+        let info = mock_info("arbi", &coins(100, "tokens"));
+        execute(deps.as_mut(), env.clone(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+
+        // only the arbiter transfers and only the proposed arbiter accepts
+        let transfer = ExecuteMsg::TransferArbiter { id: "rotate".to_string(), new_arbiter: String::from("next") };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("reci", &[]), transfer.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let accept = ExecuteMsg::AcceptArbiterTransfer { id: "rotate".to_string() };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("next", &[]), accept.clone()).unwrap_err();
+        assert_eq!(err, ContractError::NoPendingTransfer {});
+        let res = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), transfer).unwrap();
+        assert_eq!(
+            res.events,
+            vec![Event::new("escrow_updated")
+                .add_attribute("id", "rotate")
+                .add_attribute("actor", "arbi")
+                .add_attribute("from", "arbi")
+                .add_attribute("to", "next")
+                .add_attribute("change", "arbiter_transfer_proposed")]
+        );
+        let err = execute(deps.as_mut(), env.clone(), mock_info("other", &[]), accept.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let mut later = env.clone();
+        later.block.height += 10;
+        let res = execute(deps.as_mut(), later.clone(), mock_info("next", &[]), accept).unwrap();
+        assert_eq!(
+            res.events,
+            vec![Event::new("escrow_updated")
+                .add_attribute("id", "rotate")
+                .add_attribute("actor", "next")
+                .add_attribute("from", "arbi")
+                .add_attribute("to", "next")
+                .add_attribute("change", "arbiter_transferred")]
+        );
+        let details = query_details(deps.as_ref(), "rotate".to_string()).unwrap();
+        assert_eq!(details.arbiter, "next");
+        assert_eq!(details.pending_arbiter, None);
+        assert_eq!(details.backup_arbiter.unwrap().takes_over_at, Expiration::AtHeight(later.block.height + 50));
+
+        // the backup waits for the timeout since the last action of the arbiter
+        let take_over = ExecuteMsg::TakeOverArbiter { id: "rotate".to_string() };
+        let mut idle = later;
+        idle.block.height += 49;
+        let err = execute(deps.as_mut(), idle.clone(), mock_info("backup", &[]), take_over.clone()).unwrap_err();
+        assert_eq!(err, ContractError::ArbiterStillActive {});
+        idle.block.height += 1;
+        let err = execute(deps.as_mut(), idle.clone(), mock_info("next", &[]), take_over.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let res = execute(deps.as_mut(), idle.clone(), mock_info("backup", &[]), take_over.clone()).unwrap();
+        assert_eq!(
+            res.events,
+            vec![Event::new("escrow_updated")
+                .add_attribute("id", "rotate")
+                .add_attribute("actor", "backup")
+                .add_attribute("from", "next")
+                .add_attribute("to", "backup")
+                .add_attribute("change", "arbiter_taken_over")]
+        );
+        let details = query_details(deps.as_ref(), "rotate".to_string()).unwrap();
+        assert_eq!(details.arbiter, "backup");
+        assert_eq!(details.backup_arbiter, None);
+        let err = execute(deps.as_mut(), idle, mock_info("backup", &[]), take_over).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let history = query_history(deps.as_ref(), "rotate".to_string(), None, None).unwrap();
+        let changes: Vec<_> = history.entries.into_iter().map(|entry| (entry.index, entry.actor, entry.change)).collect();
+        assert_eq!(
+            changes,
+            vec![
+                (0, "arbi".to_string(), Change::ArbiterTransferProposed { to: Addr::unchecked("next") }),
+                (
+                    1,
+                    "next".to_string(),
+                    Change::ArbiterTransferred { from: Addr::unchecked("arbi"), to: Addr::unchecked("next") }
+                ),
+                (
+                    2,
+                    "backup".to_string(),
+                    Change::ArbiterTakenOver { from: Addr::unchecked("next"), to: Addr::unchecked("backup") }
+                ),
+            ]
+        );
+        let page = query_history(deps.as_ref(), "rotate".to_string(), Some(1), None).unwrap();
+        assert_eq!(page.entries.len(), 1);
+    }
+
+    #[test]
+    fn query_authorization_with_an_account_arbiter_authorizes_nothing() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        // the arbiter is an account, the escrow cannot query it
+        let create = CreateMsg {
+            id: "queried".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "some_title".to_string(),
+            description: "some_description".to_string(),
+            authorization: Some(Authorization::Query),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(100, "tokens"));
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+
+        let refund = ExecuteMsg::Refund { id: "queried".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("reci", &[]), refund.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        // the arbiter itself still decides, which renews the nonce
+        let before = query_details(deps.as_ref(), "queried".to_string()).unwrap().decision_nonce;
+        let set = ExecuteMsg::SetRecipient { id: "queried".to_string(), recipient: String::from("other") };
+        execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), set).unwrap();
+        let after = query_details(deps.as_ref(), "queried".to_string()).unwrap().decision_nonce;
+        assert!(after > before);
+        execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), refund).unwrap();
+    }
+
+    #[test]
+    fn recipient_assigns_claim_with_optional_consent() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let env = mock_env();
+        let create = CreateMsg {
+            id: "invoice".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "invoice".to_string(),
+            description: "claim can be sold".to_string(),
+            ..Default::default()
+        };
+        let info = mock_info("arbi", &coins(100, "tokens"));
+        execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap();
+
+        // only the current recipient assigns, the assignee is paid on approval
+        let assign = ExecuteMsg::AssignClaim { id: "invoice".to_string(), new_recipient: String::from("factor") };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), assign.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let res = execute(deps.as_mut(), env.clone(), mock_info("reci", &[]), assign.clone()).unwrap();
+        assert_eq!(
+            res.events,
+            vec![Event::new("escrow_updated")
+                .add_attribute("id", "invoice")
+                .add_attribute("actor", "reci")
+                .add_attribute("from", "reci")
+                .add_attribute("to", "factor")
+                .add_attribute("change", "claim_assigned")]
+        );
+        let err = execute(deps.as_mut(), env.clone(), mock_info("reci", &[]), assign).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let history = query_history(deps.as_ref(), "invoice".to_string(), None, None).unwrap();
+        assert_eq!(
+            history.entries[0].change,
+            Change::ClaimAssigned { from: Addr::unchecked("reci"), to: Addr::unchecked("factor") }
+        );
+        let approve = ExecuteMsg::Approve { id: "invoice".to_string() };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), approve).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "factor".to_string(), amount: coins(100, "tokens") })]
+        );
+
+        // with consent the assignment waits for the arbiter
+        let create = CreateMsg {
+            id: "consented".to_string(),
+            assignment_needs_consent: true,
+            ..create
+        };
+        execute(deps.as_mut(), env.clone(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+        let consent = ExecuteMsg::ConsentAssignment { id: "consented".to_string() };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), consent.clone()).unwrap_err();
+        assert_eq!(err, ContractError::NoPendingAssignment {});
+        let assign = ExecuteMsg::AssignClaim { id: "consented".to_string(), new_recipient: String::from("factor") };
+        execute(deps.as_mut(), env.clone(), mock_info("reci", &[]), assign).unwrap();
+        let details = query_details(deps.as_ref(), "consented".to_string()).unwrap();
+        assert_eq!(details.recipient, Some("reci".to_string()));
+        assert_eq!(details.pending_assignment, Some("factor".to_string()));
+
+        let err = execute(deps.as_mut(), env.clone(), mock_info("reci", &[]), consent.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let res = execute(deps.as_mut(), env, mock_info("arbi", &[]), consent).unwrap();
+        assert_eq!(
+            res.events,
+            vec![Event::new("escrow_updated")
+                .add_attribute("id", "consented")
+                .add_attribute("actor", "arbi")
+                .add_attribute("from", "reci")
+                .add_attribute("to", "factor")
+                .add_attribute("change", "claim_assigned")]
+        );
+        let details = query_details(deps.as_ref(), "consented".to_string()).unwrap();
+        assert_eq!(details.recipient, Some("factor".to_string()));
+        assert_eq!(details.pending_assignment, None);
+    }
+
+    #[test]
+    fn changing_the_recipient_drops_its_pending_assignment() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let env = mock_env();
+        let create = CreateMsg {
+            id: "invoice".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("rec_one")),
+            title: "invoice".to_string(),
+            description: "claim can be sold".to_string(),
+            assignment_needs_consent: true,
+            ..Default::default()
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &coins(100, "tokens")), ExecuteMsg::CreateEscrow(create)).unwrap();
+
+        // rec_one proposes to assign its claim, then the arbiter replaces it by rec_two
+        let assign = ExecuteMsg::AssignClaim { id: "invoice".to_string(), new_recipient: String::from("buyer") };
+        execute(deps.as_mut(), env.clone(), mock_info("rec_one", &[]), assign).unwrap();
+        let set = ExecuteMsg::SetRecipient { id: "invoice".to_string(), recipient: String::from("rec_two") };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), set).unwrap();
+        let details = query_details(deps.as_ref(), "invoice".to_string()).unwrap();
+        assert_eq!(details.recipient, Some("rec_two".to_string()));
+        assert_eq!(details.pending_assignment, None);
+
+        // the consent no longer hands the claim of rec_two to the buyer
+        let consent = ExecuteMsg::ConsentAssignment { id: "invoice".to_string() };
+        let err = execute(deps.as_mut(), env, mock_info("arbi", &[]), consent).unwrap_err();
+        assert_eq!(err, ContractError::NoPendingAssignment {});
+        let details = query_details(deps.as_ref(), "invoice".to_string()).unwrap();
+        assert_eq!(details.recipient, Some("rec_two".to_string()));
+    }
+
+    #[test]
+    fn receipts_need_a_collection() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let create = CreateMsg {
+            id: "tokenized".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "tokenized".to_string(),
+            description: "positions are nfts".to_string(),
+            recipient_receipt: true,
+            ..Default::default()
+        };
+        let info = mock_info("arbi", &coins(100, "tokens"));
+        let consent = CreateMsg { assignment_needs_consent: true, ..create.clone() };
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::CreateEscrow(consent)).unwrap_err();
+        assert_eq!(err, ContractError::ConsentWithReceipt {});
+        let err = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::CreateEscrow(create)).unwrap_err();
+        assert_eq!(err, ContractError::NoReceiptCollection {});
+    }
+
+    #[test]
+    fn applicants_apply_and_one_is_selected() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let env = mock_env();
+        let create = CreateMsg {
+            id: "bounty".to_string(),
+            arbiter: String::from("arbi"),
+            title: "bounty".to_string(),
+            description: "pick an applicant".to_string(),
+            open_to_applications: true,
+            application_deposit: Some(coin(5, "stake")),
+            ..Default::default()
+        };
+        let info = mock_info("source", &coins(100, "tokens"));
+        execute(deps.as_mut(), env.clone(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+
+        let apply = ExecuteMsg::Apply { id: "bounty".to_string(), memo: "I can do it".to_string() };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &coins(4, "stake")), apply.clone()).unwrap_err();
+        assert_eq!(err, ContractError::WrongDeposit {});
+        let long = ExecuteMsg::Apply { id: "bounty".to_string(), memo: "a".repeat(513) };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &coins(5, "stake")), long).unwrap_err();
+        assert_eq!(err, ContractError::ApplicationMemoTooLong { max: 512 });
+        for applicant in ["alice", "bob", "carol"] {
+            execute(deps.as_mut(), env.clone(), mock_info(applicant, &coins(5, "stake")), apply.clone()).unwrap();
+        }
+        let err = execute(deps.as_mut(), env.clone(), mock_info("bob", &coins(5, "stake")), apply.clone()).unwrap_err();
+        assert_eq!(err, ContractError::AlreadyApplied {});
+
+        // carol withdraws and gets the deposit back
+        let withdraw = ExecuteMsg::WithdrawApplication { id: "bounty".to_string() };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("carol", &[]), withdraw).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "carol".to_string(), amount: coins(5, "stake") })]
+        );
+
+        let page = query_applications(deps.as_ref(), "bounty".to_string(), None, Some(1)).unwrap();
+        assert_eq!(page.applications.len(), 1);
+        assert_eq!(page.applications[0].applicant, "alice");
+        assert_eq!(page.applications[0].application.memo, "I can do it");
+        let page = query_applications(deps.as_ref(), "bounty".to_string(), Some("alice".to_string()), None).unwrap();
+        let applicants: Vec<_> = page.applications.into_iter().map(|a| a.applicant).collect();
+        assert_eq!(applicants, vec!["bob".to_string()]);
+
+        // only the source or the arbiter selects, and only among applicants
+        let select = ExecuteMsg::SelectRecipient { id: "bounty".to_string(), applicant: String::from("bob") };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), select.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let wrong = ExecuteMsg::SelectRecipient { id: "bounty".to_string(), applicant: String::from("carol") };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("source", &[]), wrong).unwrap_err();
+        assert_eq!(err, ContractError::NotAnApplicant {});
+        let res = execute(deps.as_mut(), env.clone(), mock_info("source", &[]), select.clone()).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "alice".to_string(), amount: coins(5, "stake") }),
+                SubMsg::new(BankMsg::Send { to_address: "bob".to_string(), amount: coins(5, "stake") }),
+            ]
+        );
+        assert_eq!(query_details(deps.as_ref(), "bounty".to_string()).unwrap().recipient, Some("bob".to_string()));
+        assert!(query_applications(deps.as_ref(), "bounty".to_string(), None, None).unwrap().applications.is_empty());
+
+        let err = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), select).unwrap_err();
+        assert_eq!(err, ContractError::NotOpenToApplications {});
+        let err = execute(deps.as_mut(), env, mock_info("dave", &coins(5, "stake")), apply).unwrap_err();
+        assert_eq!(err, ContractError::NotOpenToApplications {});
+    }
+
+    #[test]
+    fn prize_pool_pays_every_winner_its_share() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        // 10% of the pool goes to the arbiter
+        let env = mock_env();
+        let create = CreateMsg {
+            id: "hackathon".to_string(),
+            arbiter: String::from("arbi"),
+            title: "hackathon".to_string(),
+            description: "prizes for the best projects".to_string(),
+            arbiter_fee: Some(ArbiterFeeMsg::Bps(1000)),
+            prize_pool: true,
+            ..Default::default()
+        };
+        let info = mock_info("arbi", &[coin(100, "tokens"), coin(50, "usd")]);
+        execute(deps.as_mut(), env.clone(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+
+        let winner = |address: &str, native: Vec<Coin>| WinnerMsg {
+            address: address.to_string(),
+            native,
+            cw20: vec![],
+        };
+        let set = |winners: Vec<WinnerMsg>| ExecuteMsg::SetWinners { id: "hackathon".to_string(), winners };
+
+        // shares must add up to exactly what is left after the fee
+        let invalid = vec![
+            vec![winner("alice", vec![coin(60, "tokens"), coin(45, "usd")]), winner("bob", vec![coin(20, "tokens")])],
+            vec![winner("alice", vec![coin(60, "tokens")]), winner("bob", vec![coin(30, "tokens")])],
+            vec![winner("alice", vec![coin(90, "tokens"), coin(45, "usd")]), winner("bob", vec![])],
+            vec![winner("alice", vec![coin(60, "tokens"), coin(45, "usd")]), winner("alice", vec![coin(30, "tokens")])],
+        ];
+        for winners in invalid {
+            let err = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), set(winners)).unwrap_err();
+            assert_eq!(err, ContractError::InvalidShares {});
+        }
+        let winners = vec![winner("alice", vec![coin(60, "tokens"), coin(45, "usd")]), winner("bob", vec![coin(30, "tokens")])];
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), set(winners.clone())).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), set(winners)).unwrap();
+        let details = query_details(deps.as_ref(), "hackathon".to_string()).unwrap();
+        assert_eq!(details.winners.len(), 2);
+
+        // a top up after the winners were set leaves part of the pool unassigned
+        let top_up = ExecuteMsg::TopUp { id: "hackathon".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &coins(10, "tokens")), top_up).unwrap();
+        let approve = ExecuteMsg::Approve { id: "hackathon".to_string() };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), approve.clone()).unwrap_err();
+        assert_eq!(err, ContractError::InvalidShares {});
+        let winners = vec![winner("alice", vec![coin(69, "tokens"), coin(45, "usd")]), winner("bob", vec![coin(30, "tokens")])];
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), set(winners)).unwrap();
+
+        let res = execute(deps.as_mut(), env, mock_info("arbi", &[]), approve).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "arbi".to_string(), amount: vec![coin(11, "tokens"), coin(5, "usd")] }),
+                SubMsg::new(BankMsg::Send { to_address: "alice".to_string(), amount: vec![coin(69, "tokens"), coin(45, "usd")] }),
+                SubMsg::new(BankMsg::Send { to_address: "bob".to_string(), amount: coins(30, "tokens") }),
+            ]
+        );
+    }
+
+    #[test]
+    fn events_report_every_leg_of_an_escrow() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let env = mock_env();
+        let addr = |address: &str| Addr::unchecked(address);
+        let create = CreateMsg {
+            id: "deal".to_string(),
+            arbiter: String::from("arbi"),
+            title: "deal".to_string(),
+            description: "events of every leg".to_string(),
+            arbiter_fee: Some(ArbiterFeeMsg::Fixed { native: coins(10, "tokens"), cw20: vec![] }),
+            ..Default::default()
+        };
+        let info = mock_info("source", &[coin(1000, "tokens"), coin(5, "usd")]);
+        let res = execute(deps.as_mut(), env.clone(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+        // indexers see it as wasm-escrow_created
+        assert_eq!(
+            res.events,
+            vec![Event::new("escrow_created")
+                .add_attribute("id", "deal")
+                .add_attribute("actor", "source")
+                .add_attribute("leg", "source")
+                .add_attribute("native", "1000tokens,5usd")]
+        );
+
+        let set = ExecuteMsg::SetRecipient { id: "deal".to_string(), recipient: String::from("reci") };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), set).unwrap();
+        let set_event = EscrowEvent::updated("deal", &addr("arbi"), "recipient_set").to(&addr("reci"));
+        assert_eq!(res.events, vec![set_event.into()]);
+
+        let top_up = ExecuteMsg::TopUp { id: "deal".to_string() };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("arbi", &coins(100, "tokens")), top_up).unwrap();
+        let funded = EscrowEvent::new(EventKind::EscrowFunded, "deal", &addr("arbi"))
+            .leg(Leg::Source)
+            .tokens(&GenericBalance { native: coins(100, "tokens"), cw20: vec![] });
+        assert_eq!(res.events, vec![funded.into()]);
+
+        let accept = ExecuteMsg::AcceptArbitration { id: "deal".to_string() };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), accept).unwrap();
+        let accepted = EscrowEvent::updated("deal", &addr("arbi"), "arbitration_accepted");
+        assert_eq!(res.events, vec![accepted.into()]);
+
+        // the recipient leg is funded with a cw20 token
+        let receive = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: String::from("reci"),
+            amount: Uint128::new(40),
+            msg: to_binary(&ReceiveMsg::TopUpRecip { id: "deal".to_string() }).unwrap(),
+        });
+        let res = execute(deps.as_mut(), env.clone(), mock_info("token", &[]), receive).unwrap();
+        assert_eq!(("action", "top_up_recip"), res.attributes[0]);
+        assert_eq!(
+            res.events,
+            vec![Event::new("escrow_funded")
+                .add_attribute("id", "deal")
+                .add_attribute("actor", "reci")
+                .add_attribute("leg", "recipient")
+                .add_attribute("cw20", "token:40")]
+        );
+
+        // the fee and both legs are reported with their full amounts
+        let approve = ExecuteMsg::Approve { id: "deal".to_string() };
+        let res = execute(deps.as_mut(), env, mock_info("arbi", &[]), approve).unwrap();
+        assert_eq!(
+            res.events,
+            vec![
+                Event::new("escrow_fee_paid")
+                    .add_attribute("id", "deal")
+                    .add_attribute("actor", "arbi")
+                    .add_attribute("leg", "source")
+                    .add_attribute("to", "arbi")
+                    .add_attribute("native", "10tokens"),
+                Event::new("escrow_released")
+                    .add_attribute("id", "deal")
+                    .add_attribute("actor", "arbi")
+                    .add_attribute("leg", "source")
+                    .add_attribute("to", "reci")
+                    .add_attribute("native", "1090tokens,5usd"),
+                Event::new("escrow_released")
+                    .add_attribute("id", "deal")
+                    .add_attribute("actor", "arbi")
+                    .add_attribute("leg", "recipient")
+                    .add_attribute("to", "source")
+                    .add_attribute("cw20", "token:40"),
+            ]
+        );
+    }
+
+    #[test]
+    fn events_report_refunds_of_every_kind() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let mut env = mock_env();
+        let addr = |address: &str| Addr::unchecked(address);
+        let native = |amount: u128, denom: &str| GenericBalance { native: coins(amount, denom), cw20: vec![] };
+        let moved = |kind: EventKind, id: &str, actor: &str, leg: Leg, to: &str, tokens: GenericBalance| -> Event {
+            EscrowEvent::new(kind, id, &addr(actor)).leg(leg).to(&addr(to)).tokens(&tokens).into()
+        };
+
+        // an arbitrated refund pays the fee and returns both legs
+        let create = CreateMsg {
+            id: "refunded".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "refunded".to_string(),
+            description: "arbiter refunds".to_string(),
+            arbiter_fee: Some(ArbiterFeeMsg::Fixed { native: coins(10, "tokens"), cw20: vec![] }),
+            ..Default::default()
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("source", &coins(500, "tokens")), ExecuteMsg::CreateEscrow(create)).unwrap();
+        let accept = ExecuteMsg::AcceptArbitration { id: "refunded".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), accept).unwrap();
+        let top_up = ExecuteMsg::TopUpRecip { id: "refunded".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("reci", &coins(20, "usd")), top_up).unwrap();
+        let refund = ExecuteMsg::Refund { id: "refunded".to_string() };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), refund).unwrap();
+        assert_eq!(
+            res.events,
+            vec![
+                moved(EventKind::EscrowFeePaid, "refunded", "arbi", Leg::Source, "arbi", native(10, "tokens")),
+                moved(EventKind::EscrowRefunded, "refunded", "arbi", Leg::Source, "source", native(490, "tokens")),
+                moved(EventKind::EscrowRefunded, "refunded", "arbi", Leg::Recipient, "reci", native(20, "usd")),
+            ]
+        );
+
+        // declining refunds without a fee
+        let create = CreateMsg {
+            id: "declined".to_string(),
+            arbiter: String::from("arbi"),
+            title: "declined".to_string(),
+            description: "arbiter declines".to_string(),
+            ..Default::default()
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("source", &coins(300, "tokens")), ExecuteMsg::CreateEscrow(create)).unwrap();
+        let decline = ExecuteMsg::DeclineArbitration { id: "declined".to_string() };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), decline).unwrap();
+        assert_eq!(
+            res.events,
+            vec![
+                EscrowEvent::updated("declined", &addr("arbi"), "arbitration_declined").into(),
+                moved(EventKind::EscrowRefunded, "declined", "arbi", Leg::Source, "source", native(300, "tokens")),
+            ]
+        );
+
+        // a sweep reports the keeper as the actor
+        let create = CreateMsg {
+            id: "stale".to_string(),
+            arbiter: String::from("arbi"),
+            title: "stale".to_string(),
+            description: "left to expire".to_string(),
+            expires: Some(Expiration::AtHeight(env.block.height + 10)),
+            ..Default::default()
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("source", &coins(200, "tokens")), ExecuteMsg::CreateEscrow(create)).unwrap();
+        env.block.height += 11;
+        let sweep = ExecuteMsg::SweepExpired { limit: None };
+        let res = execute(deps.as_mut(), env, mock_info("keeper", &[]), sweep).unwrap();
+        assert_eq!(
+            res.events,
+            vec![moved(EventKind::EscrowRefunded, "stale", "keeper", Leg::Source, "source", native(200, "tokens"))]
+        );
+    }
+
+    #[test]
+    fn events_report_streams_offers_and_updates() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let mut env = mock_env();
+        let start = env.block.height;
+        let addr = |address: &str| Addr::unchecked(address);
+        let updated = |id: &str, actor: &str, change: &str| -> Event {
+            EscrowEvent::updated(id, &addr(actor), change).into()
+        };
+
+        // a tick releases the accrued part of the source leg
+        let create = CreateMsg {
+            id: "payroll".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("contributor")),
+            title: "payroll".to_string(),
+            description: "monthly contributor payment".to_string(),
+            stream: Some(crate::msg::StreamMsg {
+                native_amount: coins(100, "tokens"),
+                cw20_amount: vec![],
+                interval: Duration::Height(10),
+            }),
+            ..Default::default()
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("source", &coins(350, "tokens")), ExecuteMsg::CreateEscrow(create)).unwrap();
+        let accept = ExecuteMsg::AcceptArbitration { id: "payroll".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), accept).unwrap();
+        env.block.height = start + 25;
+        let tick = ExecuteMsg::Tick { id: "payroll".to_string() };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), tick).unwrap();
+        assert_eq!(
+            res.events,
+            vec![Event::new("escrow_released")
+                .add_attribute("id", "payroll")
+                .add_attribute("actor", "keeper")
+                .add_attribute("leg", "source")
+                .add_attribute("to", "contributor")
+                .add_attribute("native", "200tokens")]
+        );
+        let pause = ExecuteMsg::PauseStream { id: "payroll".to_string() };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), pause).unwrap();
+        assert_eq!(res.events, vec![updated("payroll", "arbi", "stream_paused")]);
+        let resume = ExecuteMsg::ResumeStream { id: "payroll".to_string() };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), resume).unwrap();
+        assert_eq!(res.events, vec![updated("payroll", "arbi", "stream_resumed")]);
+
+        // a take releases the offer to the taker and the payment to the maker
+        let create = CreateMsg {
+            id: "offer".to_string(),
+            arbiter: String::from("arbi"),
+            title: "otc".to_string(),
+            description: "token for atom".to_string(),
+            terms: Some(crate::state::Terms {
+                ask: Denom::Native("atom".to_string()),
+                ask_amount: Uint128::new(50),
+                min_fill: None,
+            }),
+            ..Default::default()
+        };
+        let receive = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: String::from("maker"),
+            amount: Uint128::new(1000),
+            msg: to_binary(&ReceiveMsg::CreateEscrow(create)).unwrap(),
+        });
+        let res = execute(deps.as_mut(), env.clone(), mock_info("token", &[]), receive).unwrap();
+        assert_eq!(res.events[0].attributes[3], ("cw20", "token:1000"));
+        let accept = ExecuteMsg::AcceptArbitration { id: "offer".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), accept).unwrap();
+        let take = ExecuteMsg::Take { id: "offer".to_string() };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("taker", &coins(50, "atom")), take).unwrap();
+        assert_eq!(
+            res.events,
+            vec![
+                Event::new("escrow_released")
+                    .add_attribute("id", "offer")
+                    .add_attribute("actor", "taker")
+                    .add_attribute("leg", "source")
+                    .add_attribute("to", "taker")
+                    .add_attribute("cw20", "token:1000"),
+                Event::new("escrow_released")
+                    .add_attribute("id", "offer")
+                    .add_attribute("actor", "taker")
+                    .add_attribute("leg", "recipient")
+                    .add_attribute("to", "maker")
+                    .add_attribute("native", "50atom"),
+            ]
+        );
 
-    let details = DetailsResponse {
-        id,
-        arbiter: escrow.arbiter.into(),
-        recipient,
-        source: escrow.source.into(),
-        title: escrow.title,
-        description: escrow.description,
-        end_height: escrow.end_height,
-        end_time: escrow.end_time,
-        native_balance,
-        cw20_balance: cw20_balance?,
-        recip_native_balance,
-        recip_cw20_balance: recip_cw20_balance?,
-        cw20_wishlist,
-    };
+        // an extension is proposed by one party and agreed by the other
+        let create = CreateMsg {
+            id: "lease".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "lease".to_string(),
+            description: "extended by both parties".to_string(),
+            expires: Some(Expiration::AtHeight(start + 100)),
+            ..Default::default()
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("source", &coins(100, "tokens")), ExecuteMsg::CreateEscrow(create)).unwrap();
+        let extend = ExecuteMsg::Extend { id: "lease".to_string(), new_expiration: Expiration::AtHeight(start + 200) };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("reci", &[]), extend.clone()).unwrap();
+        assert_eq!(res.events, vec![updated("lease", "reci", "extension_proposed")]);
+        let res = execute(deps.as_mut(), env.clone(), mock_info("source", &[]), extend).unwrap();
+        assert_eq!(res.events, vec![updated("lease", "source", "extended")]);
 
-    Ok(details)
-}
+        // application deposits are reported without a leg
+        let create = CreateMsg {
+            id: "bounty".to_string(),
+            arbiter: String::from("arbi"),
+            title: "bounty".to_string(),
+            description: "open to applications".to_string(),
+            open_to_applications: true,
+            application_deposit: Some(coin(5, "usd")),
+            ..Default::default()
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("source", &coins(100, "tokens")), ExecuteMsg::CreateEscrow(create)).unwrap();
+        let deposit = GenericBalance { native: coins(5, "usd"), cw20: vec![] };
+        for applicant in ["alice", "bob"] {
+            let apply = ExecuteMsg::Apply { id: "bounty".to_string(), memo: "me".to_string() };
+            let res = execute(deps.as_mut(), env.clone(), mock_info(applicant, &coins(5, "usd")), apply).unwrap();
+            let applied = EscrowEvent::updated("bounty", &addr(applicant), "applied").tokens(&deposit);
+            assert_eq!(res.events, vec![applied.into()]);
+        }
+        let withdraw = ExecuteMsg::WithdrawApplication { id: "bounty".to_string() };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), withdraw).unwrap();
+        let withdrawn = EscrowEvent::updated("bounty", &addr("bob"), "application_withdrawn").tokens(&deposit);
+        assert_eq!(res.events, vec![withdrawn.into()]);
+        let select = ExecuteMsg::SelectRecipient { id: "bounty".to_string(), applicant: String::from("alice") };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("source", &[]), select).unwrap();
+        let selected = EscrowEvent::updated("bounty", &addr("source"), "recipient_selected").to(&addr("alice"));
+        assert_eq!(res.events, vec![selected.into()]);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-    use cosmwasm_std::{attr, coin, coins, CosmosMsg, StdError, Uint128};
-    use crate::msg::ExecuteMsg::TopUp;
+        // a prize pool releases one share per winner and omits the empty legs
+        let create = CreateMsg {
+            id: "prize".to_string(),
+            arbiter: String::from("arbi"),
+            title: "prize".to_string(),
+            description: "a single winner".to_string(),
+            prize_pool: true,
+            ..Default::default()
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &coins(100, "tokens")), ExecuteMsg::CreateEscrow(create)).unwrap();
+        let winners = vec![WinnerMsg { address: String::from("alice"), native: coins(100, "tokens"), cw20: vec![] }];
+        let set = ExecuteMsg::SetWinners { id: "prize".to_string(), winners };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), set).unwrap();
+        assert_eq!(res.events, vec![updated("prize", "arbi", "winners_set")]);
+        let approve = ExecuteMsg::Approve { id: "prize".to_string() };
+        let res = execute(deps.as_mut(), env, mock_info("arbi", &[]), approve).unwrap();
+        assert_eq!(
+            res.events,
+            vec![Event::new("escrow_released")
+                .add_attribute("id", "prize")
+                .add_attribute("actor", "arbi")
+                .add_attribute("leg", "source")
+                .add_attribute("to", "alice")
+                .add_attribute("native", "100tokens")]
+        );
+    }
 
     #[test]
-    fn instantiate_test(){
+    fn metadata_is_bounded_and_external_ids_are_unique() {
         let mut deps = mock_dependencies();
 
         // instantiate an empty contract
         let instantiate_msg = InstantiateMsg {};
-        let info = mock_info(&String::from("anyone"), &[]);
-        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
-        assert_eq!(0, res.messages.len());
+        let info = mock_info(&String::from("admin"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let env = mock_env();
+        let info = mock_info("source", &coins(100, "tokens"));
+        let sha256 = ContentHash::Sha256("9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08".to_string());
+        let create = CreateMsg {
+            id: "invoice".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "x".repeat(129),
+            description: "design work".to_string(),
+            tags: vec!["design".to_string(), "freelance".to_string()],
+            external_id: Some("INV-001".to_string()),
+            content_hash: Some(sha256.clone()),
+            ..Default::default()
+        };
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap_err();
+        assert_eq!(err, ContractError::TitleTooLong { max: 128 });
+
+        // the admin tightens the limits
+        let limits = MetadataLimits {
+            max_title_length: 16,
+            max_description_length: 32,
+            max_tags: 2,
+            max_tag_length: 10,
+            max_external_id_length: 8,
+            max_message_length: 2048,
+            max_application_memo_length: 512,
+        };
+        let update = ExecuteMsg::UpdateConfig {
+            keeper_bounty: None,
+            bounty_min_age: None,
+            acceptance_window: None,
+            require_registered_arbiter: None,
+            metadata_limits: Some(limits.clone()),
+            receipt_collection: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), update).unwrap();
+        assert_eq!(query_config(deps.as_ref()).unwrap().metadata_limits, limits);
+
+        let create = CreateMsg { title: "invoice".to_string(), ..create };
+        let invalid = vec![
+            (CreateMsg { description: "d".repeat(33), ..create.clone() }, ContractError::DescriptionTooLong { max: 32 }),
+            (
+                CreateMsg { tags: vec!["a".to_string(), "b".to_string(), "c".to_string()], ..create.clone() },
+                ContractError::TooManyTags { max: 2 },
+            ),
+            (CreateMsg { tags: vec!["".to_string()], ..create.clone() }, ContractError::InvalidTag { max: 10 }),
+            (CreateMsg { tags: vec!["engineering".to_string()], ..create.clone() }, ContractError::InvalidTag { max: 10 }),
+            (CreateMsg { external_id: Some("".to_string()), ..create.clone() }, ContractError::InvalidExternalId { max: 8 }),
+            (
+                CreateMsg { external_id: Some("INV-00001".to_string()), ..create.clone() },
+                ContractError::InvalidExternalId { max: 8 },
+            ),
+            (
+                CreateMsg { content_hash: Some(ContentHash::Sha256("abc".to_string())), ..create.clone() },
+                ContractError::InvalidContentHash {},
+            ),
+            (
+                CreateMsg { content_hash: Some(ContentHash::Ipfs("not a cid".to_string())), ..create.clone() },
+                ContractError::InvalidContentHash {},
+            ),
+        ];
+        for (msg, expected) in invalid {
+            let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(msg)).unwrap_err();
+            assert_eq!(err, expected);
+        }
+
+        // the escrow can be found by its external id
+        execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap();
+        let details = query_details_by_external_id(deps.as_ref(), "INV-001".to_string()).unwrap();
+        assert_eq!(details.id, "invoice");
+        assert_eq!(details.tags, vec!["design".to_string(), "freelance".to_string()]);
+        assert_eq!(details.external_id, Some("INV-001".to_string()));
+        assert_eq!(details.content_hash, Some(sha256));
+
+        // an external id belongs to one open escrow at a time
+        let other = CreateMsg {
+            id: "duplicate".to_string(),
+            content_hash: Some(ContentHash::Ipfs("QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG".to_string())),
+            ..create
+        };
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(other.clone())).unwrap_err();
+        assert_eq!(err, ContractError::ExternalIdInUse {});
+
+        // settling the escrow frees its external id
+        let approve = ExecuteMsg::Approve { id: "invoice".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), approve).unwrap();
+        assert!(query_details_by_external_id(deps.as_ref(), "INV-001".to_string()).is_err());
+        execute(deps.as_mut(), env, info, ExecuteMsg::CreateEscrow(other)).unwrap();
+        let details = query_details_by_external_id(deps.as_ref(), "INV-001".to_string()).unwrap();
+        assert_eq!(details.id, "duplicate");
     }
 
     #[test]
-    fn instantiate_and_create_escrow_and_query_details_test(){
+    fn parties_keep_encrypted_memos_and_a_message_thread() {
         let mut deps = mock_dependencies();
 
         // instantiate an empty contract
         let instantiate_msg = InstantiateMsg {};
         let info = mock_info(&String::from("anyone"), &[]);
-        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
-        assert_eq!(0, res.messages.len());
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
 
-        let wishlist = vec![String::from("btc"), String::from("eth")];
-
-        // create an escrow
+        let env = mock_env();
+        let memo = |party: Party, ciphertext: &[u8]| EncryptedMemo {
+            party,
+            ciphertext: Binary::from(ciphertext),
+            key_id: "age1recipientkey".to_string(),
+        };
         let create = CreateMsg {
-            id: "some_id".to_string(),
-            arbiter: String::from("some_arbiter"),
-            recipient: Some(String::from("some_recipient")),
-            title: "some_title".to_string(),
-            end_time: None,
-            end_height: Some(123456),
-            description: "some_description".to_string(),
-            cw20_wishlist: Some(wishlist),
+            id: "private".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "private".to_string(),
+            description: "terms in the memos".to_string(),
+            memos: vec![memo(Party::Recipient, b"sealed"), memo(Party::Recipient, b"again")],
+            ..Default::default()
         };
-        let sender = String::from("source");
-        let balance = coins(100, "tokens");
-        let recip_balance = None;
-        let info = mock_info(&sender, &balance);
-        let msg = ExecuteMsg::CreateEscrow(create.clone());
-        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
-        assert_eq!(("action", "create_escrow"), res.attributes[0]);
+        let info = mock_info("source", &coins(100, "tokens"));
 
-        // ensure the details is what we expect
-        let details = query_details(deps.as_ref(), "some_id".to_string()).unwrap();
+        // one memo per party, with a key id and some ciphertext
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap_err();
+        assert_eq!(err, ContractError::InvalidMemo { max: 2048 });
+        let create = CreateMsg { memos: vec![memo(Party::Recipient, b"")], ..create };
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap_err();
+        assert_eq!(err, ContractError::InvalidMemo { max: 2048 });
+        let memos = vec![memo(Party::Recipient, b"sealed"), memo(Party::Arbiter, b"for the arbiter")];
+        let create = CreateMsg { memos: memos.clone(), ..create };
+        execute(deps.as_mut(), env.clone(), info, ExecuteMsg::CreateEscrow(create)).unwrap();
+        let details = query_details(deps.as_ref(), "private".to_string()).unwrap();
+        assert_eq!(details.memos, memos);
+
+        // only the parties post, and never empty messages
+        let post = |ciphertext: &[u8]| ExecuteMsg::PostMessage {
+            id: "private".to_string(),
+            ciphertext: Binary::from(ciphertext),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("stranger", &[]), post(b"hello")).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let err = execute(deps.as_mut(), env.clone(), mock_info("source", &[]), post(b"")).unwrap_err();
+        assert_eq!(err, ContractError::InvalidMessage { max: 2048 });
+        for (i, sender) in ["source", "reci", "arbi"].iter().enumerate() {
+            let res = execute(deps.as_mut(), env.clone(), mock_info(sender, &[]), post(sender.as_bytes())).unwrap();
+            assert_eq!(("index", i.to_string()), res.attributes[2]);
+            let posted = EscrowEvent::updated("private", &Addr::unchecked(*sender), "message_posted");
+            assert_eq!(res.events, vec![posted.into()]);
+        }
+
+        // the thread is paginated oldest first
+        let page = query_messages(deps.as_ref(), "private".to_string(), None, Some(2)).unwrap();
         assert_eq!(
-            details,
-            DetailsResponse {
-                id: "some_id".to_string(),
-                arbiter: String::from("some_arbiter"),
-                recipient: Some(String::from("some_recipient")),
-                source: String::from("source"),
-                title: "some_title".to_string(),
-                description: "some_description".to_string(),
-                end_height: Some(123456),
-                end_time: None,
-                native_balance: balance.clone(),
-                cw20_balance: vec![],
-                recip_native_balance: recip_balance.clone().unwrap(),
-                recip_cw20_balance: vec![],
-                cw20_wishlist: vec![],
-            }
+            page.messages,
+            vec![
+                ThreadMessage {
+                    index: 0,
+                    sender: "source".to_string(),
+                    ciphertext: Binary::from(b"source"),
+                    height: env.block.height,
+                    time: env.block.time,
+                },
+                ThreadMessage {
+                    index: 1,
+                    sender: "reci".to_string(),
+                    ciphertext: Binary::from(b"reci"),
+                    height: env.block.height,
+                    time: env.block.time,
+                },
+            ]
         );
+        let page = query_messages(deps.as_ref(), "private".to_string(), Some(1), None).unwrap();
+        assert_eq!(page.messages.len(), 1);
+        assert_eq!(page.messages[0].sender, "arbi");
+
+        // the thread outlives the escrow, which takes no more messages
+        let approve = ExecuteMsg::Approve { id: "private".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), approve).unwrap();
+        let err = execute(deps.as_mut(), env, mock_info("source", &[]), post(b"thanks")).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::NotFound { .. })));
+        let page = query_messages(deps.as_ref(), "private".to_string(), None, None).unwrap();
+        assert_eq!(page.messages.len(), 3);
     }
 
     #[test]
-    fn instantiate_and_create_escrow_and_approve_and_approve_again_test() {
+    fn finished_stream_returns_the_recipient_leg_to_the_source() {
         let mut deps = mock_dependencies();
 
         // instantiate an empty contract
         let instantiate_msg = InstantiateMsg {};
         let info = mock_info(&String::from("anyone"), &[]);
-        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
-        assert_eq!(0, res.messages.len());
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
 
-        // create an escrow
+        let mut env = mock_env();
+        let start = env.block.height;
         let create = CreateMsg {
-            id: "some_id".to_string(),
-            arbiter: String::from("some_arbiter"),
-            recipient: Some(String::from("some_recipient")),
-            title: "some_title".to_string(),
-            end_time: None,
-            end_height: Some(123456),
-            cw20_wishlist: None,
-            description: "some_description".to_string(),
+            id: "payroll".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("contributor")),
+            title: "payroll".to_string(),
+            description: "two payments".to_string(),
+            stream: Some(crate::msg::StreamMsg {
+                native_amount: coins(100, "tokens"),
+                cw20_amount: vec![],
+                interval: Duration::Height(10),
+            }),
+            ..Default::default()
         };
-        let sender = String::from("source");
-        let balance = coins(100, "tokens");
-        let recip_balance = None;
-        let info = mock_info(&sender, &balance);
-        let msg = ExecuteMsg::CreateEscrow(create.clone());
-        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
-        assert_eq!(("action", "create_escrow"), res.attributes[0]);
+        execute(deps.as_mut(), env.clone(), mock_info("source", &coins(200, "tokens")), ExecuteMsg::CreateEscrow(create)).unwrap();
+        let accept = ExecuteMsg::AcceptArbitration { id: "payroll".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), accept).unwrap();
+        let top_up = ExecuteMsg::TopUpRecip { id: "payroll".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("contributor", &coins(7, "btc")), top_up).unwrap();
 
-        // ensure the details is what we expect
-        let details = query_details(deps.as_ref(), "some_id".to_string()).unwrap();
+        // the last tick closes the stream and pays the recipient leg to the source
+        env.block.height = start + 20;
+        let tick = ExecuteMsg::Tick { id: "payroll".to_string() };
+        let res = execute(deps.as_mut(), env, mock_info("keeper", &[]), tick).unwrap();
         assert_eq!(
-            details,
-            DetailsResponse {
-                id: "some_id".to_string(),
-                arbiter: String::from("some_arbiter"),
-                recipient: Some(String::from("some_recipient")),
-                source: String::from("source"),
-                title: "some_title".to_string(),
-                description: "some_description".to_string(),
-                end_height: Some(123456),
-                end_time: None,
-                native_balance: balance.clone(),
-                cw20_balance: vec![],
-                cw20_wishlist: vec![],
-                recip_native_balance: recip_balance.clone().unwrap(),
-                recip_cw20_balance: vec![],
-            }
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "contributor".to_string(), amount: coins(200, "tokens") }),
+                SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(7, "btc") }),
+            ]
         );
+        assert!(query_details(deps.as_ref(), "payroll".to_string()).is_err());
+    }
 
-        // someone (not arbiter) tries to approve it
-        let id = create.id.clone();
+    #[test]
+    fn takers_are_paid_without_the_payout_msg() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
         let info = mock_info(&String::from("anyone"), &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id });
-        match res {
-            Err(ContractError::Unauthorized {}) => {}
-            _ => panic!("Expect unauthorized error"),
-        }
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
 
+        // the maker set a payout message, which is meant for a recipient contract
+        let create = CreateMsg {
+            id: "offer".to_string(),
+            arbiter: String::from("arbi"),
+            title: "otc".to_string(),
+            description: "tokens for atom".to_string(),
+            payout_msg: Some(Binary::from(b"{\"deposit\":{}}".to_vec())),
+            terms: Some(crate::state::Terms {
+                ask: Denom::Native("atom".to_string()),
+                ask_amount: Uint128::new(50),
+                min_fill: None,
+            }),
+            ..Default::default()
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("maker", &coins(1000, "tokens")), ExecuteMsg::CreateEscrow(create)).unwrap();
+        let accept = ExecuteMsg::AcceptArbitration { id: "offer".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), accept).unwrap();
 
-        // arbiter approve it
-        let id = create.id.clone();
-        let info = mock_info(&create.arbiter, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap();
-        assert_eq!(1, res.messages.len());
-        assert_eq!(("action", "approve"), res.attributes[0]);
+        let take = ExecuteMsg::Take { id: "offer".to_string() };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("taker", &coins(50, "atom")), take).unwrap();
         assert_eq!(
-            res.messages[0],
-            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
-                to_address: create.recipient.unwrap(),
-                amount: balance,
-            }))
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "taker".to_string(), amount: coins(1000, "tokens") }),
+                SubMsg::new(BankMsg::Send { to_address: "maker".to_string(), amount: coins(50, "atom") }),
+            ]
         );
-
-        // second attempt fails (not found)
-        let id = create.id.clone();
-        let info = mock_info(&create.arbiter, &[]);
-        let err = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap_err();
-        assert!(matches!(err, ContractError::Std(StdError::NotFound { .. })));
     }
 
     #[test]
-    fn create_escrow_and_set_recipient_test(){
+    fn sweep_limit_only_counts_refundable_escrows() {
         let mut deps = mock_dependencies();
 
         // instantiate an empty contract
         let instantiate_msg = InstantiateMsg {};
         let info = mock_info(&String::from("anyone"), &[]);
-        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
-        assert_eq!(0, res.messages.len());
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
 
-        // create an escrow
+        // the funding deadline is indexed but doesn't apply while the arbitration is pending
+        let mut env = mock_env();
         let create = CreateMsg {
-            id: "test_id".to_string(),
-            arbiter: String::from("test_arbiter"),
-            recipient: None,
-            title: "test_title".to_string(),
-            end_time: None,
-            end_height: Some(123456),
-            cw20_wishlist: None,
-            description: "test_description".to_string(),
+            id: "waiting".to_string(),
+            arbiter: String::from("arbi"),
+            recipient: Some(String::from("reci")),
+            title: "waiting".to_string(),
+            description: "arbiter yet to accept".to_string(),
+            funding_deadline: Some(Expiration::AtHeight(env.block.height + 5)),
+            ..Default::default()
         };
-        let sender = String::from("test_source");
-        let balance = coins(100, "tokens");
-        let recip_balance = None;
-        let info = mock_info(&sender, &balance);
-        let msg = ExecuteMsg::CreateEscrow(create.clone());
-        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
-        assert_eq!(("action", "create_escrow"), res.attributes[0]);
+        execute(deps.as_mut(), env.clone(), mock_info("source", &coins(100, "tokens")), ExecuteMsg::CreateEscrow(create)).unwrap();
+        let create = CreateMsg {
+            id: "stale".to_string(),
+            arbiter: String::from("arbi"),
+            title: "stale".to_string(),
+            description: "left to expire".to_string(),
+            expires: Some(Expiration::AtHeight(env.block.height + 10)),
+            ..Default::default()
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("source", &coins(200, "tokens")), ExecuteMsg::CreateEscrow(create)).unwrap();
 
-        // ensure the details is what we expect
-        let details = query_details(deps.as_ref(), "test_id".to_string()).unwrap();
-        assert_eq!(
-            details,
-            DetailsResponse {
-                id: "test_id".to_string(),
-                arbiter: String::from("test_arbiter"),
-                recipient: None,
-                source: String::from("test_source"),
-                title: "test_title".to_string(),
-                description: "test_description".to_string(),
-                end_height: Some(123456),
-                end_time: None,
-                native_balance: balance.clone(),
-                cw20_balance: vec![],
-                recip_native_balance: recip_balance.clone().unwrap(),
-                recip_cw20_balance: vec![],
-                cw20_wishlist: vec![],
-            }
-        );
+        env.block.height += 11;
+        let sweep = ExecuteMsg::SweepExpired { limit: Some(1) };
+        let res = execute(deps.as_mut(), env, mock_info("keeper", &[]), sweep).unwrap();
+        assert_eq!(res.attributes[1], attr("id", "stale"));
+        assert_eq!(res.attributes[2], attr("swept", "1"));
+        assert!(query_details(deps.as_ref(), "waiting".to_string()).is_ok());
+    }
 
-        // approve it, should fail as we have not set recipient
-        let id = create.id.clone();
-        let info = mock_info(&create.arbiter, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id });
-        match res {
-            Err(ContractError::RecipientNotSet {}) => {}
-            _ => panic!("Expect recipient not set error"),
-        }
+    #[test]
+    fn takers_pay_their_share_of_the_arbiter_fee() {
+        let mut deps = mock_dependencies();
 
-        // test someone that is not arbiter setting recipient should fail
-        let msg = ExecuteMsg::SetRecipient {
-            id: create.id.clone(),
-            recipient: "recp".to_string(),
-        };
-        let info = mock_info("someoneelse", &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, msg);
-        match res {
-            Err(ContractError::Unauthorized {}) => {}
-            _ => panic!("Expect unauthorized error"),
-        }
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
 
-        // test setting recipient valid
-        let msg = ExecuteMsg::SetRecipient {
-            id: create.id.clone(),
-            recipient: "recp".to_string(),
+        // a fixed fee of 50 usd is split across the fills
+        let create = CreateMsg {
+            id: "fixed".to_string(),
+            arbiter: String::from("arbi"),
+            title: "otc".to_string(),
+            description: "usd for atom".to_string(),
+            arbiter_fee: Some(ArbiterFeeMsg::Fixed { native: coins(50, "usd"), cw20: vec![] }),
+            terms: Some(crate::state::Terms {
+                ask: Denom::Native("atom".to_string()),
+                ask_amount: Uint128::new(100),
+                min_fill: Some(Uint128::new(20)),
+            }),
+            ..Default::default()
         };
-        let info = mock_info(&create.arbiter, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(res.messages.len(), 0);
+        execute(deps.as_mut(), mock_env(), mock_info("maker", &coins(1000, "usd")), ExecuteMsg::CreateEscrow(create)).unwrap();
+        let accept = ExecuteMsg::AcceptArbitration { id: "fixed".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), accept).unwrap();
+        let take = ExecuteMsg::Take { id: "fixed".to_string() };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &coins(30, "atom")), take.clone()).unwrap();
         assert_eq!(
-            res.attributes,
+            res.messages,
             vec![
-                attr("action", "set_recipient"),
-                attr("id", create.id.as_str()),
-                attr("recipient", "recp")
+                SubMsg::new(BankMsg::Send { to_address: "arbi".to_string(), amount: coins(15, "usd") }),
+                SubMsg::new(BankMsg::Send { to_address: "alice".to_string(), amount: coins(285, "usd") }),
+                SubMsg::new(BankMsg::Send { to_address: "maker".to_string(), amount: coins(30, "atom") }),
+            ]
+        );
+        let details = query_details(deps.as_ref(), "fixed".to_string()).unwrap();
+        assert_eq!(details.native_balance, coins(700, "usd"));
+        assert_eq!(details.arbiter_fee, Some(ArbiterFee::Fixed(GenericBalance { native: coins(35, "usd"), cw20: vec![] })));
+
+        // the last fill pays what is left of the fee
+        let res = execute(deps.as_mut(), mock_env(), mock_info("bob", &coins(70, "atom")), take).unwrap();
+        assert_eq!(
+            res.messages[..2],
+            [
+                SubMsg::new(BankMsg::Send { to_address: "arbi".to_string(), amount: coins(35, "usd") }),
+                SubMsg::new(BankMsg::Send { to_address: "bob".to_string(), amount: coins(665, "usd") }),
             ]
         );
+        let fills = query_fills(deps.as_ref(), "fixed".to_string(), None, None).unwrap().fills;
+        assert_eq!(fills[1].native_received, coins(665, "usd"));
 
-        // approve it, should now work with recp
-        let id = create.id.clone();
-        let info = mock_info(&create.arbiter, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap();
-        assert_eq!(1, res.messages.len());
-        assert_eq!(("action", "approve"), res.attributes[0]);
+        // a fee in basis points is taken from every fill
+        let create = CreateMsg {
+            id: "bps".to_string(),
+            arbiter: String::from("arbi"),
+            title: "otc".to_string(),
+            description: "usd for atom".to_string(),
+            arbiter_fee: Some(ArbiterFeeMsg::Bps(100)),
+            terms: Some(crate::state::Terms {
+                ask: Denom::Native("atom".to_string()),
+                ask_amount: Uint128::new(100),
+                min_fill: None,
+            }),
+            ..Default::default()
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("maker", &coins(1000, "usd")), ExecuteMsg::CreateEscrow(create)).unwrap();
+        let accept = ExecuteMsg::AcceptArbitration { id: "bps".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), accept).unwrap();
+        let take = ExecuteMsg::Take { id: "bps".to_string() };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("carol", &coins(100, "atom")), take).unwrap();
         assert_eq!(
-            res.messages[0],
-            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
-                to_address: "recp".to_string(),
-                amount: balance,
-            }))
+            res.events[0],
+            EscrowEvent::new(EventKind::EscrowFeePaid, "bps", &Addr::unchecked("carol"))
+                .leg(Leg::Source)
+                .to(&Addr::unchecked("arbi"))
+                .tokens(&GenericBalance { native: coins(10, "usd"), cw20: vec![] })
+                .into()
+        );
+        assert_eq!(
+            res.messages[1],
+            SubMsg::new(BankMsg::Send { to_address: "carol".to_string(), amount: coins(990, "usd") })
         );
     }
 
-    /*
     #[test]
-    fn create_escrow_and_refund(){
+    fn every_arbiter_action_keeps_the_backup_waiting() {
         let mut deps = mock_dependencies();
 
         // instantiate an empty contract
         let instantiate_msg = InstantiateMsg {};
         let info = mock_info(&String::from("anyone"), &[]);
-        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
-        assert_eq!(0, res.messages.len());
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
 
-        // create an escrow
+        let mut env = mock_env();
+        let start = env.block.height;
         let create = CreateMsg {
-            id: "test_id".to_string(),
-            arbiter: String::from("test_arbiter"),
-            recipient: Some(String::from("recp")),
-            title: "test_title".to_string(),
-            end_time: None,
-            end_height: Some(123456),
-            cw20_wishlist: None,
-            description: "test_description".to_string(),
+            id: "watched".to_string(),
+            arbiter: String::from("arbi"),
+            title: "watched".to_string(),
+            description: "backup on standby".to_string(),
+            backup_arbiter: Some(BackupArbiterMsg {
+                address: String::from("backup"),
+                inactivity_timeout: Duration::Height(50),
+            }),
+            ..Default::default()
         };
-        let sender = String::from("test_source");
-        let balance = coins(100, "tokens");
-        let recip_balance = Some(None);
-        //let recip_balance = None;
-        let info = mock_info(&sender, &balance);
-        let msg = ExecuteMsg::CreateEscrow(create.clone());
-        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
-        assert_eq!(("action", "create_escrow"), res.attributes[0]);
+        execute(deps.as_mut(), env.clone(), mock_info("source", &coins(100, "tokens")), ExecuteMsg::CreateEscrow(create)).unwrap();
+        let takes_over_at = |deps: Deps| query_details(deps, "watched".to_string()).unwrap().backup_arbiter.unwrap().takes_over_at;
 
-        // test someone that is not arbiter refund should fail
-        let msg = ExecuteMsg::Refund {
-            id: create.id.clone(),
-        };
-        let info = mock_info("someoneelse", &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, msg);
-        match res {
-            Err(ContractError::Unauthorized {}) => {}
-            _ => panic!("Expect unauthorized error"),
-        }
+        env.block.height = start + 40;
+        let set_recipient = ExecuteMsg::SetRecipient { id: "watched".to_string(), recipient: String::from("reci") };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), set_recipient).unwrap();
+        assert_eq!(takes_over_at(deps.as_ref()), Expiration::AtHeight(start + 90));
 
-        // test refund valid
-        let id = create.id.clone();
-        let info = mock_info(&create.arbiter, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Refund { id }).unwrap();
-        assert_eq!(1, res.messages.len());
-        assert_eq!(("action", "refund"), res.attributes[0]);
-        assert_eq!(
-            res.messages[0],
-            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
-                to_address: create.arbiter,
-                amount: balance,
-            }))
-        );
-        assert_eq!(
-            res.messages[1],
-            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
-                to_address: create.recipient.unwrap(),
-                amount: recip_balance,
-            }))
-        );
-    }*/
+        env.block.height = start + 80;
+        let top_up = ExecuteMsg::TopUp { id: "watched".to_string() };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &coins(10, "tokens")), top_up).unwrap();
+        assert_eq!(takes_over_at(deps.as_ref()), Expiration::AtHeight(start + 130));
 
-    #[test]
-    fn add_native_tokens_proper() {
-        let mut tokens = GenericBalance::default();
-        tokens.add_tokens(Balance::from(vec![coin(123, "atom"), coin(789, "eth")]));
-        tokens.add_tokens(Balance::from(vec![coin(456, "atom"), coin(12, "btc")]));
-        assert_eq!(
-            tokens.native,
-            vec![coin(579, "atom"), coin(789, "eth"), coin(12, "btc")]
-        );
+        env.block.height = start + 120;
+        let post = ExecuteMsg::PostMessage { id: "watched".to_string(), ciphertext: Binary::from(b"ping".to_vec()) };
+        execute(deps.as_mut(), env.clone(), mock_info("arbi", &[]), post.clone()).unwrap();
+        assert_eq!(takes_over_at(deps.as_ref()), Expiration::AtHeight(start + 170));
+
+        // messages of the other parties don't count
+        env.block.height = start + 150;
+        execute(deps.as_mut(), env.clone(), mock_info("source", &[]), post).unwrap();
+        assert_eq!(takes_over_at(deps.as_ref()), Expiration::AtHeight(start + 170));
+        let take_over = ExecuteMsg::TakeOverArbiter { id: "watched".to_string() };
+        let err = execute(deps.as_mut(), env, mock_info("backup", &[]), take_over).unwrap_err();
+        assert_eq!(err, ContractError::ArbiterStillActive {});
     }
-    
+
     #[test]
-    fn add_cw_tokens_proper() {
-        let mut tokens = GenericBalance::default();
-        let test_token = Addr::unchecked("test_token");
-        let test2_token = Addr::unchecked("test2_token");
-        tokens.add_tokens(Balance::Cw20(Cw20CoinVerified {
-            address: test_token.clone(),
-            amount: Uint128::new(12345),
-        }));
-        tokens.add_tokens(Balance::Cw20(Cw20CoinVerified {
-            address: test2_token.clone(),
-            amount: Uint128::new(777),
-        }));
-        tokens.add_tokens(Balance::Cw20(Cw20CoinVerified {
-            address: test_token.clone(),
-            amount: Uint128::new(23400),
-        }));
-        assert_eq!(
-            tokens.cw20,
-            vec![
-                Cw20CoinVerified {
-                    address: test_token,
-                    amount: Uint128::new(35745),
-                },
-                Cw20CoinVerified {
-                    address: test2_token,
-                    amount: Uint128::new(777),
-                }
-            ]
-        );
+    fn payout_modes_cannot_be_combined() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        let info = mock_info(&String::from("anyone"), &[]);
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let create = CreateMsg {
+            id: "mixed".to_string(),
+            arbiter: String::from("arbi"),
+            title: "mixed".to_string(),
+            description: "an offer that also streams".to_string(),
+            terms: Some(crate::state::Terms {
+                ask: Denom::Native("atom".to_string()),
+                ask_amount: Uint128::new(50),
+                min_fill: None,
+            }),
+            stream: Some(crate::msg::StreamMsg {
+                native_amount: coins(100, "tokens"),
+                cw20_amount: vec![],
+                interval: Duration::Height(10),
+            }),
+            ..Default::default()
+        };
+        let info = mock_info("maker", &coins(1000, "tokens"));
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::CreateEscrow(create.clone())).unwrap_err();
+        assert_eq!(err, ContractError::ConflictingModes {});
+
+        let pool = CreateMsg { terms: None, stream: None, prize_pool: true, open_to_applications: true, ..create };
+        let err = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::CreateEscrow(pool)).unwrap_err();
+        assert_eq!(err, ContractError::ConflictingModes {});
     }
 
     #[test]
-    fn top_up_mixed_tokens() {
-        // instantiate an empty contract &&  accept only certain tokens
-        // create an escrow with 2 native tokens &&  top it up with 2 more native tokens
-        // top up with one foreign token
-        // top up with second foreign token
-        // approve it  &&  first message releases all native coins
-        // second one release token_1 cw20 token  &&  third one release token_2 cw20 token
+    fn ids_with_kept_records_are_not_reused() {
         let mut deps = mock_dependencies();
 
         // instantiate an empty contract
         let instantiate_msg = InstantiateMsg {};
         let info = mock_info(&String::from("anyone"), &[]);
-        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
-        assert_eq!(0, res.messages.len());
+        instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
 
-        // create an escrow with 2 native tokens
-        let create = CreateMsg {
-            id: "escrow_id".to_string(),
+        let create = |id: &str| CreateMsg {
+            id: id.to_string(),
             arbiter: String::from("arbi"),
             recipient: Some(String::from("reci")),
             title: "some_title".to_string(),
-            end_time: None,
-            end_height: None,
-            cw20_wishlist: None,
             description: "some_description".to_string(),
+            ..Default::default()
         };
-        let sender = String::from("source");
-        let balance = vec![coin(100, "fee"), coin(200, "stake")];
-        let info = mock_info(&sender, &balance);
-        let msg = ExecuteMsg::CreateEscrow(create.clone());
-        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
-        assert_eq!(("action", "create_escrow"), res.attributes[0]);
-
-        // top it up with 2 more native tokens
-        let extra_native = vec![coin(250, "random"), coin(300, "stake")];
-        let info = mock_info(&create.arbiter, &extra_native);
-        let top_up = ExecuteMsg::TopUp {
-            id: create.id.clone(),
-        };
-        let res = execute(deps.as_mut(), mock_env(), info, top_up).unwrap();
-        assert_eq!(0, res.messages.len());
-        assert_eq!(("action", "top_up"), res.attributes[0]);
-        
-        // top it up with 2 more native tokens |fails| not arbiter
-        let extra_native = vec![coin(250, "random"), coin(300, "stake")];
-        let info = mock_info(&sender, &extra_native);
-        let top_up = ExecuteMsg::TopUp {
-            id: create.id.clone(),
-        };
-        let res = execute(deps.as_mut(), mock_env(), info, top_up);
-        match res {
-            Err(ContractError::Unauthorized {}) => {}
-            _ => panic!("Expect unauthorized error"),
+        let info = mock_info("source", &coins(100, "tokens"));
+        for id in ["plain", "talked"] {
+            execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::CreateEscrow(create(id))).unwrap();
+        }
+        let post = ExecuteMsg::PostMessage { id: "talked".to_string(), ciphertext: Binary::from(b"hello".to_vec()) };
+        execute(deps.as_mut(), mock_env(), mock_info("reci", &[]), post).unwrap();
+        for id in ["plain", "talked"] {
+            let refund = ExecuteMsg::Refund { id: id.to_string() };
+            execute(deps.as_mut(), mock_env(), mock_info("arbi", &[]), refund).unwrap();
         }
 
-        // top up with one foreign token
-        let wl_token_1 = String::from("wl_token_1");
-        let base = TopUp {
-            id: create.id.clone(),
-        };
-        let top_up = ExecuteMsg::Receive(Cw20ReceiveMsg {
-            sender: String::from("arbi"),
-            amount: Uint128::new(7890),
-            msg: to_binary(&base).unwrap(),
-        });
-        let info = mock_info(&wl_token_1, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, top_up).unwrap();
-        assert_eq!(0, res.messages.len());
-        assert_eq!(("action", "top_up"), res.attributes[0]);
+        // the thread outlives the escrow, so its id can't be given to a new one
+        execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::CreateEscrow(create("plain"))).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::CreateEscrow(create("talked"))).unwrap_err();
+        assert_eq!(err, ContractError::IdHasRecords {});
+        let thread = query_messages(deps.as_ref(), "talked".to_string(), None, None).unwrap();
+        assert_eq!(thread.messages.len(), 1);
+    }
 
-        // top up with one foreign token
-        let wl_token_1 = String::from("wl_token_1");
-        let base = TopUp {
-            id: create.id.clone(),
+    #[test]
+    fn update_config_only_changes_the_given_settings() {
+        let mut deps = mock_dependencies();
+
+        // instantiate an empty contract
+        let instantiate_msg = InstantiateMsg {};
+        instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), instantiate_msg).unwrap();
+        let limits = MetadataLimits { max_tags: 2, ..MetadataLimits::default() };
+        let update = ExecuteMsg::UpdateConfig {
+            keeper_bounty: Some(coin(10, "tokens")),
+            bounty_min_age: Some(3600),
+            acceptance_window: Some(Duration::Height(100)),
+            require_registered_arbiter: Some(true),
+            metadata_limits: Some(limits.clone()),
+            receipt_collection: None,
         };
-        let top_up = ExecuteMsg::Receive(Cw20ReceiveMsg {
-            sender: String::from("random_sender"),
-            amount: Uint128::new(7890),
-            msg: to_binary(&base).unwrap(),
-        });
-        let info = mock_info(&wl_token_1, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, top_up);
-        match res {
-            Err(ContractError::Unauthorized {}) => {}
-            _ => panic!("Expect unauthorized error"),
-        }
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), update).unwrap();
 
-        // top up with second foreign token
-        let wl_token_2 = String::from("wl_token_2");
-        let base = TopUp {
-            id: create.id.clone(),
+        // changing the bounty alone keeps every other setting
+        let update = ExecuteMsg::UpdateConfig {
+            keeper_bounty: Some(coin(20, "tokens")),
+            bounty_min_age: None,
+            acceptance_window: None,
+            require_registered_arbiter: None,
+            metadata_limits: None,
+            receipt_collection: None,
         };
-        let top_up = ExecuteMsg::Receive(Cw20ReceiveMsg {
-            sender: String::from("arbi"),
-            amount: Uint128::new(888),
-            msg: to_binary(&base).unwrap(),
-        });
-        let info = mock_info(&wl_token_2, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, top_up).unwrap();
-        assert_eq!(0, res.messages.len());
-        assert_eq!(("action", "top_up"), res.attributes[0]);
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), update).unwrap();
+        let config = query_config(deps.as_ref()).unwrap();
+        assert_eq!(config.keeper_bounty, Some(coin(20, "tokens")));
+        assert_eq!(config.bounty_min_age, Some(3600));
+        assert_eq!(config.acceptance_window, Some(Duration::Height(100)));
+        assert!(config.require_registered_arbiter);
+        assert_eq!(config.metadata_limits, limits);
+    }
 
-        // approve it
-        let id = create.id.clone();
-        let info = mock_info(&create.arbiter, &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Approve { id }).unwrap();
-        assert_eq!(("action", "approve"), res.attributes[0]);
-        assert_eq!(3, res.messages.len());
+    #[test]
+    fn sweeping_own_or_young_escrows_earns_no_bounty() {
+        let mut deps = mock_dependencies();
 
-        // first message releases all native coins
-        assert_eq!(
-            res.messages[0],
-            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
-                to_address: create.recipient.clone().unwrap(),
-                amount: vec![coin(100, "fee"), coin(500, "stake"), coin(250, "random")],
-            }))
-        );
+        // instantiate an empty contract with a bounty of 10 for escrows an hour old
+        let instantiate_msg = InstantiateMsg {};
+        instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), instantiate_msg).unwrap();
+        let update = ExecuteMsg::UpdateConfig {
+            keeper_bounty: Some(coin(10, "tokens")),
+            bounty_min_age: Some(3600),
+            acceptance_window: None,
+            require_registered_arbiter: None,
+            metadata_limits: None,
+            receipt_collection: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), update).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &coins(100, "tokens")), ExecuteMsg::FundFeePool {}).unwrap();
 
-        // second one release token_1 cw20 token
-        let send_msg = Cw20ExecuteMsg::Transfer {
-            recipient: create.recipient.clone().unwrap(),
-            amount: Uint128::new(7890),
+        let mut env = mock_env();
+        let create = |id: &str, expires: u64| CreateMsg {
+            id: id.to_string(),
+            arbiter: String::from("arbi"),
+            title: id.to_string(),
+            description: "left to expire".to_string(),
+            expires: Some(Expiration::AtHeight(expires)),
+            ..Default::default()
         };
+        let info = mock_info("attacker", &coins(1, "tokens"));
+        execute(deps.as_mut(), env.clone(), info.clone(), ExecuteMsg::CreateEscrow(create("own", env.block.height + 1))).unwrap();
+        execute(deps.as_mut(), env.clone(), info, ExecuteMsg::CreateEscrow(create("young", env.block.height + 1))).unwrap();
+
+        // the source sweeping its own escrow is only refunded
+        let sweep = ExecuteMsg::SweepExpired { limit: Some(1) };
+        env.block.height += 1;
+        let res = execute(deps.as_mut(), env.clone(), mock_info("attacker", &[]), sweep.clone()).unwrap();
+        assert_eq!(res.attributes[1], attr("id", "own"));
+        assert_eq!(res.attributes[3], attr("rewarded", "0"));
         assert_eq!(
-            res.messages[1],
-            SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: wl_token_1,
-                msg: to_binary(&send_msg).unwrap(),
-                funds: vec![]
-            }))
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send { to_address: "attacker".to_string(), amount: coins(1, "tokens") })]
         );
 
-        // third one release token_2 cw20 token
-        let send_msg = Cw20ExecuteMsg::Transfer {
-            recipient: create.recipient.unwrap(),
-            amount: Uint128::new(888),
-        };
+        // so is another keeper sweeping an escrow younger than the minimum age
+        let res = execute(deps.as_mut(), env.clone(), mock_info("keeper", &[]), sweep.clone()).unwrap();
+        assert_eq!(res.attributes[3], attr("rewarded", "0"));
+        assert_eq!(res.messages.len(), 1);
+
+        // an escrow old enough pays the keeper
+        let info = mock_info("source", &coins(50, "tokens"));
+        execute(deps.as_mut(), env.clone(), info, ExecuteMsg::CreateEscrow(create("old", env.block.height + 1))).unwrap();
+        env.block.height += 1;
+        env.block.time = env.block.time.plus_seconds(3600);
+        let res = execute(deps.as_mut(), env, mock_info("keeper", &[]), sweep).unwrap();
+        assert_eq!(res.attributes[3], attr("rewarded", "1"));
         assert_eq!(
-            res.messages[2],
-            SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: wl_token_2,
-                msg: to_binary(&send_msg).unwrap(),
-                funds: vec![]
-            }))
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send { to_address: "source".to_string(), amount: coins(50, "tokens") }),
+                SubMsg::new(BankMsg::Send { to_address: "keeper".to_string(), amount: coins(10, "tokens") }),
+            ]
         );
+        let config = query_config(deps.as_ref()).unwrap();
+        assert_eq!(config.fee_pool_native, coins(90, "tokens"));
     }
-
 }
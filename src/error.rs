@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -15,12 +15,162 @@ pub enum ContractError {
     #[error("Escrow is expired")]
     Expired {},
 
+    #[error("Escrow would already be expired at creation")]
+    AlreadyExpired {},
+
+    #[error("Set either an expiration or a duration, not both")]
+    InvalidExpiration {},
+
+    #[error("New expiration must be later than the current one")]
+    InvalidExtension {},
+
+    #[error("Legacy escrows {ids} have a height and a time bound, neither reached yet")]
+    AmbiguousExpiry { ids: String },
+
+    #[error("Funding deadline has passed")]
+    FundingClosed {},
+
     #[error("Send some coins to create an escrow")]
     EmptyBalance {},
 
     #[error("Escrow id already in use")]
     AlreadyInUse {},
 
+    #[error("Escrow id was used by a closed escrow whose records are kept")]
+    IdHasRecords {},
+
+    #[error("Template id already in use")]
+    TemplateAlreadyInUse {},
+
     #[error("Recipient is not set")]
     RecipientNotSet {},
+
+    #[error("Escrow has no release condition")]
+    NoCondition {},
+
+    #[error("Release condition is not met")]
+    ConditionNotMet {},
+
+    #[error("Escrow is not a stream")]
+    NotAStream {},
+
+    #[error("Stream needs a recipient, a positive amount per interval and a non zero interval in the unit of the expiration")]
+    InvalidStream {},
+
+    #[error("Stream is paused")]
+    StreamPaused {},
+
+    #[error("Stream is not paused")]
+    StreamNotPaused {},
+
+    #[error("No payout is due yet")]
+    NoPayoutDue {},
+
+    #[error("Swap terms must ask for a positive amount")]
+    InvalidTerms {},
+
+    #[error("Escrow is not an open swap offer")]
+    NotAnOpenOffer {},
+
+    #[error("Sent tokens are not the asked token")]
+    WrongAskToken {},
+
+    #[error("Underpayment: expected {expected}, received {received}")]
+    Underpayment { expected: Uint128, received: Uint128 },
+
+    #[error("Overpayment: expected {expected}, received {received}")]
+    Overpayment { expected: Uint128, received: Uint128 },
+
+    #[error("Fill too small: minimum {min_fill}, received {received}")]
+    FillTooSmall { min_fill: Uint128, received: Uint128 },
+
+    #[error("Arbiter fee must be positive and at most 10000 basis points")]
+    InvalidFee {},
+
+    #[error("Arbiter has not accepted the arbitration yet")]
+    ArbitrationPending {},
+
+    #[error("Arbitration is already accepted")]
+    ArbitrationAccepted {},
+
+    #[error("Arbiter is not registered")]
+    ArbiterNotRegistered {},
+
+    #[error("No arbiter transfer is pending")]
+    NoPendingTransfer {},
+
+    #[error("No claim assignment is pending")]
+    NoPendingAssignment {},
+
+    #[error("Claims that need the consent of the arbiter cannot be tokenized")]
+    ConsentWithReceipt {},
+
+    #[error("Escrow is not open to applications")]
+    NotOpenToApplications {},
+
+    #[error("Already applied to this escrow")]
+    AlreadyApplied {},
+
+    #[error("Sent funds must match the application deposit")]
+    WrongDeposit {},
+
+    #[error("Address did not apply to this escrow")]
+    NotAnApplicant {},
+
+    #[error("Escrow is not a prize pool")]
+    NotAPrizePool {},
+
+    #[error("Prize pools, swap terms, conditions, streams and applications cannot be combined")]
+    ConflictingModes {},
+
+    #[error("Winners must be distinct and their shares must exactly cover every token of the balance")]
+    InvalidShares {},
+
+    #[error("Arbiter is still active")]
+    ArbiterStillActive {},
+
+    #[error("Batch has no operations")]
+    EmptyBatch {},
+
+    #[error("Funds sent do not match the funds assigned to the batch operations")]
+    BatchFundsMismatch {},
+
+    #[error("Title is longer than {max} bytes")]
+    TitleTooLong { max: u32 },
+
+    #[error("Description is longer than {max} bytes")]
+    DescriptionTooLong { max: u32 },
+
+    #[error("More than {max} tags")]
+    TooManyTags { max: u32 },
+
+    #[error("Tags must be between 1 and {max} bytes long")]
+    InvalidTag { max: u32 },
+
+    #[error("External id must be between 1 and {max} bytes long")]
+    InvalidExternalId { max: u32 },
+
+    #[error("External id already in use")]
+    ExternalIdInUse {},
+
+    #[error("Content hash must be a hex SHA-256 digest or an alphanumeric IPFS CID")]
+    InvalidContentHash {},
+
+    #[error("Memos need a key id and between 1 and {max} bytes of ciphertext, one per party")]
+    InvalidMemo { max: u32 },
+
+    #[error("Messages must be between 1 and {max} bytes long")]
+    InvalidMessage { max: u32 },
+
+    #[error("Application memo is longer than {max} bytes")]
+    ApplicationMemoTooLong { max: u32 },
+
+    #[error("No receipt collection is configured")]
+    NoReceiptCollection {},
+
+    #[error("The escrow contract is not the minter of the receipt collection")]
+    NotReceiptMinter {},
+
+    #[error("The position is held by a receipt, transfer the receipt instead")]
+    TokenizedPosition {},
 }
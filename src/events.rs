@@ -0,0 +1,191 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{Addr, Coin, Event};
+use cw20::Cw20Coin;
+
+use crate::state::GenericBalance;
+
+/// Type of the custom events emitted by the escrow handlers. The chain prefixes
+/// them with "wasm-", so indexers see e.g. "wasm-escrow_created"
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// An escrow was created with the tokens of the source leg
+    EscrowCreated,
+    /// Tokens were added to a leg
+    EscrowFunded,
+    /// Tokens of a leg were paid to the counterparty: the source leg to the
+    /// recipient, a winner or a taker, the recipient leg to the source
+    EscrowReleased,
+    /// Tokens of a leg went back to their depositor
+    EscrowRefunded,
+    /// The arbiter was paid its fee out of the source leg
+    EscrowFeePaid,
+    /// A party, a deadline or the state of the escrow changed, see `change`
+    EscrowUpdated,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::EscrowCreated => "escrow_created",
+            EventKind::EscrowFunded => "escrow_funded",
+            EventKind::EscrowReleased => "escrow_released",
+            EventKind::EscrowRefunded => "escrow_refunded",
+            EventKind::EscrowFeePaid => "escrow_fee_paid",
+            EventKind::EscrowUpdated => "escrow_updated",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Leg {
+    /// The balance deposited by the source
+    Source,
+    /// The balance deposited by the recipient
+    Recipient,
+}
+
+impl Leg {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Leg::Source => "source",
+            Leg::Recipient => "recipient",
+        }
+    }
+}
+
+/// Attributes of every escrow event, in this order. Unset fields and empty token
+/// lists are left out. Native tokens are written as a coin list like
+/// "100ucosm,5uatom" and cw20 tokens as "address:amount" pairs separated by commas,
+/// amounts are always the full amounts moved
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct EscrowEvent {
+    /// Event type, not an attribute
+    pub kind: EventKind,
+    pub id: String,
+    /// Sender of the message that caused the event
+    pub actor: String,
+    /// Leg the tokens were taken from or added to
+    pub leg: Option<Leg>,
+    /// Previous party of an update
+    pub from: Option<String>,
+    /// Address the tokens went to, or the new party of an update
+    pub to: Option<String>,
+    /// What an update changed, e.g. "recipient_set" or "arbiter_transferred"
+    pub change: Option<String>,
+    pub native: Vec<Coin>,
+    pub cw20: Vec<Cw20Coin>,
+}
+
+impl EscrowEvent {
+    pub fn new(kind: EventKind, id: &str, actor: &Addr) -> Self {
+        EscrowEvent {
+            kind,
+            id: id.to_string(),
+            actor: actor.to_string(),
+            leg: None,
+            from: None,
+            to: None,
+            change: None,
+            native: vec![],
+            cw20: vec![],
+        }
+    }
+
+    /// Update of the escrow that moved no tokens
+    pub fn updated(id: &str, actor: &Addr, change: &str) -> Self {
+        EscrowEvent {
+            change: Some(change.to_string()),
+            ..EscrowEvent::new(EventKind::EscrowUpdated, id, actor)
+        }
+    }
+
+    /// Tokens of a leg moved to an address, None when there are none to report
+    pub fn tokens_moved(
+        kind: EventKind,
+        id: &str,
+        actor: &Addr,
+        leg: Leg,
+        to: &Addr,
+        balance: &GenericBalance,
+    ) -> Option<Event> {
+        if balance.is_empty() {
+            return None;
+        }
+        let event = EscrowEvent::new(kind, id, actor)
+            .leg(leg)
+            .to(to)
+            .tokens(balance);
+        Some(event.into())
+    }
+
+    pub fn leg(mut self, leg: Leg) -> Self {
+        self.leg = Some(leg);
+        self
+    }
+
+    pub fn replaced(mut self, from: &Addr) -> Self {
+        self.from = Some(from.to_string());
+        self
+    }
+
+    pub fn to(mut self, to: &Addr) -> Self {
+        self.to = Some(to.to_string());
+        self
+    }
+
+    pub fn tokens(mut self, balance: &GenericBalance) -> Self {
+        self.native = balance
+            .native
+            .iter()
+            .filter(|c| !c.amount.is_zero())
+            .cloned()
+            .collect();
+        self.cw20 = balance
+            .cw20
+            .iter()
+            .filter(|c| !c.amount.is_zero())
+            .map(|c| Cw20Coin {
+                address: c.address.to_string(),
+                amount: c.amount,
+            })
+            .collect();
+        self
+    }
+}
+
+impl From<EscrowEvent> for Event {
+    fn from(event: EscrowEvent) -> Self {
+        let mut res = Event::new(event.kind.as_str())
+            .add_attribute("id", event.id)
+            .add_attribute("actor", event.actor);
+        if let Some(leg) = event.leg {
+            res = res.add_attribute("leg", leg.as_str());
+        }
+        if let Some(from) = event.from {
+            res = res.add_attribute("from", from);
+        }
+        if let Some(to) = event.to {
+            res = res.add_attribute("to", to);
+        }
+        if let Some(change) = event.change {
+            res = res.add_attribute("change", change);
+        }
+        if !event.native.is_empty() {
+            let native: Vec<String> = event.native.iter().map(|c| c.to_string()).collect();
+            res = res.add_attribute("native", native.join(","));
+        }
+        if !event.cw20.is_empty() {
+            let cw20: Vec<String> = event
+                .cw20
+                .iter()
+                .map(|c| format!("{}:{}", c.address, c.amount))
+                .collect();
+            res = res.add_attribute("cw20", cw20.join(","));
+        }
+        res
+    }
+}
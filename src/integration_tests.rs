@@ -0,0 +1,1105 @@
+use cosmwasm_std::{
+    coin, coins, to_binary, to_vec, Addr, Binary, CosmosMsg, Deps, DepsMut, Empty, Env, Event,
+    MessageInfo, Order, Response, StdResult, Uint128, WasmMsg,
+};
+use cw20::{BalanceResponse, Cw20Coin, Cw20ExecuteMsg, Cw20QueryMsg, Denom};
+use cw3_fixed_multisig::msg::Voter;
+use cw_multi_test::{App, Contract, ContractWrapper, Executor};
+use cw_storage_plus::Item;
+use cw_utils::{Duration, Expiration, Threshold};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::msg::{
+    ArbiterFeeMsg, ArbiterQueryMsg, ConditionMsg, ConfigResponse, CreateMsg, Decision,
+    DetailsResponse, EscrowOp, ExecuteMsg, InstantiateMsg, IsAuthorizedResponse, ListResponse,
+    QueryMsg, ReceiveMsg,
+};
+use crate::state::{Authorization, Comparison, Terms};
+use crate::ContractError;
+
+const SOURCE: &str = "source";
+const ARBITER: &str = "arbiter";
+const RECIPIENT: &str = "recipient";
+const DENOM: &str = "ucosm";
+
+fn escrow_contract() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new(
+        crate::contract::execute,
+        crate::contract::instantiate,
+        crate::contract::query,
+    );
+    Box::new(contract)
+}
+
+fn mock_app() -> App {
+    App::new(|router, _, storage| {
+        router
+            .bank
+            .init_balance(storage, &Addr::unchecked(SOURCE), coins(1000, DENOM))
+            .unwrap();
+    })
+}
+
+fn instantiate_escrow(app: &mut App) -> Addr {
+    let code_id = app.store_code(escrow_contract());
+    app.instantiate_contract(
+        code_id,
+        Addr::unchecked(ARBITER),
+        &InstantiateMsg {},
+        &[],
+        "escrow",
+        None,
+    )
+    .unwrap()
+}
+
+fn balance(app: &App, addr: &str) -> Uint128 {
+    app.wrap().query_balance(addr, DENOM).unwrap().amount
+}
+
+/// cw721-base as deployed, with its default extension
+mod collection {
+    use super::*;
+    use cw721_base::{ContractError, Cw721Contract, Extension, QueryMsg};
+
+    type Collection<'a> = Cw721Contract<'a, Extension, Empty>;
+
+    fn instantiate(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        msg: cw721_base::InstantiateMsg,
+    ) -> StdResult<Response> {
+        Collection::default().instantiate(deps, env, info, msg)
+    }
+
+    fn execute(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        msg: cw721_base::ExecuteMsg<Extension>,
+    ) -> Result<Response, ContractError> {
+        Collection::default().execute(deps, env, info, msg)
+    }
+
+    fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+        Collection::default().query(deps, env, msg)
+    }
+
+    pub fn contract() -> Box<dyn Contract<Empty>> {
+        Box::new(ContractWrapper::new(execute, instantiate, query))
+    }
+}
+
+/// Deploys a receipt collection minted by `minter`
+fn instantiate_collection(app: &mut App, minter: &Addr) -> Addr {
+    let code_id = app.store_code(collection::contract());
+    let msg = cw721_base::InstantiateMsg {
+        name: "Escrow receipts".to_string(),
+        symbol: "RECEIPT".to_string(),
+        minter: minter.to_string(),
+    };
+    app.instantiate_contract(
+        code_id,
+        Addr::unchecked(ARBITER),
+        &msg,
+        &[],
+        "receipts",
+        None,
+    )
+    .unwrap()
+}
+
+fn cw20_contract() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new(
+        cw20_base::contract::execute,
+        cw20_base::contract::instantiate,
+        cw20_base::contract::query,
+    );
+    Box::new(contract)
+}
+
+/// Instantiates a cw20 token with initial balances
+fn instantiate_cw20(app: &mut App, balances: Vec<(&str, u128)>) -> Addr {
+    let code_id = app.store_code(cw20_contract());
+    let msg = cw20_base::msg::InstantiateMsg {
+        name: "Stable".to_string(),
+        symbol: "USD".to_string(),
+        decimals: 6,
+        initial_balances: balances
+            .into_iter()
+            .map(|(address, amount)| Cw20Coin {
+                address: address.to_string(),
+                amount: Uint128::new(amount),
+            })
+            .collect(),
+        mint: None,
+        marketing: None,
+    };
+    app.instantiate_contract(code_id, Addr::unchecked(ARBITER), &msg, &[], "usd", None)
+        .unwrap()
+}
+
+fn cw20_balance(app: &App, token: &Addr, addr: &str) -> Uint128 {
+    let res: BalanceResponse = app
+        .wrap()
+        .query_wasm_smart(
+            token,
+            &Cw20QueryMsg::Balance {
+                address: addr.to_string(),
+            },
+        )
+        .unwrap();
+    res.balance
+}
+
+mod oracle {
+    use super::*;
+
+    /// Answer published by the mock oracle, returned as is to any query
+    const ANSWER: Item<Uint128> = Item::new("answer");
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    #[serde(rename_all = "snake_case")]
+    pub enum OracleMsg {
+        Publish { answer: Uint128 },
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    #[serde(rename_all = "snake_case")]
+    pub enum OracleQuery {
+        Price {},
+    }
+
+    fn instantiate(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        msg: OracleMsg,
+    ) -> StdResult<Response> {
+        execute(deps, env, info, msg)
+    }
+
+    fn execute(
+        deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        msg: OracleMsg,
+    ) -> StdResult<Response> {
+        match msg {
+            OracleMsg::Publish { answer } => ANSWER.save(deps.storage, &answer)?,
+        }
+        Ok(Response::new())
+    }
+
+    fn query(deps: Deps, _env: Env, msg: OracleQuery) -> StdResult<Binary> {
+        match msg {
+            OracleQuery::Price {} => to_binary(&ANSWER.load(deps.storage)?),
+        }
+    }
+
+    pub fn contract() -> Box<dyn Contract<Empty>> {
+        Box::new(ContractWrapper::new(execute, instantiate, query))
+    }
+}
+
+/// cw3-fixed-multisig as arbiter. The cw3 spec has no query for escrow decisions, so
+/// queries of the escrow are answered from the stored proposals: a decision is
+/// authorized once a proposal describing it for the escrow and its nonce has passed
+mod multisig {
+    use super::*;
+    use cw3::{Status, Vote};
+    use cw3_fixed_multisig::state::PROPOSALS;
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    #[serde(untagged)]
+    pub enum MultisigQuery {
+        Arbiter(ArbiterQueryMsg),
+        Cw3(cw3_fixed_multisig::msg::QueryMsg),
+    }
+
+    fn query(deps: Deps, env: Env, msg: MultisigQuery) -> StdResult<Binary> {
+        match msg {
+            MultisigQuery::Arbiter(query) => {
+                let wanted = description(&query)?;
+                let mut authorized = false;
+                for item in PROPOSALS.range(deps.storage, None, None, Order::Ascending) {
+                    let (_, proposal) = item?;
+                    authorized |= proposal.description == wanted
+                        && proposal.current_status(&env.block) == Status::Passed;
+                }
+                to_binary(&IsAuthorizedResponse { authorized })
+            }
+            MultisigQuery::Cw3(msg) => cw3_fixed_multisig::contract::query(deps, env, msg),
+        }
+    }
+
+    /// Description of the proposal authorizing the queried decision
+    fn description(query: &ArbiterQueryMsg) -> StdResult<String> {
+        Ok(String::from_utf8_lossy(&to_vec(query)?).into_owned())
+    }
+
+    /// Proposal authorizing the decision on the escrow for its current nonce
+    pub fn authorize(
+        id: &str,
+        nonce: u64,
+        decision: Decision,
+    ) -> cw3_fixed_multisig::msg::ExecuteMsg {
+        let query = ArbiterQueryMsg::IsAuthorized {
+            id: id.to_string(),
+            nonce,
+            decision,
+        };
+        cw3_fixed_multisig::msg::ExecuteMsg::Propose {
+            title: "escrow".to_string(),
+            description: description(&query).unwrap(),
+            msgs: vec![],
+            latest: None,
+        }
+    }
+
+    pub fn contract() -> Box<dyn Contract<Empty>> {
+        Box::new(ContractWrapper::new(
+            cw3_fixed_multisig::contract::execute,
+            cw3_fixed_multisig::contract::instantiate,
+            query,
+        ))
+    }
+
+    pub fn propose(msgs: Vec<CosmosMsg>) -> cw3_fixed_multisig::msg::ExecuteMsg {
+        cw3_fixed_multisig::msg::ExecuteMsg::Propose {
+            title: "escrow".to_string(),
+            description: "escrow decision".to_string(),
+            msgs,
+            latest: None,
+        }
+    }
+
+    pub fn vote(proposal_id: u64) -> cw3_fixed_multisig::msg::ExecuteMsg {
+        cw3_fixed_multisig::msg::ExecuteMsg::Vote {
+            proposal_id,
+            vote: Vote::Yes,
+        }
+    }
+}
+
+/// Deploys a 2 of 3 multisig
+fn instantiate_multisig(app: &mut App) -> Addr {
+    let code_id = app.store_code(multisig::contract());
+    let voter = |addr: &str| Voter {
+        addr: addr.to_string(),
+        weight: 1,
+    };
+    let msg = cw3_fixed_multisig::msg::InstantiateMsg {
+        voters: vec![voter("alice"), voter("bob"), voter("carol")],
+        threshold: Threshold::AbsoluteCount { weight: 2 },
+        max_voting_period: Duration::Height(100),
+    };
+    app.instantiate_contract(
+        code_id,
+        Addr::unchecked("alice"),
+        &msg,
+        &[],
+        "multisig",
+        None,
+    )
+    .unwrap()
+}
+
+/// Proposes as alice and passes the proposal with the vote of bob
+fn pass_proposal(
+    app: &mut App,
+    multisig: &Addr,
+    proposal_id: u64,
+    propose: cw3_fixed_multisig::msg::ExecuteMsg,
+) {
+    app.execute_contract(Addr::unchecked("alice"), multisig.clone(), &propose, &[])
+        .unwrap();
+    let vote = multisig::vote(proposal_id);
+    app.execute_contract(Addr::unchecked("bob"), multisig.clone(), &vote, &[])
+        .unwrap();
+}
+
+/// Call of the escrow made by the multisig
+fn escrow_call(escrow: &Addr, msg: &ExecuteMsg) -> CosmosMsg {
+    WasmMsg::Execute {
+        contract_addr: escrow.to_string(),
+        msg: to_binary(msg).unwrap(),
+        funds: vec![],
+    }
+    .into()
+}
+
+fn create_with_condition(app: &mut App, escrow: &Addr, oracle: &Addr, end_height: u64) {
+    let create = CreateMsg {
+        id: "conditional".to_string(),
+        arbiter: ARBITER.to_string(),
+        recipient: Some(RECIPIENT.to_string()),
+        title: "price trigger".to_string(),
+        description: "released once the price reaches 100".to_string(),
+        expires: Some(Expiration::AtHeight(end_height)),
+        condition: Some(ConditionMsg {
+            oracle: oracle.to_string(),
+            query: to_binary(&oracle::OracleQuery::Price {}).unwrap(),
+            comparison: Comparison::AtLeast(Uint128::new(100)),
+        }),
+        ..Default::default()
+    };
+    app.execute_contract(
+        Addr::unchecked(SOURCE),
+        escrow.clone(),
+        &ExecuteMsg::CreateEscrow(create),
+        &coins(400, DENOM),
+    )
+    .unwrap();
+}
+
+#[test]
+fn check_condition_releases_when_oracle_condition_is_met() {
+    let mut app = mock_app();
+    let escrow = instantiate_escrow(&mut app);
+    let oracle_id = app.store_code(oracle::contract());
+    let oracle = app
+        .instantiate_contract(
+            oracle_id,
+            Addr::unchecked(ARBITER),
+            &oracle::OracleMsg::Publish {
+                answer: Uint128::new(90),
+            },
+            &[],
+            "oracle",
+            None,
+        )
+        .unwrap();
+
+    let end_height = app.block_info().height + 100;
+    create_with_condition(&mut app, &escrow, &oracle, end_height);
+
+    // nothing is released before the arbiter accepted the escrow
+    let check = ExecuteMsg::CheckCondition {
+        id: "conditional".to_string(),
+    };
+    let err = app
+        .execute_contract(Addr::unchecked("keeper"), escrow.clone(), &check, &[])
+        .unwrap_err();
+    assert_eq!(
+        ContractError::ArbitrationPending {},
+        err.downcast().unwrap()
+    );
+    app.execute_contract(
+        Addr::unchecked(ARBITER),
+        escrow.clone(),
+        &ExecuteMsg::AcceptArbitration {
+            id: "conditional".to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+
+    // price is still below the threshold
+    let err = app
+        .execute_contract(Addr::unchecked("keeper"), escrow.clone(), &check, &[])
+        .unwrap_err();
+    assert_eq!(ContractError::ConditionNotMet {}, err.downcast().unwrap());
+
+    // oracle publishes a price above the threshold, anyone can settle
+    app.execute_contract(
+        Addr::unchecked(ARBITER),
+        oracle,
+        &oracle::OracleMsg::Publish {
+            answer: Uint128::new(105),
+        },
+        &[],
+    )
+    .unwrap();
+    let res = app
+        .execute_contract(Addr::unchecked("keeper"), escrow.clone(), &check, &[])
+        .unwrap();
+    res.assert_event(
+        &Event::new("wasm-escrow_released")
+            .add_attribute("id", "conditional")
+            .add_attribute("actor", "keeper")
+            .add_attribute("leg", "source")
+            .add_attribute("to", RECIPIENT)
+            .add_attribute("native", format!("400{}", DENOM)),
+    );
+
+    assert_eq!(balance(&app, RECIPIENT), Uint128::new(400));
+    assert_eq!(balance(&app, escrow.as_str()), Uint128::zero());
+}
+
+#[test]
+fn check_condition_returns_the_recipient_leg_to_the_source() {
+    let mut app = mock_app();
+    app.init_modules(|router, _, storage| {
+        router
+            .bank
+            .init_balance(storage, &Addr::unchecked(RECIPIENT), coins(7, "btc"))
+            .unwrap();
+    });
+    let escrow = instantiate_escrow(&mut app);
+    let oracle_id = app.store_code(oracle::contract());
+    let oracle = app
+        .instantiate_contract(
+            oracle_id,
+            Addr::unchecked(ARBITER),
+            &oracle::OracleMsg::Publish {
+                answer: Uint128::new(105),
+            },
+            &[],
+            "oracle",
+            None,
+        )
+        .unwrap();
+
+    let end_height = app.block_info().height + 100;
+    create_with_condition(&mut app, &escrow, &oracle, end_height);
+    let id = "conditional".to_string();
+    app.execute_contract(
+        Addr::unchecked(ARBITER),
+        escrow.clone(),
+        &ExecuteMsg::AcceptArbitration { id: id.clone() },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        Addr::unchecked(RECIPIENT),
+        escrow.clone(),
+        &ExecuteMsg::TopUpRecip { id: id.clone() },
+        &coins(7, "btc"),
+    )
+    .unwrap();
+
+    // the release pays both legs and leaves nothing behind
+    app.execute_contract(
+        Addr::unchecked("keeper"),
+        escrow.clone(),
+        &ExecuteMsg::CheckCondition { id },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(balance(&app, RECIPIENT), Uint128::new(400));
+    let btc = |addr: &str| app.wrap().query_balance(addr, "btc").unwrap().amount;
+    assert_eq!(btc(SOURCE), Uint128::new(7));
+    assert_eq!(btc(escrow.as_str()), Uint128::zero());
+}
+
+#[test]
+fn check_condition_pays_the_arbiter_fee() {
+    let mut app = mock_app();
+    let escrow = instantiate_escrow(&mut app);
+    let oracle_id = app.store_code(oracle::contract());
+    let oracle = app
+        .instantiate_contract(
+            oracle_id,
+            Addr::unchecked(ARBITER),
+            &oracle::OracleMsg::Publish {
+                answer: Uint128::new(105),
+            },
+            &[],
+            "oracle",
+            None,
+        )
+        .unwrap();
+
+    // the arbiter takes 2.5% of the source leg, as it would on approval
+    let create = CreateMsg {
+        id: "conditional".to_string(),
+        arbiter: ARBITER.to_string(),
+        recipient: Some(RECIPIENT.to_string()),
+        title: "price trigger".to_string(),
+        description: "released once the price reaches 100".to_string(),
+        condition: Some(ConditionMsg {
+            oracle: oracle.to_string(),
+            query: to_binary(&oracle::OracleQuery::Price {}).unwrap(),
+            comparison: Comparison::AtLeast(Uint128::new(100)),
+        }),
+        arbiter_fee: Some(ArbiterFeeMsg::Bps(250)),
+        ..Default::default()
+    };
+    app.execute_contract(
+        Addr::unchecked(SOURCE),
+        escrow.clone(),
+        &ExecuteMsg::CreateEscrow(create),
+        &coins(400, DENOM),
+    )
+    .unwrap();
+    let id = "conditional".to_string();
+    app.execute_contract(
+        Addr::unchecked(ARBITER),
+        escrow.clone(),
+        &ExecuteMsg::AcceptArbitration { id: id.clone() },
+        &[],
+    )
+    .unwrap();
+
+    let res = app
+        .execute_contract(
+            Addr::unchecked("keeper"),
+            escrow.clone(),
+            &ExecuteMsg::CheckCondition { id },
+            &[],
+        )
+        .unwrap();
+    res.assert_event(
+        &Event::new("wasm-escrow_fee_paid")
+            .add_attribute("id", "conditional")
+            .add_attribute("actor", "keeper")
+            .add_attribute("leg", "source")
+            .add_attribute("to", ARBITER)
+            .add_attribute("native", format!("10{}", DENOM)),
+    );
+    assert_eq!(balance(&app, ARBITER), Uint128::new(10));
+    assert_eq!(balance(&app, RECIPIENT), Uint128::new(390));
+    assert_eq!(balance(&app, escrow.as_str()), Uint128::zero());
+}
+
+#[test]
+fn check_condition_refunds_expired_escrow() {
+    let mut app = mock_app();
+    let escrow = instantiate_escrow(&mut app);
+    let oracle_id = app.store_code(oracle::contract());
+    let oracle = app
+        .instantiate_contract(
+            oracle_id,
+            Addr::unchecked(ARBITER),
+            &oracle::OracleMsg::Publish {
+                answer: Uint128::new(10),
+            },
+            &[],
+            "oracle",
+            None,
+        )
+        .unwrap();
+
+    let end_height = app.block_info().height + 10;
+    create_with_condition(&mut app, &escrow, &oracle, end_height);
+    assert_eq!(balance(&app, SOURCE), Uint128::new(600));
+
+    app.update_block(|block| block.height += 11);
+    let res = app
+        .execute_contract(
+            Addr::unchecked("keeper"),
+            escrow,
+            &ExecuteMsg::CheckCondition {
+                id: "conditional".to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+    res.assert_event(
+        &Event::new("wasm-escrow_refunded")
+            .add_attribute("id", "conditional")
+            .add_attribute("actor", "keeper")
+            .add_attribute("leg", "source")
+            .add_attribute("to", SOURCE)
+            .add_attribute("native", format!("400{}", DENOM)),
+    );
+
+    assert_eq!(balance(&app, SOURCE), Uint128::new(1000));
+    assert_eq!(balance(&app, RECIPIENT), Uint128::zero());
+}
+
+#[test]
+fn batch_is_all_or_nothing() {
+    let mut app = mock_app();
+    let escrow = instantiate_escrow(&mut app);
+
+    let create = CreateMsg {
+        id: "batched".to_string(),
+        arbiter: SOURCE.to_string(),
+        recipient: Some(RECIPIENT.to_string()),
+        title: "batched".to_string(),
+        description: "created in a batch".to_string(),
+        ..Default::default()
+    };
+    let ops = vec![
+        EscrowOp::Create {
+            escrow: create,
+            funds: coins(300, DENOM),
+        },
+        EscrowOp::Approve {
+            id: "missing".to_string(),
+        },
+    ];
+    app.execute_contract(
+        Addr::unchecked(SOURCE),
+        escrow.clone(),
+        &ExecuteMsg::Batch { ops },
+        &coins(300, DENOM),
+    )
+    .unwrap_err();
+
+    // the escrow created by the first operation was rolled back with the funds
+    let list: ListResponse = app
+        .wrap()
+        .query_wasm_smart(escrow.clone(), &QueryMsg::List {})
+        .unwrap();
+    assert!(list.escrows.is_empty());
+    assert_eq!(balance(&app, SOURCE), Uint128::new(1000));
+    assert_eq!(balance(&app, escrow.as_str()), Uint128::zero());
+}
+
+#[test]
+fn take_settles_offer_paid_in_cw20() {
+    let mut app = mock_app();
+    let escrow = instantiate_escrow(&mut app);
+    let usd = instantiate_cw20(&mut app, vec![("taker", 1000)]);
+
+    // source offers 400 native tokens for 250 USD
+    let create = CreateMsg {
+        id: "otc".to_string(),
+        arbiter: ARBITER.to_string(),
+        title: "otc".to_string(),
+        description: "native for USD".to_string(),
+        terms: Some(Terms {
+            ask: Denom::Cw20(usd.clone()),
+            ask_amount: Uint128::new(250),
+            min_fill: None,
+        }),
+        ..Default::default()
+    };
+    app.execute_contract(
+        Addr::unchecked(SOURCE),
+        escrow.clone(),
+        &ExecuteMsg::CreateEscrow(create),
+        &coins(400, DENOM),
+    )
+    .unwrap();
+    app.execute_contract(
+        Addr::unchecked(ARBITER),
+        escrow.clone(),
+        &ExecuteMsg::AcceptArbitration {
+            id: "otc".to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+
+    let take = |amount: u128| Cw20ExecuteMsg::Send {
+        contract: escrow.to_string(),
+        amount: Uint128::new(amount),
+        msg: to_binary(&ReceiveMsg::Take {
+            id: "otc".to_string(),
+        })
+        .unwrap(),
+    };
+    let err = app
+        .execute_contract(Addr::unchecked("taker"), usd.clone(), &take(200), &[])
+        .unwrap_err();
+    assert_eq!(
+        ContractError::Underpayment {
+            expected: Uint128::new(250),
+            received: Uint128::new(200),
+        },
+        err.downcast().unwrap()
+    );
+
+    app.execute_contract(Addr::unchecked("taker"), usd.clone(), &take(250), &[])
+        .unwrap();
+    assert_eq!(balance(&app, "taker"), Uint128::new(400));
+    assert_eq!(cw20_balance(&app, &usd, SOURCE), Uint128::new(250));
+    assert_eq!(cw20_balance(&app, &usd, "taker"), Uint128::new(750));
+    assert_eq!(balance(&app, escrow.as_str()), Uint128::zero());
+}
+
+#[test]
+fn sweep_expired_refunds_and_pays_keeper_bounty() {
+    let mut app = mock_app();
+    let escrow = instantiate_escrow(&mut app);
+
+    // the instantiating address is the admin
+    app.execute_contract(
+        Addr::unchecked(ARBITER),
+        escrow.clone(),
+        &ExecuteMsg::UpdateConfig {
+            keeper_bounty: Some(coin(5, DENOM)),
+            bounty_min_age: None,
+            acceptance_window: None,
+            require_registered_arbiter: None,
+            metadata_limits: None,
+            receipt_collection: None,
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        Addr::unchecked(SOURCE),
+        escrow.clone(),
+        &ExecuteMsg::FundFeePool {},
+        &coins(8, DENOM),
+    )
+    .unwrap();
+
+    let height = app.block_info().height;
+    let escrows = vec![
+        ("soon", Some(Expiration::AtHeight(height + 5)), None),
+        (
+            "grace",
+            Some(Expiration::AtHeight(height + 5)),
+            Some(Duration::Height(10)),
+        ),
+        ("never", None, None),
+    ];
+    for (id, expires, grace_period) in escrows {
+        let create = CreateMsg {
+            id: id.to_string(),
+            arbiter: ARBITER.to_string(),
+            recipient: Some(RECIPIENT.to_string()),
+            title: id.to_string(),
+            description: "sweepable".to_string(),
+            expires,
+            grace_period,
+            ..Default::default()
+        };
+        app.execute_contract(
+            Addr::unchecked(SOURCE),
+            escrow.clone(),
+            &ExecuteMsg::CreateEscrow(create),
+            &coins(100, DENOM),
+        )
+        .unwrap();
+    }
+    assert_eq!(balance(&app, SOURCE), Uint128::new(692));
+
+    // the escrows are a day old, old enough to earn the bounty
+    let sweep = ExecuteMsg::SweepExpired { limit: None };
+    app.update_block(|block| {
+        block.height += 8;
+        block.time = block.time.plus_seconds(24 * 60 * 60);
+    });
+    app.execute_contract(Addr::unchecked("keeper"), escrow.clone(), &sweep, &[])
+        .unwrap();
+    assert_eq!(balance(&app, SOURCE), Uint128::new(792));
+    assert_eq!(balance(&app, "keeper"), Uint128::new(5));
+
+    // past its grace period, the pool only has 3 left for the bounty
+    app.update_block(|block| block.height += 8);
+    app.execute_contract(Addr::unchecked("keeper"), escrow.clone(), &sweep, &[])
+        .unwrap();
+    assert_eq!(balance(&app, SOURCE), Uint128::new(892));
+    assert_eq!(balance(&app, "keeper"), Uint128::new(8));
+
+    let list: ListResponse = app
+        .wrap()
+        .query_wasm_smart(escrow.clone(), &QueryMsg::List {})
+        .unwrap();
+    assert_eq!(list.escrows, vec!["never".to_string()]);
+    let config: ConfigResponse = app
+        .wrap()
+        .query_wasm_smart(escrow, &QueryMsg::Config {})
+        .unwrap();
+    assert!(config.fee_pool_native.is_empty());
+}
+
+#[test]
+fn multisig_arbiter_executes_decisions() {
+    let mut app = mock_app();
+    let escrow = instantiate_escrow(&mut app);
+    let multisig = instantiate_multisig(&mut app);
+
+    let create = CreateMsg {
+        id: "dao".to_string(),
+        arbiter: multisig.to_string(),
+        title: "dao arbitrated".to_string(),
+        description: "decided by proposals".to_string(),
+        ..Default::default()
+    };
+    app.execute_contract(
+        Addr::unchecked(SOURCE),
+        escrow.clone(),
+        &ExecuteMsg::CreateEscrow(create),
+        &coins(300, DENOM),
+    )
+    .unwrap();
+
+    // the multisig sets the recipient and approves in a single proposal
+    let msgs = vec![
+        escrow_call(
+            &escrow,
+            &ExecuteMsg::SetRecipient {
+                id: "dao".to_string(),
+                recipient: RECIPIENT.to_string(),
+            },
+        ),
+        escrow_call(
+            &escrow,
+            &ExecuteMsg::Approve {
+                id: "dao".to_string(),
+            },
+        ),
+    ];
+    pass_proposal(&mut app, &multisig, 1, multisig::propose(msgs));
+
+    // members alone cannot decide for the multisig
+    let approve = ExecuteMsg::Approve {
+        id: "dao".to_string(),
+    };
+    let err = app
+        .execute_contract(Addr::unchecked("alice"), escrow.clone(), &approve, &[])
+        .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err.downcast().unwrap());
+
+    let execute = cw3_fixed_multisig::msg::ExecuteMsg::Execute { proposal_id: 1 };
+    app.execute_contract(Addr::unchecked("carol"), multisig.clone(), &execute, &[])
+        .unwrap();
+    assert_eq!(balance(&app, RECIPIENT), Uint128::new(300));
+    assert_eq!(balance(&app, SOURCE), Uint128::new(700));
+}
+
+#[test]
+fn query_authorization_asks_the_arbiter_contract() {
+    let mut app = mock_app();
+    let escrow = instantiate_escrow(&mut app);
+    let multisig = instantiate_multisig(&mut app);
+
+    let create = CreateMsg {
+        id: "queried".to_string(),
+        arbiter: multisig.to_string(),
+        title: "dao arbitrated".to_string(),
+        description: "anyone executes authorized decisions".to_string(),
+        authorization: Some(Authorization::Query),
+        ..Default::default()
+    };
+    app.execute_contract(
+        Addr::unchecked(SOURCE),
+        escrow.clone(),
+        &ExecuteMsg::CreateEscrow(create),
+        &coins(300, DENOM),
+    )
+    .unwrap();
+
+    // nothing is authorized until a proposal passes
+    let set_recipient = |recipient: &str| ExecuteMsg::SetRecipient {
+        id: "queried".to_string(),
+        recipient: recipient.to_string(),
+    };
+    let err = app
+        .execute_contract(
+            Addr::unchecked(RECIPIENT),
+            escrow.clone(),
+            &set_recipient(RECIPIENT),
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err.downcast().unwrap());
+
+    // a passed proposal authorizes the decision for the current nonce only
+    let nonce = |app: &App| -> u64 {
+        let details: DetailsResponse = app
+            .wrap()
+            .query_wasm_smart(
+                &escrow,
+                &QueryMsg::Details {
+                    id: "queried".to_string(),
+                },
+            )
+            .unwrap();
+        details.decision_nonce
+    };
+    let decision = |recipient: &str| Decision::SetRecipient {
+        recipient: recipient.to_string(),
+    };
+    let authorize = multisig::authorize("queried", nonce(&app), decision(RECIPIENT));
+    pass_proposal(&mut app, &multisig, 1, authorize);
+    app.execute_contract(
+        Addr::unchecked(RECIPIENT),
+        escrow.clone(),
+        &set_recipient(RECIPIENT),
+        &[],
+    )
+    .unwrap();
+    let authorize = multisig::authorize("queried", nonce(&app), decision("other"));
+    pass_proposal(&mut app, &multisig, 2, authorize);
+    app.execute_contract(
+        Addr::unchecked("other"),
+        escrow.clone(),
+        &set_recipient("other"),
+        &[],
+    )
+    .unwrap();
+    let details: DetailsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &escrow,
+            &QueryMsg::Details {
+                id: "queried".to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(details.recipient, Some("other".to_string()));
+    assert_eq!(details.authorization, Authorization::Query);
+
+    // the first decision cannot be replayed
+    let err = app
+        .execute_contract(
+            Addr::unchecked(RECIPIENT),
+            escrow.clone(),
+            &set_recipient(RECIPIENT),
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err.downcast().unwrap());
+
+    // an authorized approval does not authorize a refund
+    let refund = ExecuteMsg::Refund {
+        id: "queried".to_string(),
+    };
+    let authorize = multisig::authorize("queried", nonce(&app), Decision::Approve {});
+    pass_proposal(&mut app, &multisig, 3, authorize);
+    let err = app
+        .execute_contract(Addr::unchecked(SOURCE), escrow.clone(), &refund, &[])
+        .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err.downcast().unwrap());
+
+    let approve = ExecuteMsg::Approve {
+        id: "queried".to_string(),
+    };
+    app.execute_contract(Addr::unchecked("other"), escrow, &approve, &[])
+        .unwrap();
+    assert_eq!(balance(&app, "other"), Uint128::new(300));
+}
+
+#[test]
+fn receipts_are_minted_in_a_cw721_collection() {
+    let mut app = mock_app();
+    let escrow = instantiate_escrow(&mut app);
+
+    // the collection must be minted by the escrow contract
+    let update = |collection: &Addr| ExecuteMsg::UpdateConfig {
+        keeper_bounty: None,
+        bounty_min_age: None,
+        acceptance_window: None,
+        require_registered_arbiter: None,
+        metadata_limits: None,
+        receipt_collection: Some(collection.to_string()),
+    };
+    let foreign = instantiate_collection(&mut app, &Addr::unchecked(ARBITER));
+    let err = app
+        .execute_contract(
+            Addr::unchecked(ARBITER),
+            escrow.clone(),
+            &update(&foreign),
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(ContractError::NotReceiptMinter {}, err.downcast().unwrap());
+    let collection = instantiate_collection(&mut app, &escrow);
+    app.execute_contract(
+        Addr::unchecked(ARBITER),
+        escrow.clone(),
+        &update(&collection),
+        &[],
+    )
+    .unwrap();
+
+    let create = CreateMsg {
+        id: "tokenized".to_string(),
+        arbiter: ARBITER.to_string(),
+        recipient: Some(RECIPIENT.to_string()),
+        title: "tokenized".to_string(),
+        description: "positions are nfts".to_string(),
+        recipient_receipt: true,
+        source_receipt: true,
+        ..Default::default()
+    };
+    app.execute_contract(
+        Addr::unchecked(SOURCE),
+        escrow.clone(),
+        &ExecuteMsg::CreateEscrow(create),
+        &coins(400, DENOM),
+    )
+    .unwrap();
+    let details = |app: &App| -> DetailsResponse {
+        app.wrap()
+            .query_wasm_smart(
+                &escrow,
+                &QueryMsg::Details {
+                    id: "tokenized".to_string(),
+                },
+            )
+            .unwrap()
+    };
+    let minted = details(&app);
+    assert_eq!(minted.receipt_collection, Some(collection.to_string()));
+    assert_eq!(minted.source_token_id, Some("1".to_string()));
+    assert_eq!(minted.recipient_token_id, Some("2".to_string()));
+    let owner: cw721::OwnerOfResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &collection,
+            &cw721_base::QueryMsg::OwnerOf {
+                token_id: "2".to_string(),
+                include_expired: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(owner.owner, RECIPIENT);
+
+    // a marketplace approved for the claim sells it, the buyer becomes the recipient
+    let approve = cw721_base::ExecuteMsg::<cw721_base::Extension>::Approve {
+        spender: "market".to_string(),
+        token_id: "2".to_string(),
+        expires: None,
+    };
+    app.execute_contract(
+        Addr::unchecked(RECIPIENT),
+        collection.clone(),
+        &approve,
+        &[],
+    )
+    .unwrap();
+    let transfer = cw721_base::ExecuteMsg::<cw721_base::Extension>::TransferNft {
+        recipient: "buyer".to_string(),
+        token_id: "2".to_string(),
+    };
+    app.execute_contract(
+        Addr::unchecked("market"),
+        collection.clone(),
+        &transfer,
+        &[],
+    )
+    .unwrap();
+    assert_eq!(details(&app).recipient, Some("buyer".to_string()));
+
+    // the position only moves with its receipt
+    let assign = ExecuteMsg::AssignClaim {
+        id: "tokenized".to_string(),
+        new_recipient: "other".to_string(),
+    };
+    let err = app
+        .execute_contract(Addr::unchecked("buyer"), escrow.clone(), &assign, &[])
+        .unwrap_err();
+    assert_eq!(ContractError::TokenizedPosition {}, err.downcast().unwrap());
+    let set_recipient = ExecuteMsg::SetRecipient {
+        id: "tokenized".to_string(),
+        recipient: "other".to_string(),
+    };
+    let err = app
+        .execute_contract(
+            Addr::unchecked(ARBITER),
+            escrow.clone(),
+            &set_recipient,
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(ContractError::TokenizedPosition {}, err.downcast().unwrap());
+
+    // a burned receipt can't be queried, the stored party is kept
+    let burn = cw721_base::ExecuteMsg::<cw721_base::Extension>::Burn {
+        token_id: "1".to_string(),
+    };
+    app.execute_contract(Addr::unchecked(SOURCE), collection.clone(), &burn, &[])
+        .unwrap();
+    assert_eq!(details(&app).source, SOURCE.to_string());
+
+    // the owner of the receipt is paid
+    let approve = ExecuteMsg::Approve {
+        id: "tokenized".to_string(),
+    };
+    app.execute_contract(Addr::unchecked(ARBITER), escrow.clone(), &approve, &[])
+        .unwrap();
+    assert_eq!(balance(&app, "buyer"), Uint128::new(400));
+    assert_eq!(balance(&app, RECIPIENT), Uint128::zero());
+}
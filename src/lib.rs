@@ -1,6 +1,12 @@
 pub mod contract;
 mod error;
+pub mod events;
 pub mod msg;
+pub mod payout;
+pub mod receipt;
 pub mod state;
 
+#[cfg(test)]
+mod integration_tests;
+
 pub use crate::error::ContractError;
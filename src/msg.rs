@@ -1,19 +1,96 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{ Addr, Api, Coin, StdResult };
-use cw20::{ Cw20Coin, Cw20ReceiveMsg };
+use cosmwasm_std::{ Addr, Api, Binary, BlockInfo, Coin, Decimal, StdResult, Timestamp, Uint128 };
+use cw20::{ Cw20Coin, Cw20CoinVerified, Cw20ReceiveMsg, Denom };
+use cw_utils::{Duration, Expiration};
+
+use crate::state::{Application, ArbiterFee, ArbiterProfile, Arbitration, Authorization, BackupArbiter, Change, Comparison, Condition, ContentHash, EncryptedMemo, Extension, GenericBalance, MetadataLimits, Phase, Template, Terms, Winner};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
     
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {
+    /// Admin of the config, only used when the migrated contract has no config yet
+    #[serde(default)]
+    pub admin: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
+    /// Contract config and fee pool
+    Config {},
     List {},
     Details { id: String },
+    /// Details of the escrow with this external reference id
+    DetailsByExternalId { external_id: String },
+    /// Time or blocks left before the escrow expires
+    TimeRemaining { id: String },
+    /// Phase of the escrow between its funding and settlement deadlines
+    Phase { id: String },
+    /// Payout schedule and remaining runway of a stream escrow
+    Stream { id: String },
+    /// Open swap offers for a token pair, cheapest implied price first
+    Offers {
+        offer_asset: Denom,
+        ask_asset: Denom,
+        /// Price and id of the last offer of the previous page, the page resumes after
+        /// them even once that offer is taken
+        start_after: Option<(Decimal, String)>,
+        limit: Option<u32>,
+    },
+    /// Defaults stored in an escrow template
+    Template { id: String },
+    /// Registered arbiters ordered by address
+    Arbiters {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Track record of an arbiter
+    ArbiterStats { address: String },
+    /// Changes made to an escrow, oldest first
+    History {
+        id: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Applications to an escrow ordered by applicant
+    Applications {
+        id: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Message thread of an escrow, oldest first
+    Messages {
+        id: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Fill history of a swap offer, oldest first
+    Fills {
+        id: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ConfigResponse {
+    pub admin: Option<String>,
+    pub keeper_bounty: Option<Coin>,
+    pub bounty_min_age: Option<u64>,
+    pub acceptance_window: Option<Duration>,
+    pub require_registered_arbiter: bool,
+    pub metadata_limits: MetadataLimits,
+    pub receipt_collection: Option<String>,
+    /// Native tokens in the fee pool
+    pub fee_pool_native: Vec<Coin>,
+    /// Cw20 tokens in the fee pool
+    pub fee_pool_cw20: Vec<Cw20Coin>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -33,8 +110,7 @@ pub struct DetailsResponse {
     pub source: String,
     pub title: String,
     pub description: String,
-    pub end_height: Option<u64>,
-    pub end_time: Option<u64>,
+    pub expires: Expiration,
     /// Balance in native tokens
     pub native_balance: Vec<Coin>,
     /// Balance in cw20 tokens
@@ -45,11 +121,199 @@ pub struct DetailsResponse {
     pub recip_native_balance: Vec<Coin>,
     /// Recipient Balance in cw20 tokens
     pub recip_cw20_balance: Vec<Cw20Coin>,
+    /// Oracle condition that releases the escrow
+    pub condition: Option<Condition>,
+    /// Message the recipient contract is called with on release
+    pub payout_msg: Option<Binary>,
+    /// What the source asks in exchange for the balance
+    pub terms: Option<Terms>,
+    /// Time or blocks after the expiration during which only the arbiter can refund
+    pub grace_period: Option<Duration>,
+    /// Whether the arbiter alone extends the expiration
+    pub arbiter_extends: bool,
+    /// Extension waiting for the agreement of the other party
+    pub pending_extension: Option<Extension>,
+    /// Deadline for the recipient to fund its leg
+    pub funding_deadline: Option<Expiration>,
+    /// Paid to the arbiter when it approves or refunds the escrow, or its condition releases it
+    pub arbiter_fee: Option<ArbiterFee>,
+    pub arbitration: Arbitration,
+    /// Deadline for the arbiter to accept the escrow
+    pub acceptance_deadline: Option<Expiration>,
+    /// Arbiter the escrow is being transferred to
+    pub pending_arbiter: Option<String>,
+    pub backup_arbiter: Option<BackupArbiter>,
+    pub authorization: Authorization,
+    /// Nonce the arbiter contract authorizes the next decision for
+    pub decision_nonce: u64,
+    pub assignment_needs_consent: bool,
+    /// Assignee waiting for the consent of the arbiter
+    pub pending_assignment: Option<String>,
+    /// Collection the receipts of the escrow are minted in
+    pub receipt_collection: Option<String>,
+    /// Receipt NFT of the recipient position, once there is a recipient
+    pub recipient_token_id: Option<String>,
+    /// Receipt NFT of the source position
+    pub source_token_id: Option<String>,
+    pub open_to_applications: bool,
+    pub application_deposit: Option<Coin>,
+    pub prize_pool: bool,
+    pub winners: Vec<Winner>,
+    pub tags: Vec<String>,
+    pub external_id: Option<String>,
+    pub content_hash: Option<ContentHash>,
+    pub memos: Vec<EncryptedMemo>,
+}
+
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OffersResponse {
+    pub offers: Vec<OfferInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OfferInfo {
+    pub id: String,
+    pub source: String,
+    /// Amount of the offered token held by the escrow
+    pub offer_amount: Uint128,
+    /// Amount of the asked token requested for the whole offer
+    pub ask_amount: Uint128,
+    /// Ask amount per offered token
+    pub price: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ApplicationsResponse {
+    pub applications: Vec<ApplicationInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ApplicationInfo {
+    pub applicant: String,
+    pub application: Application,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct HistoryResponse {
+    pub entries: Vec<HistoryInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct HistoryInfo {
+    pub index: u64,
+    pub actor: String,
+    pub change: Change,
+    pub height: u64,
+    pub time: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MessagesResponse {
+    pub messages: Vec<ThreadMessage>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ThreadMessage {
+    pub index: u64,
+    pub sender: String,
+    pub ciphertext: Binary,
+    pub height: u64,
+    pub time: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FillsResponse {
+    pub fills: Vec<FillInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FillInfo {
+    pub index: u64,
+    pub taker: String,
+    /// Amount of ask token paid to the source
+    pub paid: Uint128,
+    /// Native tokens released to the taker
+    pub native_received: Vec<Coin>,
+    /// Cw20 tokens released to the taker
+    pub cw20_received: Vec<Cw20Coin>,
+    pub height: u64,
+    pub time: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TimeRemainingResponse {
+    pub id: String,
+    pub expires: Expiration,
+    pub expired: bool,
+    /// Blocks or seconds left, zero once expired and None if the escrow never expires
+    pub remaining: Option<Duration>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PhaseResponse {
+    pub id: String,
+    pub phase: Phase,
+    pub funding_deadline: Option<Expiration>,
+    pub settlement_deadline: Expiration,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ArbitersResponse {
+    pub arbiters: Vec<ArbiterInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ArbiterInfo {
+    pub address: String,
+    pub profile: ArbiterProfile,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ArbiterStatsResponse {
+    pub address: String,
+    pub approvals: u64,
+    pub refunds: u64,
+    /// Escrows the arbiter agreed to arbitrate
+    pub accepted: u64,
+    /// Refunds and declines, and resolutions of escrows with an extension the
+    /// parties did not agree on
+    pub disputes: u64,
+    /// Average seconds between creation and approval or refund, None until an
+    /// escrow with a known creation time is resolved
+    pub avg_resolution_time: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TemplateResponse {
+    pub id: String,
+    pub owner: String,
+    pub arbiter: String,
+    pub cw20_wishlist: Vec<String>,
+    pub expires_in: Option<Duration>,
+    pub arbiter_fee: Option<ArbiterFee>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StreamResponse {
+    pub id: String,
+    /// Native tokens released on every interval
+    pub native_amount: Vec<Coin>,
+    /// Cw20 tokens released on every interval
+    pub cw20_amount: Vec<Cw20Coin>,
+    pub interval: Duration,
+    pub paused: bool,
+    /// When the next payout can be ticked
+    pub next_payout: Expiration,
+    /// Number of payouts left before the streamed tokens are exhausted
+    pub remaining_payouts: u64,
+    /// When the last payout can be ticked, Never if nothing is left to stream
+    pub last_payout: Expiration,
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
+#[allow(clippy::large_enum_variant)]
 pub enum ExecuteMsg {
     CreateEscrow( CreateMsg ),
 
@@ -58,6 +322,7 @@ pub enum ExecuteMsg {
         recipient: String,
     },
 
+    /// Releases the balance to the recipient and the recipient leg to the source
     Approve {
         id: String,
     },
@@ -71,48 +336,552 @@ pub enum ExecuteMsg {
         id: String,
     },
 
-    /// Adds all sent native tokens to the contract
+    /// Adds all sent native tokens to the recipient leg, which goes to the source on
+    /// approval and back to the recipient on refund. Only the recipient can call it
     TopUpRecip {
         id: String,
     },
 
     /// This accepts a properly-encoded ReceiveMsg from a cw20 contract
     Receive(Cw20ReceiveMsg),
+
+    /// Queries the oracle of the escrow condition, releases the escrow to the
+    /// recipient if the condition is met or refunds it if the escrow is expired.
+    /// Nothing is released before the arbiter accepted the escrow. Anyone can call it
+    CheckCondition {
+        id: String,
+    },
+
+    /// Pays out all accrued intervals of a stream escrow once the arbiter accepted it.
+    /// Anyone can call it
+    Tick {
+        id: String,
+    },
+
+    /// Stops a stream from accruing after paying the intervals accrued so far, only
+    /// the arbiter can call it
+    PauseStream {
+        id: String,
+    },
+
+    /// Restarts a paused stream, the next payout is one interval from now
+    ResumeStream {
+        id: String,
+    },
+
+    /// Runs all operations in order, if any of them fails the whole batch fails.
+    /// The funds sent must exactly match the funds assigned to the operations
+    Batch {
+        ops: Vec<EscrowOp>,
+    },
+
+    /// Fills an open swap offer with the asked native tokens. Paying the whole terms
+    /// settles both legs at once, offers with a minimum fill also accept a share
+    /// of the terms and release the matching share of the balance
+    Take {
+        id: String,
+    },
+
+    /// Stores defaults that escrows can be created from by referencing the template id
+    CreateTemplate(TemplateMsg),
+
+    /// Replaces the defaults of a template, only its owner can call it. Escrows
+    /// already created from it keep theirs
+    UpdateTemplate(TemplateMsg),
+
+    /// Removes a template, only its owner can call it
+    DeleteTemplate {
+        id: String,
+    },
+
+    /// Moves the expiration later. Depending on the escrow either the arbiter alone
+    /// extends it, or the source and the recipient must both send the same extension
+    Extend {
+        id: String,
+        new_expiration: Expiration,
+    },
+
+    /// Refunds up to limit escrows that anyone can refund, oldest expiration first.
+    /// The caller earns the keeper bounty for every escrow swept that it is not the
+    /// source or the arbiter of and that is old enough, as long as the fee pool can
+    /// pay it
+    SweepExpired {
+        limit: Option<u32>,
+    },
+
+    /// Adds all sent native tokens to the fee pool
+    FundFeePool {},
+
+    /// The arbiter agrees to arbitrate the escrow for its fee, the recipient can only
+    /// fund its leg afterwards. Approving or refunding the escrow also accepts it
+    AcceptArbitration {
+        id: String,
+    },
+
+    /// The arbiter turns down a pending escrow, which is refunded
+    DeclineArbitration {
+        id: String,
+    },
+
+    /// The arbiter hands the escrow over to a new arbiter, who must accept it
+    TransferArbiter {
+        id: String,
+        new_arbiter: String,
+    },
+
+    /// The proposed arbiter accepts the transfer and the arbitration of the escrow
+    AcceptArbiterTransfer {
+        id: String,
+    },
+
+    /// The backup arbiter replaces an arbiter that stayed inactive for the timeout
+    TakeOverArbiter {
+        id: String,
+    },
+
+    /// The recipient assigns its claim, all later payouts go to the new recipient.
+    /// Escrows that need consent wait for the arbiter to call "consent_assignment"
+    AssignClaim {
+        id: String,
+        new_recipient: String,
+    },
+
+    /// The arbiter consents to the pending claim assignment
+    ConsentAssignment {
+        id: String,
+    },
+
+    /// Applies to become the recipient of an escrow open to applications, with the
+    /// application deposit attached
+    Apply {
+        id: String,
+        memo: String,
+    },
+
+    /// Appends a message encrypted off-chain to the thread of the escrow, only its
+    /// source, recipient and arbiter can post
+    PostMessage {
+        id: String,
+        ciphertext: Binary,
+    },
+
+    /// Takes back an application and its deposit, also after the escrow is settled
+    WithdrawApplication {
+        id: String,
+    },
+
+    /// The source or the arbiter picks the recipient among the applicants, every
+    /// deposit is returned
+    SelectRecipient {
+        id: String,
+        applicant: String,
+    },
+
+    /// The arbiter sets how a prize pool is split, the shares must exactly cover the
+    /// balance left after its fee. Approving then pays every winner its share
+    SetWinners {
+        id: String,
+        winners: Vec<WinnerMsg>,
+    },
+
+    /// Sets the bounty paid per swept escrow and the age escrows need to earn it, the
+    /// time arbiters have to accept new escrows, whether they must be registered, the
+    /// limits on escrow metadata and the receipt collection, only the admin can call it.
+    /// Settings left unset keep their current value
+    UpdateConfig {
+        #[serde(default)]
+        keeper_bounty: Option<Coin>,
+        /// Seconds, defaults to a day
+        #[serde(default)]
+        bounty_min_age: Option<u64>,
+        #[serde(default)]
+        acceptance_window: Option<Duration>,
+        #[serde(default)]
+        require_registered_arbiter: Option<bool>,
+        /// The built in limits apply until the admin sets others
+        #[serde(default)]
+        metadata_limits: Option<MetadataLimits>,
+        /// cw721-base collection that has this contract as minter, without one
+        /// escrows can't have receipts
+        #[serde(default)]
+        receipt_collection: Option<String>,
+    },
+
+    /// Registers the sender as an arbiter, or updates its profile
+    RegisterArbiter {
+        name: String,
+        fee_schedule: Option<ArbiterFeeMsg>,
+        #[serde(default)]
+        supported_tokens: Vec<Denom>,
+    },
+}
+
+/// Query sent to the arbiter contract of an escrow in query authorization mode,
+/// answered with an `IsAuthorizedResponse`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ArbiterQueryMsg {
+    /// The nonce is the `decision_nonce` of the escrow, it changes once the decision
+    /// is applied so that an authorization cannot be replayed
+    IsAuthorized { id: String, nonce: u64, decision: Decision },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Approve {},
+    Refund {},
+    SetRecipient { recipient: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct IsAuthorizedResponse {
+    pub authorized: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[allow(clippy::large_enum_variant)]
+pub enum EscrowOp {
+    /// Creates an escrow funded with part of the native tokens sent with the batch
+    Create {
+        escrow: CreateMsg,
+        funds: Vec<Coin>,
+    },
+    SetRecipient {
+        id: String,
+        recipient: String,
+    },
+    Approve {
+        id: String,
+    },
+    Refund {
+        id: String,
+    },
+    /// Adds part of the native tokens sent with the batch to the escrow
+    TopUp {
+        id: String,
+        funds: Vec<Coin>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
 pub struct CreateMsg {
     //escrow struct
     pub id: String,
+    /// Can be left empty when the escrow is created from a template
+    #[serde(default)]
     pub arbiter: String,
     pub recipient: Option<String>,
     pub title: String,
     pub description: String,
-    pub end_height: Option<u64>,
-    pub end_time: Option<u64>,
+    /// Settlement deadline: once expired, the escrow can be refunded by anyone.
+    /// Never expires if unset
+    #[serde(default)]
+    pub expires: Option<Expiration>,
     pub cw20_wishlist: Option<Vec<String>>,
+    /// Release the escrow when an oracle query satisfies a comparison
+    #[serde(default)]
+    pub condition: Option<ConditionMsg>,
+    /// Release the balance to the recipient a fixed amount per interval
+    #[serde(default)]
+    pub stream: Option<StreamMsg>,
+    /// For contract recipients: on release cw20 tokens are delivered with a cw20 Send
+    /// and native tokens with an execute of the recipient, both carrying this message.
+    /// Dropped whenever the recipient changes
+    #[serde(default)]
+    pub payout_msg: Option<Binary>,
+    /// Turns the escrow into a swap offer asking these terms for the balance
+    #[serde(default)]
+    pub terms: Option<Terms>,
+    /// Expire the escrow this long after its creation, instead of at a fixed expiration
+    #[serde(default)]
+    pub expires_in: Option<Duration>,
+    /// Deadline for the recipient to fund its leg, an escrow still unfunded by then
+    /// can be refunded to the source by anyone
+    #[serde(default)]
+    pub funding_deadline: Option<Expiration>,
+    /// After expiring, only the arbiter can refund for this long, then anyone can
+    #[serde(default)]
+    pub grace_period: Option<Duration>,
+    /// Paid to the arbiter from the balance when it approves or refunds the escrow, or its
+    /// condition releases it
+    #[serde(default)]
+    pub arbiter_fee: Option<ArbiterFeeMsg>,
+    /// Let the arbiter alone extend the expiration instead of the source and the recipient
+    #[serde(default)]
+    pub arbiter_extends: bool,
+    /// Template whose defaults fill in the arbiter, wishlist, expiration and arbiter fee
+    /// left unset
+    #[serde(default)]
+    pub template_id: Option<String>,
+    /// Can take over the arbitration when the arbiter stays inactive
+    #[serde(default)]
+    pub backup_arbiter: Option<BackupArbiterMsg>,
+    /// Set to query to let anyone send the decisions an arbiter contract authorizes,
+    /// defaults to the arbiter sending them itself
+    #[serde(default)]
+    pub authorization: Option<Authorization>,
+    /// Require the consent of the arbiter before the recipient assigns its claim
+    #[serde(default)]
+    pub assignment_needs_consent: bool,
+    /// Mint a receipt NFT of the configured collection to the recipient once there is
+    /// one, whoever owns it is paid as the recipient
+    #[serde(default)]
+    pub recipient_receipt: bool,
+    /// Mint a receipt NFT to the source, whoever owns it is refunded as the source
+    #[serde(default)]
+    pub source_receipt: bool,
+    /// Let anyone apply to become the recipient until one is selected
+    #[serde(default)]
+    pub open_to_applications: bool,
+    /// Native deposit applicants must stake
+    #[serde(default)]
+    pub application_deposit: Option<Coin>,
+    /// Split the balance across several winners instead of a single recipient
+    #[serde(default)]
+    pub prize_pool: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Reference of the escrow in another system, unique among open escrows
+    #[serde(default)]
+    pub external_id: Option<String>,
+    /// Hash of an off-chain document, such as the signed contract
+    #[serde(default)]
+    pub content_hash: Option<ContentHash>,
+    /// Private notes, each encrypted to the key of one party
+    #[serde(default)]
+    pub memos: Vec<EncryptedMemo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WinnerMsg {
+    pub address: String,
+    #[serde(default)]
+    pub native: Vec<Coin>,
+    #[serde(default)]
+    pub cw20: Vec<Cw20Coin>,
+}
+
+impl WinnerMsg {
+    pub fn validated(&self, api: &dyn Api) -> StdResult<Winner> {
+        let cw20 = self
+            .cw20
+            .iter()
+            .map(|c| {
+                Ok(Cw20CoinVerified {
+                    address: api.addr_validate(&c.address)?,
+                    amount: c.amount,
+                })
+            })
+            .collect::<StdResult<_>>()?;
+        Ok(Winner {
+            address: api.addr_validate(&self.address)?,
+            share: GenericBalance {
+                native: self.native.clone(),
+                cw20,
+            },
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BackupArbiterMsg {
+    pub address: String,
+    /// How long the arbiter can stay inactive before the backup can take over
+    pub inactivity_timeout: Duration,
 }
 
 impl CreateMsg {
+    /// Fills in the fields left unset with the defaults of the template
+    pub fn with_template(mut self, template: &Template) -> Self {
+        if self.arbiter.is_empty() {
+            self.arbiter = template.arbiter.to_string();
+        }
+        if self.cw20_wishlist.is_none() {
+            self.cw20_wishlist = Some(template.cw20_wishlist.iter().map(|a| a.to_string()).collect());
+        }
+        if self.expires.is_none() && self.expires_in.is_none() {
+            self.expires_in = template.expires_in;
+        }
+        if self.arbiter_fee.is_none() {
+            self.arbiter_fee = template.arbiter_fee.clone().map(ArbiterFeeMsg::from);
+        }
+        self
+    }
+
     pub fn addr_wishlist(&self, api: &dyn Api) -> StdResult<Vec<Addr>> {
         match self.cw20_wishlist.as_ref() {
             Some(v) => v.iter().map(|h| api.addr_validate(h)).collect(),
             None => Ok(vec![]),
         }
     }
+
+    pub fn validated_terms(&self, api: &dyn Api) -> StdResult<Option<Terms>> {
+        self.terms
+            .as_ref()
+            .map(|terms| {
+                let ask = match &terms.ask {
+                    Denom::Native(denom) => Denom::Native(denom.clone()),
+                    Denom::Cw20(addr) => Denom::Cw20(api.addr_validate(addr.as_str())?),
+                };
+                Ok(Terms {
+                    ask,
+                    ask_amount: terms.ask_amount,
+                    min_fill: terms.min_fill,
+                })
+            })
+            .transpose()
+    }
+
+    pub fn validated_condition(&self, api: &dyn Api) -> StdResult<Option<Condition>> {
+        self.condition
+            .as_ref()
+            .map(|c| {
+                Ok(Condition {
+                    oracle: api.addr_validate(&c.oracle)?,
+                    query: c.query.clone(),
+                    comparison: c.comparison.clone(),
+                })
+            })
+            .transpose()
+    }
+
+    /// Absolute expiration of the escrow, relative durations start at the given block
+    pub fn resolved_expiration(&self, block: &BlockInfo) -> Option<Expiration> {
+        match (self.expires, self.expires_in) {
+            (Some(_), Some(_)) => None,
+            (Some(expires), None) => Some(expires),
+            (None, Some(duration)) => Some(duration.after(block)),
+            (None, None) => Some(Expiration::Never {}),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct TemplateMsg {
+    pub id: String,
+    pub arbiter: String,
+    pub cw20_wishlist: Option<Vec<String>>,
+    /// Escrows created from the template expire this long after their creation
+    pub expires_in: Option<Duration>,
+    /// Fee escrows created from the template pay their arbiter
+    #[serde(default)]
+    pub arbiter_fee: Option<ArbiterFeeMsg>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StreamMsg {
+    /// Native tokens released on every interval
+    #[serde(default)]
+    pub native_amount: Vec<Coin>,
+    /// Cw20 tokens released on every interval
+    #[serde(default)]
+    pub cw20_amount: Vec<Cw20Coin>,
+    pub interval: Duration,
+}
+
+impl StreamMsg {
+    pub fn validated_amount(&self, api: &dyn Api) -> StdResult<GenericBalance> {
+        let cw20 = self
+            .cw20_amount
+            .iter()
+            .map(|c| {
+                Ok(Cw20CoinVerified {
+                    address: api.addr_validate(&c.address)?,
+                    amount: c.amount,
+                })
+            })
+            .collect::<StdResult<_>>()?;
+        Ok(GenericBalance {
+            native: self.native_amount.clone(),
+            cw20,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ArbiterFeeMsg {
+    /// Fixed tokens, capped by what the balance holds
+    Fixed {
+        #[serde(default)]
+        native: Vec<Coin>,
+        #[serde(default)]
+        cw20: Vec<Cw20Coin>,
+    },
+    /// Share of every token of the balance, in basis points
+    Bps(u16),
+}
+
+impl ArbiterFeeMsg {
+    pub fn validated(&self, api: &dyn Api) -> StdResult<ArbiterFee> {
+        match self {
+            ArbiterFeeMsg::Fixed { native, cw20 } => {
+                let cw20 = cw20
+                    .iter()
+                    .map(|c| {
+                        Ok(Cw20CoinVerified {
+                            address: api.addr_validate(&c.address)?,
+                            amount: c.amount,
+                        })
+                    })
+                    .collect::<StdResult<_>>()?;
+                Ok(ArbiterFee::Fixed(GenericBalance {
+                    native: native.clone(),
+                    cw20,
+                }))
+            }
+            ArbiterFeeMsg::Bps(bps) => Ok(ArbiterFee::Bps(*bps)),
+        }
+    }
+}
+
+impl From<ArbiterFee> for ArbiterFeeMsg {
+    fn from(fee: ArbiterFee) -> Self {
+        match fee {
+            ArbiterFee::Fixed(balance) => ArbiterFeeMsg::Fixed {
+                native: balance.native,
+                cw20: balance
+                    .cw20
+                    .into_iter()
+                    .map(|c| Cw20Coin {
+                        address: c.address.into_string(),
+                        amount: c.amount,
+                    })
+                    .collect(),
+            },
+            ArbiterFee::Bps(bps) => ArbiterFeeMsg::Bps(bps),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ConditionMsg {
+    /// Oracle contract address
+    pub oracle: String,
+    /// Smart query sent to the oracle, its answer must be a bare number or boolean
+    pub query: Binary,
+    pub comparison: Comparison,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
+#[allow(clippy::large_enum_variant)]
 pub enum ReceiveMsg {
     CreateEscrow(CreateMsg),
     /// Adds all sent native tokens to the contract
     TopUp {
         id: String,
     },
+    /// Adds the sent cw20 tokens to the recipient leg
     TopUpRecip {
         id: String,
     },
+    /// Fills an open swap offer with the asked cw20 tokens
+    Take {
+        id: String,
+    },
 }
 
 pub fn is_valid_name(name: &str) -> bool {
@@ -0,0 +1,211 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{to_binary, Addr, BankMsg, Binary, StdResult, SubMsg, WasmMsg};
+use cw20::Cw20ExecuteMsg;
+
+use crate::state::GenericBalance;
+
+/// How tokens are delivered to a payee. The `payout_msg` of an escrow selects the
+/// callback, it also covers a cw20 Send hook since native tokens are only attached
+/// when there are some
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Delivery {
+    /// Bank send and plain cw20 Transfer, the payee is not notified
+    Transfer,
+    /// cw20 Send with this hook message and, for native tokens, an execute of the
+    /// payee contract with this message and the tokens attached
+    Callback { msg: Binary },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Payout {
+    to: Addr,
+    delivery: Delivery,
+    balance: GenericBalance,
+}
+
+/// Collects everything owed to the parties of an action and builds the
+/// smallest set of messages paying them: zero amounts are dropped and tokens owed
+/// to the same payee with the same delivery are coalesced
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Payouts {
+    payouts: Vec<Payout>,
+}
+
+impl Payouts {
+    /// Owes tokens to an address, delivered with a plain transfer
+    pub fn add(&mut self, to: &Addr, balance: &GenericBalance) {
+        self.add_with(to, balance, Delivery::Transfer)
+    }
+
+    /// Owes tokens to an address, delivered as requested
+    pub fn add_with(&mut self, to: &Addr, balance: &GenericBalance, delivery: Delivery) {
+        match self
+            .payouts
+            .iter_mut()
+            .find(|p| &p.to == to && p.delivery == delivery)
+        {
+            Some(payout) => payout.balance.add_balance(balance),
+            None => {
+                let mut total = GenericBalance::default();
+                total.add_balance(balance);
+                self.payouts.push(Payout {
+                    to: to.clone(),
+                    delivery,
+                    balance: total,
+                });
+            }
+        }
+    }
+
+    pub fn into_msgs(self) -> StdResult<Vec<SubMsg>> {
+        let mut msgs = vec![];
+        for payout in self.payouts {
+            let native: Vec<_> = payout
+                .balance
+                .native
+                .into_iter()
+                .filter(|c| !c.amount.is_zero())
+                .collect();
+            if !native.is_empty() {
+                msgs.push(match &payout.delivery {
+                    Delivery::Callback { msg } => SubMsg::new(WasmMsg::Execute {
+                        contract_addr: payout.to.to_string(),
+                        msg: msg.clone(),
+                        funds: native,
+                    }),
+                    Delivery::Transfer => SubMsg::new(BankMsg::Send {
+                        to_address: payout.to.to_string(),
+                        amount: native,
+                    }),
+                });
+            }
+
+            for token in payout.balance.cw20 {
+                if token.amount.is_zero() {
+                    continue;
+                }
+                let msg = match &payout.delivery {
+                    Delivery::Transfer => Cw20ExecuteMsg::Transfer {
+                        recipient: payout.to.to_string(),
+                        amount: token.amount,
+                    },
+                    Delivery::Callback { msg } => Cw20ExecuteMsg::Send {
+                        contract: payout.to.to_string(),
+                        amount: token.amount,
+                        msg: msg.clone(),
+                    },
+                };
+                msgs.push(SubMsg::new(WasmMsg::Execute {
+                    contract_addr: token.address.to_string(),
+                    msg: to_binary(&msg)?,
+                    funds: vec![],
+                }));
+            }
+        }
+        Ok(msgs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::{coin, Uint128};
+    use cw20::Cw20CoinVerified;
+
+    fn cw20(address: &str, amount: u128) -> Cw20CoinVerified {
+        Cw20CoinVerified {
+            address: Addr::unchecked(address),
+            amount: Uint128::new(amount),
+        }
+    }
+
+    #[test]
+    fn payouts_drop_zero_amounts_and_coalesce() {
+        let alice = Addr::unchecked("alice");
+        let bob = Addr::unchecked("bob");
+
+        let mut payouts = Payouts::default();
+        payouts.add(
+            &alice,
+            &GenericBalance {
+                native: vec![coin(10, "atom"), coin(0, "eth"), coin(5, "atom")],
+                cw20: vec![cw20("token", 7), cw20("empty", 0)],
+            },
+        );
+        payouts.add(
+            &bob,
+            &GenericBalance {
+                native: vec![coin(0, "atom")],
+                cw20: vec![],
+            },
+        );
+        payouts.add(
+            &alice,
+            &GenericBalance {
+                native: vec![coin(1, "btc")],
+                cw20: vec![cw20("token", 3)],
+            },
+        );
+
+        let msgs = payouts.into_msgs().unwrap();
+        assert_eq!(
+            msgs,
+            vec![
+                SubMsg::new(BankMsg::Send {
+                    to_address: "alice".to_string(),
+                    amount: vec![coin(15, "atom"), coin(1, "btc")],
+                }),
+                SubMsg::new(WasmMsg::Execute {
+                    contract_addr: "token".to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                        recipient: "alice".to_string(),
+                        amount: Uint128::new(10),
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn payouts_callback_attaches_native_funds() {
+        let vault = Addr::unchecked("vault");
+        let hook = Binary::from(b"{\"settled\":{}}".to_vec());
+
+        let mut payouts = Payouts::default();
+        payouts.add_with(
+            &vault,
+            &GenericBalance {
+                native: vec![coin(5, "atom")],
+                cw20: vec![cw20("token", 42)],
+            },
+            Delivery::Callback { msg: hook.clone() },
+        );
+
+        let msgs = payouts.into_msgs().unwrap();
+        assert_eq!(
+            msgs,
+            vec![
+                SubMsg::new(WasmMsg::Execute {
+                    contract_addr: "vault".to_string(),
+                    msg: hook.clone(),
+                    funds: vec![coin(5, "atom")],
+                }),
+                SubMsg::new(WasmMsg::Execute {
+                    contract_addr: "token".to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::Send {
+                        contract: "vault".to_string(),
+                        amount: Uint128::new(42),
+                        msg: hook,
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                }),
+            ]
+        );
+    }
+}
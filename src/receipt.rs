@@ -0,0 +1,114 @@
+use cosmwasm_std::{
+    to_binary, Addr, CosmosMsg, QuerierWrapper, StdError, StdResult, Storage, WasmMsg,
+};
+use cw721::{Cw721QueryMsg, OwnerOfResponse};
+use cw721_base::{Extension, MintMsg, MinterResponse, QueryMsg};
+use cw_storage_plus::Item;
+
+use crate::state::Escrow;
+
+/// Receipts minted so far, the last one has this number as token id
+pub const RECEIPT_COUNT: Item<u64> = Item::new("receipt_count");
+
+/// Whether the escrow contract is the minter of the collection
+pub fn is_minter(querier: &QuerierWrapper, collection: &Addr, minter: &Addr) -> StdResult<bool> {
+    let res: MinterResponse = querier.query_wasm_smart(collection, &QueryMsg::Minter {})?;
+    Ok(&res.minter == minter)
+}
+
+/// Tokenized positions of escrows are receipts in a separate cw721-base collection
+/// the escrow contract is the minter of. Whoever owns the receipt of a position is
+/// the party of that position, so selling the NFT hands the position over. Receipts
+/// of closed escrows stay in the collection, only their owner can burn them.
+///
+/// Mints the receipts of the tokenized positions that have a party and no receipt
+/// yet, token ids are numbers that are never reused
+pub fn mint_receipts(storage: &mut dyn Storage, escrow: &mut Escrow) -> StdResult<Vec<CosmosMsg>> {
+    let collection = match &escrow.receipt_collection {
+        Some(collection) => collection.clone(),
+        None => return Ok(vec![]),
+    };
+    let mut owners = vec![];
+    if escrow.source_receipt && escrow.source_token_id.is_none() {
+        let token_id = next_token_id(storage)?;
+        escrow.source_token_id = Some(token_id.clone());
+        owners.push((token_id, escrow.source.clone()));
+    }
+    if let Some(recipient) = escrow.recipient.as_ref() {
+        if escrow.recipient_receipt && escrow.recipient_token_id.is_none() {
+            let token_id = next_token_id(storage)?;
+            escrow.recipient_token_id = Some(token_id.clone());
+            owners.push((token_id, recipient.clone()));
+        }
+    }
+
+    owners
+        .into_iter()
+        .map(|(token_id, owner)| {
+            let mint = cw721_base::ExecuteMsg::<Extension>::Mint(MintMsg {
+                token_id,
+                owner: owner.into_string(),
+                token_uri: None,
+                extension: None,
+            });
+            Ok(WasmMsg::Execute {
+                contract_addr: collection.to_string(),
+                msg: to_binary(&mint)?,
+                funds: vec![],
+            }
+            .into())
+        })
+        .collect()
+}
+
+fn next_token_id(storage: &mut dyn Storage) -> StdResult<String> {
+    let count = RECEIPT_COUNT.may_load(storage)?.unwrap_or_default() + 1;
+    RECEIPT_COUNT.save(storage, &count)?;
+    Ok(count.to_string())
+}
+
+/// Replaces the parties of the tokenized positions with the current owners of their
+/// receipts. A receipt minted in the same transaction or burned by its owner can't
+/// be queried, the stored party is kept then. Any other query error is returned
+pub fn resolve_owners(querier: &QuerierWrapper, escrow: &mut Escrow) -> StdResult<()> {
+    let collection = match &escrow.receipt_collection {
+        Some(collection) => collection.clone(),
+        None => return Ok(()),
+    };
+    let owner_of = |token_id: &Option<String>| -> StdResult<Option<Addr>> {
+        let token_id = match token_id {
+            Some(token_id) => token_id.clone(),
+            None => return Ok(None),
+        };
+        let query = Cw721QueryMsg::OwnerOf {
+            token_id,
+            include_expired: None,
+        };
+        match querier.query_wasm_smart::<OwnerOfResponse>(&collection, &query) {
+            Ok(res) => Ok(Some(Addr::unchecked(res.owner))),
+            Err(err) if is_not_found(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    };
+    if let Some(owner) = owner_of(&escrow.source_token_id)? {
+        escrow.source = owner;
+    }
+    if let Some(owner) = owner_of(&escrow.recipient_token_id)? {
+        if escrow.recipient.as_ref() != Some(&owner) {
+            escrow.recipient = Some(owner);
+            // the payout message was meant for the old recipient contract
+            escrow.payout_msg = None;
+        }
+    }
+    Ok(())
+}
+
+/// Errors of queries to other contracts reach the querier as generic errors with
+/// the original message, a missing token shows up as "... not found" there
+fn is_not_found(err: &StdError) -> bool {
+    match err {
+        StdError::NotFound { .. } => true,
+        StdError::GenericErr { msg } => msg.ends_with("not found"),
+        _ => false,
+    }
+}
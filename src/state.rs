@@ -1,13 +1,180 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{ Addr, Coin, Env, Order, StdResult, Storage, Timestamp };
-use cw_storage_plus::Map;
+use cosmwasm_std::{
+    Addr, BlockInfo, Binary, Coin, Decimal, Empty, Env, Order, QuerierWrapper, QueryRequest,
+    StdError, StdResult, Storage, Timestamp, Uint128, WasmQuery,
+};
+use cw_storage_plus::{Item, Map};
+use cw_utils::{Duration, Expiration};
 
-use cw20::{ Balance, Cw20CoinVerified };
+use cw20::{ Balance, Cw20CoinVerified, Denom };
+
+use crate::payout::Delivery;
+use crate::receipt::resolve_owners;
+
+pub const CONFIG: Item<Config> = Item::new("config");
+
+/// Profiles of the arbiters that registered
+pub const ARBITERS: Map<&Addr, ArbiterProfile> = Map::new("arbiters");
+
+/// Track record of every arbiter, registered or not
+pub const ARBITER_STATS: Map<&Addr, ArbiterStats> = Map::new("arbiter_stats");
+
+/// Last decision nonce handed out, nonces are never reused so that an arbiter
+/// contract authorization is good for a single decision
+pub const DECISION_NONCE: Item<u64> = Item::new("decision_nonce");
+
+/// Tokens owned by the protocol, keeper bounties are paid from it
+pub const FEE_POOL: Item<GenericBalance> = Item::new("fee_pool");
 
 pub const ESCROWS: Map<&str, Escrow> = Map::new("escrow");
 
+/// Escrows by (height|time, expiration, escrow id), an escrow is listed at the points
+/// from which anyone can refund it
+pub const EXPIRATIONS: Map<(&str, u64, &str), Empty> = Map::new("expirations");
+
+/// Open swap offers indexed by (offer|ask pair, implied price, escrow id)
+pub const OFFERS: Map<(&str, u128, &str), Empty> = Map::new("offers");
+
+/// Escrow ids by external reference id, a reference belongs to one escrow at a time
+pub const EXTERNAL_IDS: Map<&str, String> = Map::new("external_ids");
+
+/// Reusable escrow defaults by template id
+pub const TEMPLATES: Map<&str, Template> = Map::new("templates");
+
+/// Fills of swap offers by (escrow id, fill index), kept after the escrow is closed
+pub const FILLS: Map<(&str, u64), Fill> = Map::new("fills");
+
+/// Applications to escrows open to applications, keyed by escrow id and applicant
+pub const APPLICATIONS: Map<(&str, &Addr), Application> = Map::new("applications");
+
+/// Changes made to an escrow, keyed by escrow id and an increasing index
+pub const HISTORY: Map<(&str, u64), HistoryEntry> = Map::new("history");
+
+/// Encrypted messages between the parties of an escrow, keyed by escrow id and an
+/// increasing index, kept after the escrow is closed
+pub const MESSAGES: Map<(&str, u64), Message> = Map::new("messages");
+
+/// Acceptance window of escrows created while the config sets none, a week
+pub const DEFAULT_ACCEPTANCE_WINDOW: Duration = Duration::Time(7 * 24 * 60 * 60);
+
+/// Age an escrow needs to earn the keeper bounty while the config sets none, a day
+pub const DEFAULT_BOUNTY_MIN_AGE: u64 = 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct Config {
+    /// Can update the config, contracts migrated without an admin have none
+    pub admin: Option<Addr>,
+    /// Paid from the fee pool to whoever sweeps an expired escrow
+    pub keeper_bounty: Option<Coin>,
+    /// Seconds an escrow must have existed before sweeping it earns the bounty,
+    /// `DEFAULT_BOUNTY_MIN_AGE` when unset
+    #[serde(default)]
+    pub bounty_min_age: Option<u64>,
+    /// How long arbiters have to accept a new escrow before it can be refunded,
+    /// `DEFAULT_ACCEPTANCE_WINDOW` when unset
+    #[serde(default)]
+    pub acceptance_window: Option<Duration>,
+    /// Only arbiters of the registry can be named in new escrows
+    #[serde(default)]
+    pub require_registered_arbiter: bool,
+    #[serde(default)]
+    pub metadata_limits: MetadataLimits,
+    /// cw721 collection receipts of new escrows are minted in, the contract must be
+    /// its minter
+    #[serde(default)]
+    pub receipt_collection: Option<Addr>,
+}
+
+/// Bounds on the free text of escrows, lengths are in bytes
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(default)]
+pub struct MetadataLimits {
+    pub max_title_length: u32,
+    pub max_description_length: u32,
+    pub max_tags: u32,
+    pub max_tag_length: u32,
+    pub max_external_id_length: u32,
+    /// Applies to the ciphertext of memos and messages
+    pub max_message_length: u32,
+    pub max_application_memo_length: u32,
+}
+
+impl Default for MetadataLimits {
+    fn default() -> Self {
+        MetadataLimits {
+            max_title_length: 128,
+            max_description_length: 1024,
+            max_tags: 10,
+            max_tag_length: 32,
+            max_external_id_length: 64,
+            max_message_length: 2048,
+            max_application_memo_length: 512,
+        }
+    }
+}
+
+/// Longest IPFS CID accepted as a content hash
+const MAX_CID_LENGTH: usize = 128;
+
+/// Hash of an off-chain document the escrow refers to
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentHash {
+    /// Hex encoded SHA-256 digest of the document
+    Sha256(String),
+    /// IPFS content identifier of the document
+    Ipfs(String),
+}
+
+impl ContentHash {
+    pub fn is_valid(&self) -> bool {
+        match self {
+            ContentHash::Sha256(digest) => {
+                digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit())
+            }
+            ContentHash::Ipfs(cid) => {
+                !cid.is_empty()
+                    && cid.len() <= MAX_CID_LENGTH
+                    && cid.chars().all(|c| c.is_ascii_alphanumeric())
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ArbiterProfile {
+    pub name: String,
+    /// Fee the arbiter usually asks for
+    pub fee_schedule: Option<ArbiterFee>,
+    /// Tokens the arbiter is willing to handle
+    pub supported_tokens: Vec<Denom>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct ArbiterStats {
+    pub approvals: u64,
+    pub refunds: u64,
+    /// Escrows the arbiter agreed to arbitrate
+    pub accepted: u64,
+    /// Refunds and declines, and resolutions of escrows with an extension the
+    /// parties did not agree on
+    #[serde(default)]
+    pub disputes: u64,
+    /// Approvals and refunds of escrows with a known creation time
+    pub timed_resolutions: u64,
+    /// Seconds between creation and resolution, summed over timed resolutions
+    pub total_resolution_time: u64,
+}
+
+impl ArbiterStats {
+    /// Average seconds between creation and resolution
+    pub fn avg_resolution_time(&self) -> Option<u64> {
+        self.total_resolution_time.checked_div(self.timed_resolutions)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Escrow {
     /// arbiter can decide to approve or refund the escrow
@@ -20,19 +187,386 @@ pub struct Escrow {
     pub title: String,
     /// Description of the escrow, a more in depth description of how to meet the escrow condition
     pub description: String,
-    /// When end height set and block height exceeds this value, the escrow is expired.
-    /// Once an escrow is expired, it can be returned to the original funder (via "refund").
-    pub end_height: Option<u64>,
-    /// When end time (in seconds since epoch 00:00:00 UTC on 1 January 1970) is set and
-    /// block time exceeds this value, the escrow is expired.
     /// Once an escrow is expired, it can be returned to the original funder (via "refund").
-    pub end_time: Option<u64>,
+    /// Escrows stored before expirations were introduced get theirs on migration
+    #[serde(default)]
+    pub expires: Expiration,
     /// Balance in Native and Cw20 tokens
     pub balance: GenericBalance,
     /// Recipient Balance in Native and Cw20 tokens
     pub recip_balance: GenericBalance,
     /// All possible contracts that we accept tokens from recipient
     pub cw20_wishlist: Vec<Addr>,
+    /// Optional oracle condition, when set anyone can settle the escrow via "check_condition"
+    #[serde(default)]
+    pub condition: Option<Condition>,
+    /// Optional payment stream, when set the balance is released to the recipient
+    /// a fixed amount per interval via "tick"
+    #[serde(default)]
+    pub stream: Option<Stream>,
+    /// When set, the recipient is a contract that is called with this message on
+    /// release: cw20 tokens are delivered with Send and native tokens are attached
+    /// to an execute of the recipient. Dropped whenever the recipient changes
+    #[serde(default)]
+    pub payout_msg: Option<Binary>,
+    /// What the source asks in exchange for the balance. An escrow with terms, a
+    /// single token balance and no recipient yet is listed as an open swap offer
+    #[serde(default)]
+    pub terms: Option<Terms>,
+    /// Time or blocks after the expiration during which only the arbiter can refund
+    #[serde(default)]
+    pub grace_period: Option<Duration>,
+    /// When true the arbiter alone extends the expiration, otherwise the source and
+    /// the recipient must agree on it
+    #[serde(default)]
+    pub arbiter_extends: bool,
+    /// Extension proposed by the source or the recipient, waiting for the other one
+    #[serde(default)]
+    pub pending_extension: Option<Extension>,
+    /// When set, the recipient must fund its leg before this deadline, otherwise the
+    /// escrow can be refunded to the source by anyone
+    #[serde(default)]
+    pub funding_deadline: Option<Expiration>,
+    /// Paid to the arbiter from the balance when it approves or refunds the escrow
+    #[serde(default)]
+    pub arbiter_fee: Option<ArbiterFee>,
+    /// Whether the arbiter agreed to arbitrate the escrow, escrows stored before
+    /// the handshake was introduced count as accepted
+    #[serde(default = "accepted")]
+    pub arbitration: Arbitration,
+    /// A pending arbitration not accepted by then makes the escrow refundable by anyone
+    #[serde(default)]
+    pub acceptance_deadline: Option<Expiration>,
+    /// Block time at creation, unknown for escrows stored before it was recorded
+    #[serde(default)]
+    pub created_at: Option<Timestamp>,
+    /// Arbiter the current one transfers the escrow to, waiting for its acceptance
+    #[serde(default)]
+    pub pending_arbiter: Option<Addr>,
+    /// Takes over the arbitration once the arbiter stays inactive for too long
+    #[serde(default)]
+    pub backup_arbiter: Option<BackupArbiter>,
+    /// How the arbiter authorizes approvals, refunds and recipient changes
+    #[serde(default = "sender_authorization")]
+    pub authorization: Authorization,
+    /// Nonce the arbiter contract authorizes the next decision for, renewed every
+    /// time a decision is applied
+    #[serde(default)]
+    pub decision_nonce: u64,
+    /// When true the arbiter must consent before the recipient assigns its claim
+    #[serde(default)]
+    pub assignment_needs_consent: bool,
+    /// Assignee proposed by the recipient, waiting for the consent of the arbiter
+    #[serde(default)]
+    pub pending_assignment: Option<Addr>,
+    /// The recipient position is a receipt NFT, its owner is the recipient
+    #[serde(default)]
+    pub recipient_receipt: bool,
+    /// The source position is a receipt NFT, its owner is the source
+    #[serde(default)]
+    pub source_receipt: bool,
+    /// cw721 collection the receipts are minted in
+    #[serde(default)]
+    pub receipt_collection: Option<Addr>,
+    /// Receipt of the recipient position, minted once there is a recipient
+    #[serde(default)]
+    pub recipient_token_id: Option<String>,
+    #[serde(default)]
+    pub source_token_id: Option<String>,
+    /// While there is no recipient, anyone can apply to become it
+    #[serde(default)]
+    pub open_to_applications: bool,
+    /// Native deposit applicants stake, returned once a recipient is selected
+    #[serde(default)]
+    pub application_deposit: Option<Coin>,
+    /// The balance is split across the winners set by the arbiter instead of going
+    /// to a single recipient
+    #[serde(default)]
+    pub prize_pool: bool,
+    #[serde(default)]
+    pub winners: Vec<Winner>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Reference of the escrow in another system, such as an invoice number
+    #[serde(default)]
+    pub external_id: Option<String>,
+    #[serde(default)]
+    pub content_hash: Option<ContentHash>,
+    #[serde(default)]
+    pub memos: Vec<EncryptedMemo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Winner {
+    pub address: Addr,
+    pub share: GenericBalance,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Party {
+    Source,
+    Recipient,
+    Arbiter,
+}
+
+/// Memo for one party, encrypted off-chain, the contract only stores it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct EncryptedMemo {
+    pub party: Party,
+    pub ciphertext: Binary,
+    /// Identifier of the public key of the party the memo was encrypted to
+    pub key_id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Message {
+    pub sender: Addr,
+    pub ciphertext: Binary,
+    pub height: u64,
+    pub time: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Application {
+    pub memo: String,
+    pub deposit: Vec<Coin>,
+    pub height: u64,
+    pub time: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Authorization {
+    /// The arbiter sends its decisions itself
+    Sender,
+    /// Anyone can send a decision the arbiter contract answers is authorized,
+    /// see `ArbiterQueryMsg`
+    Query,
+}
+
+fn sender_authorization() -> Authorization {
+    Authorization::Sender
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BackupArbiter {
+    pub address: Addr,
+    /// How long the arbiter can stay inactive before the backup can take over
+    pub inactivity_timeout: Duration,
+    /// Pushed back by every action of the arbiter on the escrow
+    pub takes_over_at: Expiration,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct HistoryEntry {
+    pub actor: Addr,
+    pub change: Change,
+    pub height: u64,
+    pub time: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    /// The arbiter proposed to hand the escrow over to another arbiter
+    ArbiterTransferProposed { to: Addr },
+    /// The proposed arbiter accepted the transfer
+    ArbiterTransferred { from: Addr, to: Addr },
+    /// The backup arbiter replaced an inactive arbiter
+    ArbiterTakenOver { from: Addr, to: Addr },
+    /// The recipient asked the arbiter to consent to assigning its claim
+    ClaimAssignmentProposed { to: Addr },
+    /// The claim of the recipient now belongs to the assignee
+    ClaimAssigned { from: Addr, to: Addr },
+    /// One of the applicants was selected as the recipient
+    RecipientSelected { recipient: Addr },
+    /// The arbiter set the recipient, replacing the previous one if any
+    RecipientSet { from: Option<Addr>, to: Addr },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Arbitration {
+    /// The arbiter has not accepted yet, the recipient cannot fund its leg
+    Pending,
+    /// The arbiter accepted the escrow and its fee
+    Accepted,
+}
+
+fn accepted() -> Arbitration {
+    Arbitration::Accepted
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ArbiterFee {
+    /// Fixed tokens, capped by what the balance holds
+    Fixed(GenericBalance),
+    /// Share of every token of the balance, in basis points
+    Bps(u16),
+}
+
+/// Where an escrow stands between its funding and settlement deadlines
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Waiting for the arbiter to accept the escrow
+    Arbitration,
+    /// Acceptance deadline passed without the arbiter accepting, refundable to the source
+    Unaccepted,
+    /// Waiting for the recipient to fund its leg before the funding deadline
+    Funding,
+    /// Funded or without funding deadline, waiting to be settled
+    Settlement,
+    /// Funding deadline passed without recipient funds, refundable to the source
+    Unfunded,
+    /// Settlement deadline passed, both legs are refundable to their depositors
+    Expired,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Extension {
+    pub expires: Expiration,
+    pub proposed_by: Addr,
+}
+
+/// Expiration fields of escrows stored before the switch to cw_utils expirations
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LegacyExpiry {
+    /// Expired once the block height exceeds it
+    #[serde(default)]
+    pub end_height: Option<u64>,
+    /// Expired once the block time exceeds it, in seconds
+    #[serde(default)]
+    pub end_time: Option<u64>,
+}
+
+/// Legacy view of the escrows map, only the old expiration fields are read
+pub const LEGACY_EXPIRIES: Map<&str, LegacyExpiry> = Map::new("escrow");
+
+impl LegacyExpiry {
+    /// Equivalent expiration: the old bounds were exclusive, cw_utils ones are inclusive.
+    /// An expiration holds a single bound, when both were set the one already reached
+    /// wins. None when neither is reached yet, as which one comes first is unknown
+    pub fn to_expiration(&self, block: &BlockInfo) -> Option<Expiration> {
+        let at_height = self.end_height.map(|h| Expiration::AtHeight(h + 1));
+        let at_time = self
+            .end_time
+            .map(|t| Expiration::AtTime(Timestamp::from_seconds(t).plus_nanos(1)));
+        match (at_height, at_time) {
+            (Some(height), Some(_)) if height.is_expired(block) => Some(height),
+            (Some(_), Some(time)) if time.is_expired(block) => Some(time),
+            (Some(_), Some(_)) => None,
+            (Some(height), None) => Some(height),
+            (None, Some(time)) => Some(time),
+            (None, None) => Some(Expiration::Never {}),
+        }
+    }
+}
+
+/// Defaults applied to escrows created from a template
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Template {
+    /// Address that created the template
+    pub owner: Addr,
+    pub arbiter: Addr,
+    pub cw20_wishlist: Vec<Addr>,
+    /// Escrows expire this long after their creation
+    pub expires_in: Option<Duration>,
+    /// Fee escrows created from the template pay their arbiter
+    #[serde(default)]
+    pub arbiter_fee: Option<ArbiterFee>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Terms {
+    /// Token the source wants in exchange for the whole balance
+    pub ask: Denom,
+    /// Amount of ask token requested for the whole balance
+    pub ask_amount: Uint128,
+    /// When set, takers can fill part of the offer paying at least this amount
+    /// of ask token, except for the last fill that takes whatever is left
+    #[serde(default)]
+    pub min_fill: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Fill {
+    pub taker: Addr,
+    /// Amount of ask token paid to the source
+    pub paid: Uint128,
+    /// Share of the balance released to the taker
+    pub received: GenericBalance,
+    pub height: u64,
+    pub time: Timestamp,
+}
+
+/// Open swap offer derived from an escrow balance and terms
+#[derive(Clone, Debug, PartialEq)]
+pub struct Offer {
+    /// Offered token
+    pub denom: Denom,
+    /// Amount of the offered token held by the escrow
+    pub amount: Uint128,
+    pub terms: Terms,
+}
+
+impl Offer {
+    /// Ask amount per offered token, saturating on overflow
+    pub fn price(&self) -> Decimal {
+        Decimal::checked_from_ratio(self.terms.ask_amount, self.amount).unwrap_or(Decimal::MAX)
+    }
+
+    /// Key of the offer in the offers index
+    pub fn index_key(&self) -> (String, u128) {
+        (
+            pair_key(&self.denom, &self.terms.ask),
+            self.price().atomics().u128(),
+        )
+    }
+}
+
+/// Condition evaluated by querying an oracle contract
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Condition {
+    /// Oracle contract that is queried
+    pub oracle: Addr,
+    /// Smart query sent to the oracle
+    pub query: Binary,
+    /// How the oracle answer is compared, the escrow is released once it holds
+    pub comparison: Comparison,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    /// Oracle answers with a number that must be greater than or equal to this value
+    AtLeast(Uint128),
+    /// Oracle answers with a number that must be lower than or equal to this value
+    AtMost(Uint128),
+    /// Oracle answers with a number that must be equal to this value
+    Equal(Uint128),
+    /// Oracle answers with a boolean that must be equal to this value
+    Is(bool),
+}
+
+impl Condition {
+    pub fn is_met(&self, querier: &QuerierWrapper) -> StdResult<bool> {
+        let request: QueryRequest<_> = WasmQuery::Smart {
+            contract_addr: self.oracle.to_string(),
+            msg: self.query.clone(),
+        }
+        .into();
+
+        let met = match self.comparison {
+            Comparison::AtLeast(value) => querier.query::<Uint128>(&request)? >= value,
+            Comparison::AtMost(value) => querier.query::<Uint128>(&request)? <= value,
+            Comparison::Equal(value) => querier.query::<Uint128>(&request)? == value,
+            Comparison::Is(value) => querier.query::<bool>(&request)? == value,
+        };
+        Ok(met)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug, Default)]
@@ -74,6 +608,384 @@ impl GenericBalance {
             }
         };
     }
+
+    /// Adds every token of another balance
+    pub fn add_balance(&mut self, other: &GenericBalance) {
+        self.add_tokens(Balance::from(other.native.clone()));
+        for token in &other.cw20 {
+            self.add_tokens(Balance::Cw20(token.clone()));
+        }
+    }
+
+    /// Returns true if both balances hold the same amount of every token, regardless
+    /// of order and of zero amounts
+    pub fn same_tokens(&self, other: &GenericBalance) -> bool {
+        let normalized = |balance: &GenericBalance| {
+            let mut native: Vec<_> = balance
+                .native
+                .iter()
+                .filter(|c| !c.amount.is_zero())
+                .map(|c| (c.denom.clone(), c.amount))
+                .collect();
+            native.sort();
+            let mut cw20: Vec<_> = balance
+                .cw20
+                .iter()
+                .filter(|c| !c.amount.is_zero())
+                .map(|c| (c.address.clone(), c.amount))
+                .collect();
+            cw20.sort();
+            (native, cw20)
+        };
+        normalized(self) == normalized(other)
+    }
+
+    /// Returns true if no token has a positive amount
+    pub fn is_empty(&self) -> bool {
+        self.native.iter().all(|c| c.amount.is_zero()) && self.cw20.iter().all(|c| c.amount.is_zero())
+    }
+
+    /// Returns a copy with every amount multiplied by factor
+    pub fn checked_mul(&self, factor: u64) -> StdResult<GenericBalance> {
+        let native = self
+            .native
+            .iter()
+            .map(|c| Ok(Coin::new(c.amount.checked_mul(factor.into())?.u128(), &c.denom)))
+            .collect::<StdResult<_>>()?;
+        let cw20 = self
+            .cw20
+            .iter()
+            .map(|c| {
+                Ok(Cw20CoinVerified {
+                    address: c.address.clone(),
+                    amount: c.amount.checked_mul(factor.into())?,
+                })
+            })
+            .collect::<StdResult<_>>()?;
+        Ok(GenericBalance { native, cw20 })
+    }
+
+    /// Returns a copy with every amount multiplied by numerator / denominator, rounded down
+    pub fn multiply_ratio(&self, numerator: Uint128, denominator: Uint128) -> GenericBalance {
+        GenericBalance {
+            native: self
+                .native
+                .iter()
+                .map(|c| Coin::new(c.amount.multiply_ratio(numerator, denominator).u128(), &c.denom))
+                .collect(),
+            cw20: self
+                .cw20
+                .iter()
+                .map(|c| Cw20CoinVerified {
+                    address: c.address.clone(),
+                    amount: c.amount.multiply_ratio(numerator, denominator),
+                })
+                .collect(),
+        }
+    }
+
+    /// Removes up to max of every token from the balance and returns what was removed.
+    /// Tokens that reach zero are dropped from the balance
+    pub fn take_up_to(&mut self, max: &GenericBalance) -> GenericBalance {
+        let mut taken = GenericBalance::default();
+        for limit in &max.native {
+            if let Some(coin) = self.native.iter_mut().find(|c| c.denom == limit.denom) {
+                let amount = coin.amount.min(limit.amount);
+                coin.amount -= amount;
+                taken.native.push(Coin::new(amount.u128(), &coin.denom));
+            }
+        }
+        for limit in &max.cw20 {
+            if let Some(coin) = self.cw20.iter_mut().find(|c| c.address == limit.address) {
+                let amount = coin.amount.min(limit.amount);
+                coin.amount -= amount;
+                taken.cw20.push(Cw20CoinVerified {
+                    address: coin.address.clone(),
+                    amount,
+                });
+            }
+        }
+        self.native.retain(|c| !c.amount.is_zero());
+        self.cw20.retain(|c| !c.amount.is_zero());
+        taken.native.retain(|c| !c.amount.is_zero());
+        taken.cw20.retain(|c| !c.amount.is_zero());
+        taken
+    }
+}
+
+/// Recurring payment released from the escrow balance
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Stream {
+    /// Tokens released to the recipient on every interval
+    pub amount: GenericBalance,
+    /// Time or blocks between two payouts
+    pub interval: Duration,
+    /// When the next interval is accrued
+    pub next_payout: Expiration,
+    /// A paused stream accrues nothing, the clock restarts when it is resumed
+    pub paused: bool,
+}
+
+impl Stream {
+    /// Number of intervals accrued and not yet paid out at the given block
+    pub fn accrued_intervals(&self, block: &BlockInfo) -> u64 {
+        if self.paused {
+            return 0;
+        }
+        match (self.next_payout, self.interval) {
+            (Expiration::AtHeight(next), Duration::Height(step)) if block.height >= next => {
+                (block.height - next) / step + 1
+            }
+            (Expiration::AtTime(next), Duration::Time(step)) if block.time >= next => {
+                (block.time.seconds() - next.seconds()) / step + 1
+            }
+            _ => 0,
+        }
+    }
+
+    /// Number of payouts, including a last partial one, until the streamed tokens
+    /// of the balance are exhausted
+    pub fn remaining_payouts(&self, balance: &GenericBalance) -> u64 {
+        let native = self.amount.native.iter().map(|per_interval| {
+            let available = balance
+                .native
+                .iter()
+                .find(|c| c.denom == per_interval.denom)
+                .map(|c| c.amount)
+                .unwrap_or_default();
+            ceil_div(available, per_interval.amount)
+        });
+        let cw20 = self.amount.cw20.iter().map(|per_interval| {
+            let available = balance
+                .cw20
+                .iter()
+                .find(|c| c.address == per_interval.address)
+                .map(|c| c.amount)
+                .unwrap_or_default();
+            ceil_div(available, per_interval.amount)
+        });
+        native.chain(cw20).max().unwrap_or_default()
+    }
+}
+
+fn ceil_div(amount: Uint128, by: Uint128) -> u64 {
+    let (amount, by) = (amount.u128(), by.u128());
+    let div = amount / by + u128::from(amount % by != 0);
+    div.min(u64::MAX as u128) as u64
+}
+
+/// Key identifying a token in the offers index
+pub fn denom_key(denom: &Denom) -> String {
+    match denom {
+        Denom::Native(denom) => format!("native:{}", denom),
+        Denom::Cw20(addr) => format!("cw20:{}", addr),
+    }
+}
+
+/// Key identifying an (offered token, asked token) pair in the offers index
+pub fn pair_key(offer: &Denom, ask: &Denom) -> String {
+    format!("{}|{}", denom_key(offer), denom_key(ask))
+}
+
+/// Key of an expiration in the expirations index, None if it never expires
+pub fn expiration_key(expires: &Expiration) -> Option<(&'static str, u64)> {
+    match expires {
+        Expiration::AtHeight(height) => Some(("height", *height)),
+        Expiration::AtTime(time) => Some(("time", time.nanos())),
+        Expiration::Never {} => None,
+    }
+}
+
+/// Loads the escrow with the owners of its receipts as parties
+pub fn load_escrow(storage: &dyn Storage, querier: &QuerierWrapper, id: &str) -> StdResult<Escrow> {
+    let mut escrow = ESCROWS.load(storage, id)?;
+    resolve_owners(querier, &mut escrow)?;
+    Ok(escrow)
+}
+
+/// Saves the escrow and keeps the secondary indexes in sync
+pub fn save_escrow(storage: &mut dyn Storage, id: &str, escrow: &Escrow) -> StdResult<()> {
+    if let Some(external_id) = &escrow.external_id {
+        if matches!(EXTERNAL_IDS.may_load(storage, external_id)?, Some(other) if other != id) {
+            return Err(StdError::generic_err("External id already in use"));
+        }
+    }
+    if let Some(old) = ESCROWS.may_load(storage, id)? {
+        unindex_escrow(storage, id, &old);
+    }
+    if let Some(external_id) = &escrow.external_id {
+        EXTERNAL_IDS.save(storage, external_id, &id.to_string())?;
+    }
+    if let Some(offer) = escrow.open_offer() {
+        let (pair, price) = offer.index_key();
+        OFFERS.save(storage, (&pair, price, id), &Empty {})?;
+    }
+    for (kind, at) in escrow.public_refund_points().iter().filter_map(expiration_key) {
+        EXPIRATIONS.save(storage, (kind, at, id), &Empty {})?;
+    }
+    ESCROWS.save(storage, id, escrow)
+}
+
+/// Removes the escrow and its secondary index entries
+pub fn remove_escrow(storage: &mut dyn Storage, id: &str) -> StdResult<()> {
+    if let Some(old) = ESCROWS.may_load(storage, id)? {
+        unindex_escrow(storage, id, &old);
+    }
+    ESCROWS.remove(storage, id);
+    Ok(())
+}
+
+fn unindex_escrow(storage: &mut dyn Storage, id: &str, escrow: &Escrow) {
+    if let Some(offer) = escrow.open_offer() {
+        let (pair, price) = offer.index_key();
+        OFFERS.remove(storage, (&pair, price, id));
+    }
+    for (kind, at) in escrow.public_refund_points().iter().filter_map(expiration_key) {
+        EXPIRATIONS.remove(storage, (kind, at, id));
+    }
+    if let Some(external_id) = &escrow.external_id {
+        EXTERNAL_IDS.remove(storage, external_id);
+    }
+}
+
+/// Counts an escrow the arbiter agreed to arbitrate, unless the arbiter is its source
+pub fn record_acceptance(storage: &mut dyn Storage, escrow: &Escrow) -> StdResult<()> {
+    if escrow.arbiter == escrow.source {
+        return Ok(());
+    }
+    let mut stats = ARBITER_STATS.may_load(storage, &escrow.arbiter)?.unwrap_or_default();
+    stats.accepted += 1;
+    ARBITER_STATS.save(storage, &escrow.arbiter, &stats)
+}
+
+/// Counts a declined arbitration as a dispute of the arbiter of the escrow
+pub fn record_decline(storage: &mut dyn Storage, escrow: &Escrow) -> StdResult<()> {
+    if escrow.arbiter == escrow.source {
+        return Ok(());
+    }
+    let mut stats = ARBITER_STATS.may_load(storage, &escrow.arbiter)?.unwrap_or_default();
+    stats.disputes += 1;
+    ARBITER_STATS.save(storage, &escrow.arbiter, &stats)
+}
+
+/// Counts an approval or a refund decided by the arbiter of the escrow. An arbiter
+/// deciding its own escrows builds no track record
+pub fn record_resolution(
+    storage: &mut dyn Storage,
+    env: &Env,
+    escrow: &Escrow,
+    approved: bool,
+) -> StdResult<()> {
+    if escrow.arbiter == escrow.source {
+        return Ok(());
+    }
+    let mut stats = ARBITER_STATS.may_load(storage, &escrow.arbiter)?.unwrap_or_default();
+    // deciding a pending escrow accepts it
+    if escrow.arbitration == Arbitration::Pending {
+        stats.accepted += 1;
+    }
+    if approved {
+        stats.approvals += 1;
+    } else {
+        stats.refunds += 1;
+    }
+    // the parties disagreed on the escrow while the arbiter decided it
+    if !approved || escrow.pending_extension.is_some() {
+        stats.disputes += 1;
+    }
+    if let Some(created_at) = escrow.created_at {
+        stats.timed_resolutions += 1;
+        stats.total_resolution_time += env.block.time.seconds().saturating_sub(created_at.seconds());
+    }
+    ARBITER_STATS.save(storage, &escrow.arbiter, &stats)
+}
+
+/// Appends a fill to the history of the escrow
+pub fn record_fill(
+    storage: &mut dyn Storage,
+    env: &Env,
+    id: &str,
+    taker: &Addr,
+    paid: Uint128,
+    received: &GenericBalance,
+) -> StdResult<()> {
+    let index = FILLS
+        .prefix(id)
+        .keys(storage, None, None, Order::Descending)
+        .next()
+        .transpose()?
+        .map_or(0, |last| last + 1);
+    let fill = Fill {
+        taker: taker.clone(),
+        paid,
+        received: received.clone(),
+        height: env.block.height,
+        time: env.block.time,
+    };
+    FILLS.save(storage, (id, index), &fill)
+}
+
+/// Hands out a decision nonce no escrow had before
+pub fn next_decision_nonce(storage: &mut dyn Storage) -> StdResult<u64> {
+    let nonce = DECISION_NONCE.may_load(storage)?.unwrap_or_default() + 1;
+    DECISION_NONCE.save(storage, &nonce)?;
+    Ok(nonce)
+}
+
+/// Appends a change to the history of the escrow
+pub fn record_history(
+    storage: &mut dyn Storage,
+    env: &Env,
+    id: &str,
+    actor: &Addr,
+    change: Change,
+) -> StdResult<()> {
+    let index = HISTORY
+        .prefix(id)
+        .keys(storage, None, None, Order::Descending)
+        .next()
+        .transpose()?
+        .map_or(0, |last| last + 1);
+    let entry = HistoryEntry {
+        actor: actor.clone(),
+        change,
+        height: env.block.height,
+        time: env.block.time,
+    };
+    HISTORY.save(storage, (id, index), &entry)
+}
+
+/// Appends a message to the thread of an escrow
+pub fn post_message(
+    storage: &mut dyn Storage,
+    env: &Env,
+    id: &str,
+    sender: &Addr,
+    ciphertext: Binary,
+) -> StdResult<u64> {
+    let index = MESSAGES
+        .prefix(id)
+        .keys(storage, None, None, Order::Descending)
+        .next()
+        .transpose()?
+        .map_or(0, |last| last + 1);
+    let message = Message {
+        sender: sender.clone(),
+        ciphertext,
+        height: env.block.height,
+        time: env.block.time,
+    };
+    MESSAGES.save(storage, (id, index), &message)?;
+    Ok(index)
+}
+
+/// Whether a closed escrow left fills, applications, history or messages under the
+/// id. These are kept after closing, so the id can't be given to a new escrow
+pub fn has_records(storage: &dyn Storage, id: &str) -> bool {
+    FILLS.prefix(id).keys_raw(storage, None, None, Order::Ascending).next().is_some()
+        || APPLICATIONS.prefix(id).keys_raw(storage, None, None, Order::Ascending).next().is_some()
+        || HISTORY.prefix(id).keys_raw(storage, None, None, Order::Ascending).next().is_some()
+        || MESSAGES.prefix(id).keys_raw(storage, None, None, Order::Ascending).next().is_some()
 }
 
 /// This returns the list of ids for all registered escrows
@@ -84,20 +996,188 @@ pub fn all_escrow_ids(storage: &dyn Storage) -> StdResult<Vec<String>> {
 }
 
 impl Escrow {
+    /// Pushes back the moment the backup arbiter can take over
+    pub fn arbiter_active(&mut self, env: &Env) {
+        if let Some(backup) = self.backup_arbiter.as_mut() {
+            backup.takes_over_at = backup.inactivity_timeout.after(&env.block);
+        }
+    }
+
+    /// Party the address plays in the escrow, if any
+    pub fn party_of(&self, address: &Addr) -> Option<Party> {
+        if address == &self.source {
+            Some(Party::Source)
+        } else if Some(address) == self.recipient.as_ref() {
+            Some(Party::Recipient)
+        } else if address == &self.arbiter {
+            Some(Party::Arbiter)
+        } else {
+            None
+        }
+    }
+
     pub fn is_expired(&self, env: &Env) -> bool {
-        if let Some(end_height) = self.end_height {
-            if env.block.height > end_height {
-                return true;
+        self.expires.is_expired(&env.block)
+    }
+
+    /// Block a stream accrues until: the current one, or once the escrow expired the
+    /// last one before its expiration, so intervals that fell due before it are paid
+    pub fn accrual_block(&self, block: &BlockInfo) -> BlockInfo {
+        let mut until = block.clone();
+        if self.expires.is_expired(block) {
+            match self.expires {
+                Expiration::AtHeight(height) => until.height = height.saturating_sub(1),
+                Expiration::AtTime(time) => until.time = time.minus_nanos(1),
+                Expiration::Never {} => {}
+            }
+        }
+        until
+    }
+
+    pub fn phase(&self, env: &Env) -> Phase {
+        if self.is_expired(env) {
+            return Phase::Expired;
+        }
+        if self.arbitration == Arbitration::Pending {
+            return match self.acceptance_deadline {
+                Some(deadline) if deadline.is_expired(&env.block) => Phase::Unaccepted,
+                _ => Phase::Arbitration,
+            };
+        }
+        match self.funding_deadline {
+            Some(deadline) if self.recip_balance.is_empty() => {
+                if deadline.is_expired(&env.block) {
+                    Phase::Unfunded
+                } else {
+                    Phase::Funding
+                }
             }
+            _ => Phase::Settlement,
         }
+    }
 
-        if let Some(end_time) = self.end_time {
-            if env.block.time > Timestamp::from_seconds(end_time) {
-                return true;
+    /// Points from which anyone can refund the escrow: the end of its grace period,
+    /// the acceptance deadline while the arbitration is pending and, while the
+    /// recipient leg is unfunded, the funding deadline
+    pub fn public_refund_points(&self) -> Vec<Expiration> {
+        let mut points = vec![self.grace_end()];
+        if let Some(deadline) = self.acceptance_deadline {
+            if self.arbitration == Arbitration::Pending {
+                points.push(deadline);
+            }
+        }
+        if let Some(deadline) = self.funding_deadline {
+            if self.recip_balance.is_empty() {
+                points.push(deadline);
             }
         }
+        points
+    }
 
-        false
+    /// Removes the share of the arbiter fee owed on a fill of `received` out of the
+    /// `expected` ask from the filled tokens and returns it. A fixed fee is charged
+    /// in proportion and shrinks by the part paid, the last fill pays what is left
+    pub fn take_fill_fee(&mut self, filled: &mut GenericBalance, received: Uint128, expected: Uint128) -> GenericBalance {
+        let fee = match self.arbiter_fee.as_mut() {
+            Some(ArbiterFee::Fixed(fee)) => {
+                let share = fee.multiply_ratio(received, expected);
+                fee.take_up_to(&share)
+            }
+            Some(ArbiterFee::Bps(bps)) => filled.multiply_ratio(Uint128::from(*bps), Uint128::new(10_000)),
+            None => return GenericBalance::default(),
+        };
+        filled.take_up_to(&fee)
+    }
+
+    /// Removes the arbiter fee from the balance and returns it
+    pub fn take_arbiter_fee(&mut self) -> GenericBalance {
+        let fee = match &self.arbiter_fee {
+            Some(ArbiterFee::Fixed(fee)) => fee.clone(),
+            Some(ArbiterFee::Bps(bps)) => self.balance.multiply_ratio(Uint128::from(*bps), Uint128::new(10_000)),
+            None => return GenericBalance::default(),
+        };
+        self.balance.take_up_to(&fee)
+    }
+
+    /// Whether the shares of the winners add up to exactly the balance left after
+    /// the arbiter fee, with every winner getting something
+    pub fn winners_cover_balance(&self, winners: &[Winner]) -> bool {
+        let mut prize = self.clone();
+        prize.take_arbiter_fee();
+        let mut total = GenericBalance::default();
+        for winner in winners {
+            total.add_balance(&winner.share);
+        }
+        let unique = winners
+            .iter()
+            .enumerate()
+            .all(|(i, w)| winners[..i].iter().all(|other| other.address != w.address));
+        !winners.is_empty()
+            && unique
+            && winners.iter().all(|w| !w.share.is_empty())
+            && total.same_tokens(&prize.balance)
+    }
+
+    /// Whether sweeping the escrow earns the keeper bounty: the keeper is neither its
+    /// source nor its arbiter and the escrow is old enough. Otherwise anyone could
+    /// drain the fee pool sweeping short lived escrows of its own
+    pub fn earns_bounty(&self, env: &Env, keeper: &Addr, min_age: u64) -> bool {
+        let old_enough = match self.created_at {
+            Some(created_at) => env.block.time.seconds().saturating_sub(created_at.seconds()) >= min_age,
+            None => true,
+        };
+        keeper != &self.source && keeper != &self.arbiter && old_enough
+    }
+
+    /// Anyone can refund the escrow once it missed its acceptance or funding deadline,
+    /// or once it is expired and its grace period is over
+    pub fn is_refundable_by_anyone(&self, env: &Env) -> bool {
+        matches!(self.phase(env), Phase::Unaccepted | Phase::Unfunded)
+            || self.grace_end().is_expired(&env.block)
+    }
+
+    /// End of the grace period following the expiration
+    pub fn grace_end(&self) -> Expiration {
+        match self.grace_period {
+            Some(grace) => (self.expires + grace).unwrap_or(self.expires),
+            None => self.expires,
+        }
+    }
+
+    /// The escrow as an open swap offer: it has terms, no recipient yet, an accepted
+    /// arbitration and a balance made of a single token
+    pub fn open_offer(&self) -> Option<Offer> {
+        let terms = self.terms.clone()?;
+        // takers are paid right away, which needs an arbiter that accepted the escrow
+        // and no other way of paying out
+        if self.recipient.is_some()
+            || self.arbitration == Arbitration::Pending
+            || self.prize_pool
+            || self.open_to_applications
+            || self.condition.is_some()
+        {
+            return None;
+        }
+        let native: Vec<_> = self.balance.native.iter().filter(|c| !c.amount.is_zero()).collect();
+        let cw20: Vec<_> = self.balance.cw20.iter().filter(|c| !c.amount.is_zero()).collect();
+        let (denom, amount) = match (native.as_slice(), cw20.as_slice()) {
+            ([coin], []) => (Denom::Native(coin.denom.clone()), coin.amount),
+            ([], [token]) => (Denom::Cw20(token.address.clone()), token.amount),
+            _ => return None,
+        };
+        Some(Offer {
+            denom,
+            amount,
+            terms,
+        })
+    }
+
+    /// How released tokens are delivered to the recipient
+    pub fn recipient_delivery(&self) -> Delivery {
+        match &self.payout_msg {
+            Some(msg) => Delivery::Callback { msg: msg.clone() },
+            None => Delivery::Transfer,
+        }
     }
 
     pub fn human_wishlist(&self) -> Vec<String> {